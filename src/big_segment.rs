@@ -0,0 +1,114 @@
+use base64ct::{Base64, Encoding};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+
+/// BigSegmentStore is an interface for a data store that holds the membership lists of big
+/// segments (also known as unbounded segments; see [crate::Segment::unbounded]).
+///
+/// Membership of a big segment is looked up by a hash of the context key, rather than by the key
+/// itself; see [big_segment_context_hash]. Each segment is identified by
+/// [crate::Segment::unbounded_segment_id], which changes whenever a new generation of the segment's
+/// membership list is created.
+///
+/// A BigSegmentStore is made available to the evaluator through [crate::Store::big_segment_store].
+/// The evaluator queries it at most once for each context key during an evaluation, however many
+/// big segments the flag refers to.
+pub trait BigSegmentStore {
+    /// Retrieve the membership of the context whose hashed key is `context_hash` in every big
+    /// segment, keyed by segment id.
+    ///
+    /// A value of `true` means the context is included in the segment, and `false` means it is
+    /// explicitly excluded. Segments for which the store has no membership information for the
+    /// context are left out. An error should be returned if the store could not be queried.
+    fn membership(&self, context_hash: &str) -> Result<HashMap<String, bool>, String>;
+
+    /// Reports whether the data in the store is up to date. Only [BigSegmentsStatus::Healthy] and
+    /// [BigSegmentsStatus::Stale] are meaningful here.
+    fn status(&self) -> BigSegmentsStatus;
+}
+
+/// BigSegmentsStatus describes the state of the big segment data used during an evaluation.
+///
+/// It is reported in [crate::EvaluationResult::big_segments_status] for any evaluation that referenced a big
+/// segment. If more than one big segment was queried, the least healthy status is reported.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum BigSegmentsStatus {
+    /// The big segment query was successful, and the data is up to date.
+    Healthy,
+    /// The big segment query was successful, but the store reports that its data may be out of
+    /// date.
+    Stale,
+    /// Big segments could not be queried because no [BigSegmentStore] was configured, or because
+    /// the segment's generation is unknown.
+    NotConfigured,
+    /// The [BigSegmentStore] returned an error.
+    StoreError,
+}
+
+impl BigSegmentsStatus {
+    fn severity(&self) -> u8 {
+        match self {
+            BigSegmentsStatus::Healthy => 0,
+            BigSegmentsStatus::Stale => 1,
+            BigSegmentsStatus::NotConfigured => 2,
+            BigSegmentsStatus::StoreError => 3,
+        }
+    }
+
+    // Combine the status of an earlier query with that of a later one, keeping the least healthy.
+    pub(crate) fn merge(current: Option<Self>, status: Self) -> Self {
+        match current {
+            Some(current) if current.severity() >= status.severity() => current,
+            _ => status,
+        }
+    }
+}
+
+/// Compute the hash of a context key which is used to look up membership in a [BigSegmentStore].
+///
+/// This is the base64 encoding of the SHA-256 hash of the key, which is the form in which
+/// LaunchDarkly writes big segment membership data.
+pub fn big_segment_context_hash(context_key: &str) -> String {
+    let digest = Sha256::digest(context_key.as_bytes());
+    Base64::encode_string(&digest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test]
+    fn context_hash_is_base64_sha256_of_key() {
+        assert_eq!(
+            "72cBpXPyn4N6TqqlS8Tti37jEcoNhFzL9ZdG1jXkILE=",
+            big_segment_context_hash("userkey")
+        );
+    }
+
+    #[test_case(None, BigSegmentsStatus::Healthy, BigSegmentsStatus::Healthy)]
+    #[test_case(
+        Some(BigSegmentsStatus::Healthy),
+        BigSegmentsStatus::Stale,
+        BigSegmentsStatus::Stale
+    )]
+    #[test_case(
+        Some(BigSegmentsStatus::Stale),
+        BigSegmentsStatus::Healthy,
+        BigSegmentsStatus::Stale
+    )]
+    #[test_case(
+        Some(BigSegmentsStatus::StoreError),
+        BigSegmentsStatus::NotConfigured,
+        BigSegmentsStatus::StoreError
+    )]
+    fn merge_keeps_least_healthy_status(
+        current: Option<BigSegmentsStatus>,
+        status: BigSegmentsStatus,
+        expected: BigSegmentsStatus,
+    ) {
+        assert_eq!(expected, BigSegmentsStatus::merge(current, status));
+    }
+}
@@ -0,0 +1,173 @@
+use serde::Serialize;
+
+use crate::eval::{evaluate_all_with_options, EvaluationOptions, FlagsState, FlagsStateOptions};
+use crate::store::Store;
+use crate::Context;
+
+/// BootstrapOptions control what [ClientSideBootstrap::new] includes for each flag.
+#[derive(Clone, Debug, Default)]
+pub struct BootstrapOptions {
+    /// Include the evaluation reason for every flag. Without this, the reason is only included
+    /// for flags whose [crate::FlagState::track_reason] is true.
+    pub with_reasons: bool,
+
+    /// Omit the version and reason for flags which do not have event tracking or debugging
+    /// enabled. See [FlagsStateOptions::details_only_for_tracked_flags].
+    pub details_only_for_tracked_flags: bool,
+}
+
+/// ClientSideBootstrap is the data with which a server can bootstrap the JavaScript SDK, so that
+/// flag values are available in the browser without waiting for a request to LaunchDarkly.
+///
+/// It serializes as its [FlagsState] does, which is the form the JavaScript SDK expects. `$valid`
+/// is false if the flags could not be evaluated (see [FlagsState::is_valid]), in which case the
+/// JavaScript SDK does not use the bootstrap data.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(transparent)]
+pub struct ClientSideBootstrap {
+    state: FlagsState,
+}
+
+impl ClientSideBootstrap {
+    /// Evaluate every flag in the store which is available to client-side SDKs using the
+    /// environment id (see [crate::Flag::using_environment_id]) for the context.
+    pub fn new(store: &dyn Store, context: &Context, options: &BootstrapOptions) -> Self {
+        Self::new_with_options(store, context, options, &EvaluationOptions::default())
+    }
+
+    /// Like [ClientSideBootstrap::new], but every flag is evaluated with the given
+    /// [EvaluationOptions].
+    pub fn new_with_options(
+        store: &dyn Store,
+        context: &Context,
+        options: &BootstrapOptions,
+        evaluation_options: &EvaluationOptions,
+    ) -> Self {
+        let state = evaluate_all_with_options(
+            store,
+            context,
+            &FlagsStateOptions {
+                client_side_only: true,
+                with_reasons: options.with_reasons,
+                details_only_for_tracked_flags: options.details_only_for_tracked_flags,
+                ..Default::default()
+            },
+            evaluation_options,
+        );
+        Self { state }
+    }
+
+    /// The evaluation results which will be serialized.
+    pub fn flags_state(&self) -> &FlagsState {
+        &self.state
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_common::TestStore;
+    use crate::{ContextBuilder, FixedClock, InMemoryStore};
+    use assert_json_diff::assert_json_eq;
+    use chrono::{TimeZone, Utc};
+    use serde_json::json;
+
+    fn bootstrap(options: &BootstrapOptions) -> serde_json::Value {
+        let store = TestStore::new_flags_state();
+        let alice = ContextBuilder::new("alice").build().unwrap();
+        serde_json::to_value(ClientSideBootstrap::new(&store, &alice, options)).unwrap()
+    }
+
+    #[test]
+    fn bootstrap_includes_client_side_flags() {
+        assert_json_eq!(
+            bootstrap(&BootstrapOptions::default()),
+            json!({
+                "tracked": "b",
+                "untracked": false,
+                "$flagsState": {
+                    "tracked": {
+                        "variation": 1,
+                        "version": 3,
+                        "reason": {"kind": "FALLTHROUGH"},
+                        "trackEvents": true,
+                        "trackReason": true
+                    },
+                    "untracked": {
+                        "variation": 0,
+                        "version": 7
+                    }
+                },
+                "$valid": true
+            })
+        );
+    }
+
+    #[test]
+    fn bootstrap_can_include_every_reason() {
+        let options = BootstrapOptions {
+            with_reasons: true,
+            ..Default::default()
+        };
+        assert_json_eq!(
+            bootstrap(&options)["$flagsState"]["untracked"],
+            json!({
+                "variation": 0,
+                "version": 7,
+                "reason": {"kind": "OFF"}
+            })
+        );
+    }
+
+    #[test]
+    fn bootstrap_can_omit_details_for_untracked_flags() {
+        let options = BootstrapOptions {
+            with_reasons: true,
+            details_only_for_tracked_flags: true,
+        };
+        let json = bootstrap(&options);
+        assert_json_eq!(json["$flagsState"]["untracked"], json!({"variation": 0}));
+        assert_eq!(json!(3), json["$flagsState"]["tracked"]["version"]);
+    }
+
+    #[test]
+    fn bootstrap_decides_debugging_with_clock() {
+        let mut store = TestStore::new_flags_state();
+        store.update_flag("untracked", |flag| {
+            flag.debug_events_until_date = Some(1_000_000)
+        });
+        let alice = ContextBuilder::new("alice").build().unwrap();
+        let options = BootstrapOptions {
+            details_only_for_tracked_flags: true,
+            ..Default::default()
+        };
+        let untracked_version_at = |millis| {
+            let clock = FixedClock(Utc.timestamp_millis_opt(millis).unwrap());
+            let evaluation_options = EvaluationOptions {
+                clock: Some(&clock),
+                ..Default::default()
+            };
+            let bootstrap = ClientSideBootstrap::new_with_options(
+                &store,
+                &alice,
+                &options,
+                &evaluation_options,
+            );
+            serde_json::to_value(bootstrap).unwrap()["$flagsState"]["untracked"]["version"].clone()
+        };
+
+        assert_eq!(json!(7), untracked_version_at(999_999));
+        assert_eq!(serde_json::Value::Null, untracked_version_at(1_000_000));
+    }
+
+    #[test]
+    fn bootstrap_is_invalid_if_store_is_not_initialized() {
+        let store = InMemoryStore::new();
+        let alice = ContextBuilder::new("alice").build().unwrap();
+        let bootstrap = ClientSideBootstrap::new(&store, &alice, &BootstrapOptions::default());
+        assert_json_eq!(
+            serde_json::to_value(bootstrap).unwrap(),
+            json!({"$flagsState": {}, "$valid": false})
+        );
+    }
+}
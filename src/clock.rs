@@ -0,0 +1,46 @@
+use std::time::SystemTime;
+
+use chrono::{DateTime, Utc};
+
+/// Clock tells evaluations the current time, for clause operations which compare a context's
+/// dates to the time of evaluation, such as [crate::Op::NewerThan], and to decide whether a flag's
+/// [crate::Flag::debug_events_until_date] has passed.
+///
+/// A clock is supplied to [crate::evaluate_with_options], [crate::evaluate_with_trace_and_options]
+/// and [crate::evaluate_all_with_options] through [crate::EvaluationOptions]. Without one,
+/// [SystemClock] is used. A [FixedClock] makes evaluations deterministic, for tests and for
+/// replaying past evaluations.
+pub trait Clock {
+    /// The current time.
+    fn now(&self) -> DateTime<Utc>;
+}
+
+impl<F> Clock for F
+where
+    F: Fn() -> DateTime<Utc>,
+{
+    fn now(&self) -> DateTime<Utc> {
+        self()
+    }
+}
+
+/// SystemClock is a [Clock] which reports the system's wall time.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        SystemTime::now().into()
+    }
+}
+
+/// FixedClock is a [Clock] which always reports the same time, so that an evaluation can be
+/// repeated as of any instant.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FixedClock(pub DateTime<Utc>);
+
+impl Clock for FixedClock {
+    fn now(&self) -> DateTime<Utc> {
+        self.0
+    }
+}
@@ -0,0 +1,163 @@
+use std::num::NonZeroUsize;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use lru::LruCache;
+
+use crate::clock::Clock;
+use crate::Context;
+
+/// ContextDeduplicator remembers which contexts have been seen recently, so that each context is
+/// only sent in full, in an index event, once per flush interval.
+///
+/// Contexts are identified by [Context::canonical_key]. At most `capacity` keys are remembered;
+/// once that many have been seen, the least recently seen key is forgotten to make room for a new
+/// one. Every key is forgotten when the flush interval has passed, so that contexts are sent again
+/// periodically even if they are seen often.
+///
+/// The deduplicator does not keep time itself: the caller supplies a [Clock] to
+/// [ContextDeduplicator::process_context] and [ContextDeduplicator::flush].
+#[derive(Debug)]
+pub struct ContextDeduplicator {
+    // None if the capacity is zero, in which case no keys are remembered.
+    keys: Option<LruCache<String, ()>>,
+    flush_interval: Duration,
+    last_flush: Option<DateTime<Utc>>,
+}
+
+impl ContextDeduplicator {
+    /// Create a deduplicator which remembers up to `capacity` context keys, and forgets them all
+    /// every `flush_interval`. With a capacity of zero, every context is treated as new.
+    pub fn new(capacity: usize, flush_interval: Duration) -> Self {
+        Self {
+            keys: NonZeroUsize::new(capacity).map(LruCache::new),
+            flush_interval,
+            last_flush: None,
+        }
+    }
+
+    /// The interval after which every remembered context key is forgotten.
+    pub fn flush_interval(&self) -> Duration {
+        self.flush_interval
+    }
+
+    /// Record that the context was seen at the time given by `clock`. Returns true if it had not
+    /// been seen since the last flush, meaning that an index event should be sent for it.
+    ///
+    /// If the flush interval has passed since the last flush, or since the first context was
+    /// processed, the deduplicator is flushed first.
+    pub fn process_context(&mut self, context: &Context, clock: &dyn Clock) -> bool {
+        let now = clock.now();
+        match self.last_flush {
+            Some(last_flush) if !self.is_flush_due(last_flush, now) => (),
+            Some(_) => self.flush_at(now),
+            None => self.last_flush = Some(now),
+        }
+
+        let keys = match self.keys.as_mut() {
+            Some(keys) => keys,
+            None => return true,
+        };
+        let key = context.canonical_key();
+        if keys.get(key).is_some() {
+            return false;
+        }
+        keys.put(key.to_string(), ());
+        true
+    }
+
+    /// Forget every context key, and start a new flush interval at the time given by `clock`.
+    pub fn flush(&mut self, clock: &dyn Clock) {
+        self.flush_at(clock.now());
+    }
+
+    fn flush_at(&mut self, now: DateTime<Utc>) {
+        if let Some(keys) = self.keys.as_mut() {
+            keys.clear();
+        }
+        self.last_flush = Some(now);
+    }
+
+    fn is_flush_due(&self, last_flush: DateTime<Utc>, now: DateTime<Utc>) -> bool {
+        match (now - last_flush).to_std() {
+            Ok(elapsed) => elapsed >= self.flush_interval,
+            // The clock went backwards; wait for it to catch up.
+            Err(_) => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ContextBuilder, FixedClock, MultiContextBuilder};
+    use chrono::TimeZone;
+
+    fn at(seconds: i64) -> FixedClock {
+        FixedClock(Utc.timestamp_opt(seconds, 0).unwrap())
+    }
+
+    fn user(key: &str) -> Context {
+        ContextBuilder::new(key).build().unwrap()
+    }
+
+    #[test]
+    fn contexts_are_only_new_once_per_interval() {
+        let mut deduplicator = ContextDeduplicator::new(10, Duration::from_secs(60));
+        assert_eq!(Duration::from_secs(60), deduplicator.flush_interval());
+
+        assert!(deduplicator.process_context(&user("alice"), &at(0)));
+        assert!(!deduplicator.process_context(&user("alice"), &at(30)));
+        assert!(deduplicator.process_context(&user("bob"), &at(30)));
+        assert!(!deduplicator.process_context(&user("alice"), &at(59)));
+
+        assert!(deduplicator.process_context(&user("alice"), &at(60)));
+        assert!(deduplicator.process_context(&user("bob"), &at(60)));
+        assert!(!deduplicator.process_context(&user("alice"), &at(61)));
+    }
+
+    #[test]
+    fn contexts_are_identified_by_canonical_key() {
+        let mut deduplicator = ContextDeduplicator::new(10, Duration::from_secs(60));
+        let org = ContextBuilder::new("alice").kind("org").build().unwrap();
+        let multi = MultiContextBuilder::new()
+            .add_context(user("alice"))
+            .add_context(org.clone())
+            .build()
+            .unwrap();
+
+        assert!(deduplicator.process_context(&user("alice"), &at(0)));
+        assert!(deduplicator.process_context(&org, &at(0)));
+        assert!(deduplicator.process_context(&multi, &at(0)));
+        assert!(!deduplicator.process_context(&multi, &at(0)));
+    }
+
+    #[test]
+    fn least_recently_seen_context_is_forgotten_at_capacity() {
+        let mut deduplicator = ContextDeduplicator::new(2, Duration::from_secs(60));
+        assert!(deduplicator.process_context(&user("a"), &at(0)));
+        assert!(deduplicator.process_context(&user("b"), &at(0)));
+        assert!(!deduplicator.process_context(&user("a"), &at(0)));
+        assert!(deduplicator.process_context(&user("c"), &at(0)));
+
+        assert!(!deduplicator.process_context(&user("a"), &at(0)));
+        assert!(deduplicator.process_context(&user("b"), &at(0)));
+    }
+
+    #[test]
+    fn flush_forgets_every_context() {
+        let mut deduplicator = ContextDeduplicator::new(10, Duration::from_secs(60));
+        assert!(deduplicator.process_context(&user("alice"), &at(0)));
+        deduplicator.flush(&at(10));
+        assert!(deduplicator.process_context(&user("alice"), &at(20)));
+        assert!(!deduplicator.process_context(&user("alice"), &at(69)));
+        assert!(deduplicator.process_context(&user("alice"), &at(70)));
+    }
+
+    #[test]
+    fn zero_capacity_treats_every_context_as_new() {
+        let mut deduplicator = ContextDeduplicator::new(0, Duration::from_secs(60));
+        assert!(deduplicator.process_context(&user("alice"), &at(0)));
+        assert!(deduplicator.process_context(&user("alice"), &at(0)));
+    }
+}
@@ -1,5 +1,6 @@
 use super::attribute_reference::Reference;
 use crate::contexts::context_serde::ContextVariant;
+use crate::eval::EvaluationError;
 use crate::AttributeValue;
 use itertools::Itertools;
 use log::warn;
@@ -286,7 +287,7 @@ impl Context {
     /// 1. For a single context of kind "user", the canonical key is equivalent to the key.
     /// 2. For other kinds of single contexts, the canonical key is "kind:key".
     /// 3. For a multi-context, the canonical key is the concatenation of its constituent contexts'
-    /// canonical keys with `:` according to (2) (including kind "user").
+    ///    canonical keys with `:` according to (2) (including kind "user").
     pub fn canonical_key(&self) -> &str {
         &self.canonical_key
     }
@@ -349,14 +350,14 @@ impl Context {
         prefix: BucketPrefix,
         is_experiment: bool,
         context_kind: &Kind,
-    ) -> Result<(f32, bool), String> {
+    ) -> Result<(f32, bool), EvaluationError> {
         let reference = match (is_experiment, by_attr) {
             (true, _) | (false, None) => Reference::new("key"),
             (false, Some(reference)) => reference.clone(),
         };
 
         if !reference.is_valid() {
-            return Err(reference.error());
+            return Err(EvaluationError::invalid_reference(&reference));
         }
 
         match self.as_kind(context_kind) {
@@ -795,7 +796,7 @@ impl ContextAttributes {
     }
 }
 
-impl<'dispatcher> ser::Serialize for ContextAttributes {
+impl ser::Serialize for ContextAttributes {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: ser::Serializer,
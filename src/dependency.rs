@@ -0,0 +1,683 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+
+use crate::flag::Flag;
+use crate::segment::Segment;
+use crate::store::Store;
+
+/// DependencyKey identifies a flag or segment within a [DependencyGraph].
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum DependencyKey {
+    /// The flag with the given key.
+    Flag(String),
+    /// The segment with the given key.
+    Segment(String),
+}
+
+impl fmt::Display for DependencyKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DependencyKey::Flag(key) => write!(f, "flag {}", key),
+            DependencyKey::Segment(key) => write!(f, "segment {}", key),
+        }
+    }
+}
+
+/// DanglingReference is a reference from a flag or segment to a prerequisite flag or a segment
+/// which is not in the store.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DanglingReference {
+    /// The flag or segment containing the reference.
+    pub from: DependencyKey,
+    /// The missing flag or segment.
+    pub to: DependencyKey,
+}
+
+/// CycleError is returned by [DependencyGraph::topological_order] when the graph contains cycles.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CycleError {
+    /// The cycles in the graph, as returned by [DependencyGraph::cycles].
+    pub cycles: Vec<Vec<DependencyKey>>,
+}
+
+impl fmt::Display for CycleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "dependency graph contains cycles: ")?;
+        for (index, cycle) in self.cycles.iter().enumerate() {
+            if index > 0 {
+                write!(f, ", ")?;
+            }
+            let keys: Vec<String> = cycle.iter().map(ToString::to_string).collect();
+            write!(f, "[{}]", keys.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for CycleError {}
+
+/// DependencyGraph describes which flags and segments in a [Store] depend on which others.
+///
+/// A flag depends on each of its prerequisite flags, and a flag or segment depends on every
+/// segment named by a segment match clause in its rules. The graph is computed from the contents
+/// of the store at the time it is created, and is not updated if the store changes.
+///
+/// Evaluation only detects a prerequisite cycle or a recursive segment reference when it reaches
+/// one, and then reports [crate::Error::MalformedFlag]. The graph allows such problems, and
+/// references to missing flags or segments, to be found ahead of time.
+#[derive(Clone, Debug, Default)]
+pub struct DependencyGraph {
+    // The flags and segments which are present in the store, each with the ones it depends on.
+    dependencies: BTreeMap<DependencyKey, BTreeSet<DependencyKey>>,
+    dangling: Vec<DanglingReference>,
+}
+
+impl DependencyGraph {
+    /// Build the graph of every flag in the store, and every segment which is either referred to
+    /// by a flag or listed by [Store::segment_keys].
+    ///
+    /// Returns None if the store cannot list its flags (see [Store::flag_keys]).
+    pub fn new(store: &dyn Store) -> Option<Self> {
+        let mut graph = Self::default();
+        let mut pending: Vec<DependencyKey> = store
+            .flag_keys()?
+            .into_iter()
+            .map(DependencyKey::Flag)
+            .chain(
+                store
+                    .segment_keys()
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(DependencyKey::Segment),
+            )
+            .collect();
+
+        while let Some(key) = pending.pop() {
+            if graph.dependencies.contains_key(&key) {
+                continue;
+            }
+            let referenced = match &key {
+                DependencyKey::Flag(flag_key) => match store.shared_flag(flag_key) {
+                    Some(flag) => flag_references(&flag),
+                    None => continue,
+                },
+                DependencyKey::Segment(segment_key) => match store.shared_segment(segment_key) {
+                    Some(segment) => segment_references(&segment),
+                    None => continue,
+                },
+            };
+
+            let mut dependencies = BTreeSet::new();
+            for dependency in referenced {
+                let exists = graph.dependencies.contains_key(&dependency)
+                    || match &dependency {
+                        DependencyKey::Flag(k) => store.shared_flag(k).is_some(),
+                        DependencyKey::Segment(k) => store.shared_segment(k).is_some(),
+                    };
+                if exists {
+                    pending.push(dependency.clone());
+                    dependencies.insert(dependency);
+                } else {
+                    graph.dangling.push(DanglingReference {
+                        from: key.clone(),
+                        to: dependency,
+                    });
+                }
+            }
+            graph.dependencies.insert(key, dependencies);
+        }
+
+        graph
+            .dangling
+            .sort_by(|a, b| (&a.from, &a.to).cmp(&(&b.from, &b.to)));
+        graph.dangling.dedup();
+        Some(graph)
+    }
+
+    /// The flags and segments in the graph, in order of kind and then key.
+    pub fn keys(&self) -> impl Iterator<Item = &DependencyKey> {
+        self.dependencies.keys()
+    }
+
+    /// The flags and segments which `key` depends on directly, excluding any which are missing
+    /// from the store.
+    pub fn dependencies(&self, key: &DependencyKey) -> impl Iterator<Item = &DependencyKey> {
+        self.dependencies.get(key).into_iter().flatten()
+    }
+
+    /// References to prerequisite flags or segments which are not in the store.
+    pub fn dangling_references(&self) -> &[DanglingReference] {
+        &self.dangling
+    }
+
+    /// The cycles in the graph. Each cycle is the set of flags and segments which depend on each
+    /// other, directly or indirectly; a flag or segment which depends on itself is a cycle of one.
+    pub fn cycles(&self) -> Vec<Vec<DependencyKey>> {
+        self.components()
+            .into_iter()
+            .filter(|component| match component.as_slice() {
+                [key] => self.dependencies(key).any(|dependency| dependency == key),
+                _ => true,
+            })
+            .collect()
+    }
+
+    /// Every flag and segment in the graph, ordered so that each one comes after everything it
+    /// depends on.
+    ///
+    /// An error is returned if the graph contains a cycle, since no such order exists.
+    pub fn topological_order(&self) -> Result<Vec<DependencyKey>, CycleError> {
+        let cycles = self.cycles();
+        if !cycles.is_empty() {
+            return Err(CycleError { cycles });
+        }
+
+        Ok(self.components().into_iter().flatten().collect())
+    }
+
+    // Computes the strongly connected components of the graph using Tarjan's algorithm. Each
+    // component is emitted only after every component it depends on, so the result is in
+    // dependency order.
+    fn components(&self) -> Vec<Vec<DependencyKey>> {
+        let mut tarjan = Tarjan {
+            graph: self,
+            index: HashMap::new(),
+            low_link: HashMap::new(),
+            stack: Vec::new(),
+            on_stack: BTreeSet::new(),
+            components: Vec::new(),
+        };
+        for key in self.dependencies.keys() {
+            if !tarjan.index.contains_key(key) {
+                tarjan.visit(key);
+            }
+        }
+        tarjan.components
+    }
+}
+
+/// DependencyIndex records, for each flag and segment, which flags and segments refer to it.
+///
+/// Unlike a [DependencyGraph], the index can be kept up to date as flags and segments change, by
+/// calling [DependencyIndex::update_flag], [DependencyIndex::update_segment] and
+/// [DependencyIndex::remove]. [crate::InMemoryStore] maintains one automatically. References are
+/// recorded whether or not the flag or segment they refer to exists, so the index does not depend
+/// on the order in which items are added.
+#[derive(Clone, Debug, Default)]
+pub struct DependencyIndex {
+    // The flags and segments each item refers to directly.
+    dependencies: HashMap<DependencyKey, BTreeSet<DependencyKey>>,
+    // The flags and segments which refer directly to each item.
+    dependents: HashMap<DependencyKey, BTreeSet<DependencyKey>>,
+}
+
+impl DependencyIndex {
+    /// Build an index of every flag and segment listed by [Store::flag_keys] and
+    /// [Store::segment_keys].
+    ///
+    /// Returns None if the store cannot list its flags or its segments, since references made by
+    /// the unlisted items would be missing from the index.
+    pub fn new(store: &dyn Store) -> Option<Self> {
+        let mut index = Self::default();
+        for key in store.flag_keys()? {
+            if let Some(flag) = store.shared_flag(&key) {
+                index.update_flag(&flag);
+            }
+        }
+        for key in store.segment_keys()? {
+            if let Some(segment) = store.shared_segment(&key) {
+                index.update_segment(&segment);
+            }
+        }
+        Some(index)
+    }
+
+    /// Record the references made by `flag`, replacing those of any earlier version of it.
+    pub fn update_flag(&mut self, flag: &Flag) {
+        self.set(DependencyKey::Flag(flag.key.clone()), flag_references(flag));
+    }
+
+    /// Record the references made by `segment`, replacing those of any earlier version of it.
+    pub fn update_segment(&mut self, segment: &Segment) {
+        self.set(
+            DependencyKey::Segment(segment.key.clone()),
+            segment_references(segment),
+        );
+    }
+
+    /// Forget the references made by a deleted flag or segment.
+    ///
+    /// References made to it by other items are kept, since those items still depend on it.
+    pub fn remove(&mut self, key: &DependencyKey) {
+        self.set(key.clone(), Vec::new());
+    }
+
+    /// The flags and segments which refer directly to `key`, as a prerequisite or in a segment
+    /// match clause.
+    pub fn direct_dependents(&self, key: &DependencyKey) -> impl Iterator<Item = &DependencyKey> {
+        self.dependents.get(key).into_iter().flatten()
+    }
+
+    /// Every flag and segment which depends on `key`, directly or indirectly. For example, if
+    /// flag A has flag B as a prerequisite, and B's rules match segment S, then both A and B
+    /// depend on S.
+    ///
+    /// The result does not include `key` itself, even if it is part of a cycle.
+    pub fn dependents(&self, key: &DependencyKey) -> BTreeSet<DependencyKey> {
+        let mut found = BTreeSet::new();
+        let mut pending = vec![key];
+        while let Some(next) = pending.pop() {
+            for dependent in self.direct_dependents(next) {
+                if dependent != key && found.insert(dependent.clone()) {
+                    pending.push(dependent);
+                }
+            }
+        }
+        found
+    }
+
+    fn set(&mut self, key: DependencyKey, references: Vec<DependencyKey>) {
+        if let Some(previous) = self.dependencies.remove(&key) {
+            for dependency in previous {
+                if let Some(dependents) = self.dependents.get_mut(&dependency) {
+                    dependents.remove(&key);
+                    if dependents.is_empty() {
+                        self.dependents.remove(&dependency);
+                    }
+                }
+            }
+        }
+
+        let references: BTreeSet<DependencyKey> = references.into_iter().collect();
+        for dependency in &references {
+            self.dependents
+                .entry(dependency.clone())
+                .or_default()
+                .insert(key.clone());
+        }
+        if !references.is_empty() {
+            self.dependencies.insert(key, references);
+        }
+    }
+}
+
+struct Tarjan<'a> {
+    graph: &'a DependencyGraph,
+    index: HashMap<&'a DependencyKey, usize>,
+    low_link: HashMap<&'a DependencyKey, usize>,
+    stack: Vec<&'a DependencyKey>,
+    on_stack: BTreeSet<&'a DependencyKey>,
+    components: Vec<Vec<DependencyKey>>,
+}
+
+impl<'a> Tarjan<'a> {
+    // Visits every key reachable from `root`. This is the usual recursive depth-first search, but
+    // with an explicit stack of the keys being visited, since a long chain of prerequisites or
+    // segments received from LaunchDarkly could otherwise overflow the call stack.
+    fn visit(&mut self, root: &'a DependencyKey) {
+        let mut visiting = vec![self.enter(root)];
+        while let Some((key, dependencies)) = visiting.last_mut() {
+            let key = *key;
+            match dependencies.next() {
+                Some(dependency) if !self.index.contains_key(dependency) => {
+                    let frame = self.enter(dependency);
+                    visiting.push(frame);
+                }
+                Some(dependency) => {
+                    if self.on_stack.contains(dependency) {
+                        let low_link = self.low_link[key].min(self.index[dependency]);
+                        self.low_link.insert(key, low_link);
+                    }
+                }
+                None => {
+                    visiting.pop();
+                    self.leave(key);
+                    if let Some((parent, _)) = visiting.last() {
+                        let low_link = self.low_link[*parent].min(self.low_link[key]);
+                        self.low_link.insert(*parent, low_link);
+                    }
+                }
+            }
+        }
+    }
+
+    fn enter(
+        &mut self,
+        key: &'a DependencyKey,
+    ) -> (&'a DependencyKey, std::vec::IntoIter<&'a DependencyKey>) {
+        let index = self.index.len();
+        self.index.insert(key, index);
+        self.low_link.insert(key, index);
+        self.stack.push(key);
+        self.on_stack.insert(key);
+        let dependencies: Vec<_> = self.graph.dependencies(key).collect();
+        (key, dependencies.into_iter())
+    }
+
+    // Called once every dependency of `key` has been visited. If `key` is the root of a strongly
+    // connected component, the component is emitted.
+    fn leave(&mut self, key: &'a DependencyKey) {
+        if self.low_link[key] != self.index[key] {
+            return;
+        }
+
+        let mut component = Vec::new();
+        while let Some(member) = self.stack.pop() {
+            self.on_stack.remove(member);
+            component.push(member.clone());
+            if member == key {
+                break;
+            }
+        }
+        component.sort();
+        self.components.push(component);
+    }
+}
+
+fn flag_references(flag: &Flag) -> Vec<DependencyKey> {
+    let prerequisites = flag
+        .prerequisites()
+        .iter()
+        .map(|prereq| DependencyKey::Flag(prereq.key().to_string()));
+    let segments = flag
+        .rules()
+        .iter()
+        .flat_map(|rule| rule.clauses())
+        .flat_map(|clause| clause.segment_keys())
+        .map(|key| DependencyKey::Segment(key.to_string()));
+    prerequisites.chain(segments).collect()
+}
+
+fn segment_references(segment: &Segment) -> Vec<DependencyKey> {
+    segment
+        .rules()
+        .iter()
+        .flat_map(|rule| rule.clauses())
+        .flat_map(|clause| clause.segment_keys())
+        .map(|key| DependencyKey::Segment(key.to_string()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ClauseBuilder, FlagBuilder, FlagRuleBuilder, InMemoryStore, SegmentBuilder};
+
+    fn flag(key: &str, prerequisites: &[&str], segments: &[&str]) -> Flag {
+        let mut builder = FlagBuilder::new(key);
+        builder
+            .boolean_variations()
+            .off_variation(0)
+            .fallthrough_variation(1);
+        for prereq in prerequisites {
+            builder.add_prerequisite(*prereq, 1);
+        }
+        if !segments.is_empty() {
+            builder.add_rule(
+                FlagRuleBuilder::new("rule")
+                    .add_clause(
+                        ClauseBuilder::segment_match(segments.to_vec())
+                            .build()
+                            .unwrap(),
+                    )
+                    .variation(1)
+                    .build()
+                    .unwrap(),
+            );
+        }
+        builder.build().unwrap()
+    }
+
+    fn segment(key: &str, segments: &[&str]) -> Segment {
+        let mut builder = SegmentBuilder::new(key);
+        if !segments.is_empty() {
+            builder.add_rule(vec![ClauseBuilder::segment_match(segments.to_vec())
+                .build()
+                .unwrap()]);
+        }
+        builder.build().unwrap()
+    }
+
+    fn flag_key(key: &str) -> DependencyKey {
+        DependencyKey::Flag(key.to_string())
+    }
+
+    fn segment_key(key: &str) -> DependencyKey {
+        DependencyKey::Segment(key.to_string())
+    }
+
+    #[test]
+    fn orders_dependencies_before_dependents() {
+        let store = InMemoryStore::new();
+        store.init(
+            vec![
+                flag("a", &["b", "c"], &[]),
+                flag("b", &["c"], &["s1"]),
+                flag("c", &[], &[]),
+            ],
+            vec![
+                segment("s1", &["s2"]),
+                segment("s2", &[]),
+                segment("unused", &[]),
+            ],
+        );
+
+        let graph = DependencyGraph::new(&store).unwrap();
+        assert!(graph.cycles().is_empty());
+        assert!(graph.dangling_references().is_empty());
+        assert_eq!(
+            vec![&flag_key("c"), &segment_key("s1")],
+            graph.dependencies(&flag_key("b")).collect::<Vec<_>>()
+        );
+
+        let order = graph.topological_order().unwrap();
+        assert_eq!(6, order.len());
+        let position = |key: DependencyKey| order.iter().position(|k| *k == key).unwrap();
+        assert!(position(flag_key("c")) < position(flag_key("b")));
+        assert!(position(flag_key("b")) < position(flag_key("a")));
+        assert!(position(segment_key("s2")) < position(segment_key("s1")));
+        assert!(position(segment_key("s1")) < position(flag_key("b")));
+    }
+
+    #[test]
+    fn reports_cycles() {
+        let store = InMemoryStore::new();
+        store.init(
+            vec![
+                flag("a", &["b"], &[]),
+                flag("b", &["a"], &[]),
+                flag("c", &["a"], &["s1"]),
+            ],
+            vec![segment("s1", &["s1"])],
+        );
+
+        let graph = DependencyGraph::new(&store).unwrap();
+        assert_eq!(
+            vec![vec![flag_key("a"), flag_key("b")], vec![segment_key("s1")]],
+            graph.cycles()
+        );
+        let error = graph.topological_order().unwrap_err();
+        assert_eq!(graph.cycles(), error.cycles);
+        assert_eq!(
+            "dependency graph contains cycles: [flag a, flag b], [segment s1]",
+            error.to_string()
+        );
+    }
+
+    #[test]
+    fn long_chains_do_not_overflow_the_stack() {
+        let keys: Vec<String> = (0..20_000).map(|i| format!("f{}", i)).collect();
+        let flags = keys
+            .iter()
+            .enumerate()
+            .map(|(i, key)| match keys.get(i + 1) {
+                Some(next) => flag(key, &[next], &[]),
+                None => flag(key, &[], &[]),
+            });
+        let store = InMemoryStore::new();
+        store.init(flags, vec![]);
+
+        let graph = DependencyGraph::new(&store).unwrap();
+        assert!(graph.cycles().is_empty());
+        let order = graph.topological_order().unwrap();
+        assert_eq!(flag_key("f19999"), order[0]);
+        assert_eq!(flag_key("f0"), order[19_999]);
+    }
+
+    #[test]
+    fn reports_dangling_references() {
+        let store = InMemoryStore::new();
+        store.init(
+            vec![flag("a", &["missing"], &["s1", "gone"])],
+            vec![segment("s1", &["gone"])],
+        );
+
+        let graph = DependencyGraph::new(&store).unwrap();
+        assert_eq!(
+            vec![
+                DanglingReference {
+                    from: flag_key("a"),
+                    to: flag_key("missing")
+                },
+                DanglingReference {
+                    from: flag_key("a"),
+                    to: segment_key("gone")
+                },
+                DanglingReference {
+                    from: segment_key("s1"),
+                    to: segment_key("gone")
+                },
+            ],
+            graph.dangling_references()
+        );
+        assert_eq!(
+            vec![segment_key("s1"), flag_key("a")],
+            graph.topological_order().unwrap()
+        );
+    }
+
+    #[test]
+    fn index_finds_transitive_dependents() {
+        let store = InMemoryStore::new();
+        store.init(
+            vec![
+                flag("a", &["b"], &[]),
+                flag("b", &[], &["s1"]),
+                flag("c", &[], &["s2"]),
+                flag("d", &[], &[]),
+            ],
+            vec![segment("s1", &["s2"]), segment("s2", &[])],
+        );
+
+        let index = DependencyIndex::new(&store).unwrap();
+        assert_eq!(
+            vec![flag_key("c"), segment_key("s1")],
+            index
+                .direct_dependents(&segment_key("s2"))
+                .cloned()
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            BTreeSet::from([
+                flag_key("a"),
+                flag_key("b"),
+                flag_key("c"),
+                segment_key("s1")
+            ]),
+            index.dependents(&segment_key("s2"))
+        );
+        assert_eq!(
+            BTreeSet::from([flag_key("a")]),
+            index.dependents(&flag_key("b"))
+        );
+        assert!(index.dependents(&flag_key("d")).is_empty());
+    }
+
+    #[test]
+    fn index_is_updated_when_items_change() {
+        let mut index = DependencyIndex::default();
+        index.update_flag(&flag("a", &["b"], &["s1"]));
+        index.update_segment(&segment("s2", &["s1"]));
+        assert_eq!(
+            BTreeSet::from([flag_key("a"), segment_key("s2")]),
+            index.dependents(&segment_key("s1"))
+        );
+
+        index.update_flag(&flag("a", &["c"], &[]));
+        assert!(index.dependents(&flag_key("b")).is_empty());
+        assert_eq!(
+            BTreeSet::from([flag_key("a")]),
+            index.dependents(&flag_key("c"))
+        );
+        assert_eq!(
+            BTreeSet::from([segment_key("s2")]),
+            index.dependents(&segment_key("s1"))
+        );
+
+        index.remove(&segment_key("s2"));
+        assert!(index.dependents(&segment_key("s1")).is_empty());
+    }
+
+    #[test]
+    fn index_excludes_the_item_itself_from_cycles() {
+        let mut index = DependencyIndex::default();
+        index.update_flag(&flag("a", &["b"], &[]));
+        index.update_flag(&flag("b", &["a"], &[]));
+        assert_eq!(
+            BTreeSet::from([flag_key("b")]),
+            index.dependents(&flag_key("a"))
+        );
+    }
+
+    #[test]
+    fn includes_referenced_segments_when_store_does_not_list_them() {
+        struct FlagsOnly(InMemoryStore);
+
+        impl Store for FlagsOnly {
+            fn flag(&self, flag_key: &str) -> Option<Flag> {
+                self.0.flag(flag_key)
+            }
+            fn segment(&self, segment_key: &str) -> Option<Segment> {
+                self.0.segment(segment_key)
+            }
+            fn flag_keys(&self) -> Option<Vec<String>> {
+                self.0.flag_keys()
+            }
+        }
+
+        let store = InMemoryStore::new();
+        store.init(
+            vec![flag("a", &[], &["s1"])],
+            vec![segment("s1", &[]), segment("unused", &[])],
+        );
+
+        let store = FlagsOnly(store);
+        let graph = DependencyGraph::new(&store).unwrap();
+        assert_eq!(
+            vec![&flag_key("a"), &segment_key("s1")],
+            graph.keys().collect::<Vec<_>>()
+        );
+        assert!(DependencyIndex::new(&store).is_none());
+    }
+
+    #[test]
+    fn requires_store_to_list_flags() {
+        struct Unlisted(InMemoryStore);
+
+        impl Store for Unlisted {
+            fn flag(&self, flag_key: &str) -> Option<Flag> {
+                self.0.flag(flag_key)
+            }
+            fn segment(&self, segment_key: &str) -> Option<Segment> {
+                self.0.segment(segment_key)
+            }
+        }
+
+        let store = InMemoryStore::new();
+        store.init(vec![flag("a", &[], &[])], vec![]);
+
+        let store = Unlisted(store);
+        assert!(DependencyGraph::new(&store).is_none());
+        assert!(DependencyIndex::new(&store).is_none());
+    }
+}
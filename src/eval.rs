@@ -1,11 +1,19 @@
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::sync::Arc;
 
+use crate::big_segment::{big_segment_context_hash, BigSegmentStore, BigSegmentsStatus};
+use crate::clock::{Clock, SystemClock};
 use crate::flag::Flag;
 use crate::flag_value::FlagValue;
+use crate::operator::OperatorRegistry;
 use crate::store::Store;
+use crate::trace::{EvaluationTrace, TraceEvent};
 use crate::variation::VariationIndex;
-use crate::{BucketResult, Context, Target};
+use crate::{BucketResult, Context, Reference, Target};
+use chrono::{DateTime, Utc};
 use log::warn;
+use serde::ser::{SerializeMap, Serializer};
 use serde::Serialize;
 
 /// A struct representing the results of an evaluation on a prerequisite flag.
@@ -20,35 +28,201 @@ pub struct PrerequisiteEvent {
     pub prerequisite_result: Detail<FlagValue>,
 }
 
+/// Like [PrerequisiteEvent], but holding the prerequisite flag as it is shared with the [Store],
+/// rather than a copy of it. It is passed to [PrerequisiteEventRecorder::record_shared].
+#[derive(Clone)]
+pub struct SharedPrerequisiteEvent {
+    /// String representing the [crate::Flag::key] of the original flag being evaluated.
+    pub target_flag_key: String,
+    /// The [crate::Context] provided during the evaluation process.
+    pub context: Context,
+    /// The prerequisite [crate::Flag] that was evaluated, shared with the [Store] it came from.
+    pub prerequisite_flag: Arc<Flag>,
+    /// The result of calling [evaluate] on the [SharedPrerequisiteEvent::prerequisite_flag].
+    pub prerequisite_result: Detail<FlagValue>,
+    /// The state of the big segment data used to evaluate the prerequisite flag. See
+    /// [EvaluationResult::big_segments_status].
+    pub big_segments_status: Option<BigSegmentsStatus>,
+}
+
+impl From<SharedPrerequisiteEvent> for PrerequisiteEvent {
+    fn from(event: SharedPrerequisiteEvent) -> Self {
+        PrerequisiteEvent {
+            target_flag_key: event.target_flag_key,
+            context: event.context,
+            prerequisite_flag: Arc::try_unwrap(event.prerequisite_flag)
+                .unwrap_or_else(|flag| Flag::clone(&flag)),
+            prerequisite_result: event.prerequisite_result,
+        }
+    }
+}
+
+impl From<PrerequisiteEvent> for SharedPrerequisiteEvent {
+    fn from(event: PrerequisiteEvent) -> Self {
+        SharedPrerequisiteEvent {
+            target_flag_key: event.target_flag_key,
+            context: event.context,
+            prerequisite_flag: Arc::new(event.prerequisite_flag),
+            prerequisite_result: event.prerequisite_result,
+            big_segments_status: None,
+        }
+    }
+}
+
 /// Trait used by [evaluate] to record the result of prerequisite flag evaluations.
 pub trait PrerequisiteEventRecorder {
     /// Record the results of a prerequisite flag evaluation.
     fn record(&self, event: PrerequisiteEvent);
+
+    /// Record the results of a prerequisite flag evaluation, with the prerequisite flag shared
+    /// with the [Store] rather than copied. This is what the evaluator calls.
+    ///
+    /// The default implementation copies the flag and passes the event to
+    /// [PrerequisiteEventRecorder::record], so existing recorders continue to work unchanged.
+    /// Recorders which can keep the shared flag should override this to avoid the copy.
+    fn record_shared(&self, event: SharedPrerequisiteEvent) {
+        self.record(event.into());
+    }
 }
 
 const PREALLOCATED_PREREQUISITE_CHAIN_SIZE: usize = 20;
 const PREALLOCATED_SEGMENT_CHAIN_SIZE: usize = 20;
 
-pub(crate) struct EvaluationStack {
+pub(crate) struct EvaluationStack<'a> {
+    pub(crate) options: EvaluationOptions<'a>,
     pub(crate) prerequisite_flag_chain: HashSet<String>,
     pub(crate) segment_chain: HashSet<String>,
+    // Only present when the caller has asked for the evaluation to be traced.
+    pub(crate) trace: Option<EvaluationTrace>,
+    // The combined status of every big segment queried so far, if any.
+    pub(crate) big_segments_status: Option<BigSegmentsStatus>,
+    // The status and big segment membership of each context key queried so far. The membership is
+    // None if the query failed.
+    big_segment_memberships: HashMap<String, (BigSegmentsStatus, Option<HashMap<String, bool>>)>,
+    // The first problem in the flag data which caused the evaluation to fail, if any.
+    pub(crate) evaluation_error: Option<EvaluationError>,
 }
 
-impl EvaluationStack {
-    fn new() -> Self {
+impl<'a> EvaluationStack<'a> {
+    fn new(options: EvaluationOptions<'a>) -> Self {
         // Preallocate some space for prerequisite_flag_chain and segment_chain on the stack. We
         // can get up to that many levels of nested prerequisites or nested segments before
         // appending to the slice will cause a heap allocation.
         Self {
+            options,
             prerequisite_flag_chain: HashSet::with_capacity(PREALLOCATED_PREREQUISITE_CHAIN_SIZE),
             segment_chain: HashSet::with_capacity(PREALLOCATED_SEGMENT_CHAIN_SIZE),
+            trace: None,
+            big_segments_status: None,
+            big_segment_memberships: HashMap::new(),
+            evaluation_error: None,
+        }
+    }
+
+    pub(crate) fn merge_big_segments_status(&mut self, status: BigSegmentsStatus) {
+        self.big_segments_status = Some(BigSegmentsStatus::merge(self.big_segments_status, status));
+    }
+
+    // Looks up whether the context with the given key is in the big segment with the given id. The
+    // store is only queried the first time the key is seen during an evaluation, so a flag which
+    // refers to several big segments, directly or through its prerequisites, costs one query.
+    pub(crate) fn big_segment_membership(
+        &mut self,
+        big_segment_store: &dyn BigSegmentStore,
+        context_key: &str,
+        segment_id: &str,
+    ) -> Option<bool> {
+        if !self.big_segment_memberships.contains_key(context_key) {
+            let context_hash = big_segment_context_hash(context_key);
+            let queried = match big_segment_store.membership(&context_hash) {
+                Ok(membership) => (big_segment_store.status(), Some(membership)),
+                Err(e) => {
+                    warn!("Unable to query big segment membership: {}", e);
+                    (BigSegmentsStatus::StoreError, None)
+                }
+            };
+            self.big_segment_memberships
+                .insert(context_key.to_string(), queried);
+        }
+
+        let (status, membership) = &self.big_segment_memberships[context_key];
+        let status = *status;
+        let included = membership
+            .as_ref()
+            .and_then(|membership| membership.get(segment_id).copied());
+        self.merge_big_segments_status(status);
+        included
+    }
+
+    // Returns an [Error::MalformedFlag] detail, recording the underlying problem.
+    fn malformed<T>(&mut self, error: EvaluationError) -> Detail<T> {
+        self.evaluation_error.get_or_insert(error);
+        Detail::err(Error::MalformedFlag)
+    }
+
+    // Builds the result returned to the caller from the detail of the evaluation.
+    fn result<T>(self, detail: Detail<T>) -> EvaluationResult<T> {
+        let evaluation_error = match detail.reason {
+            Reason::Error {
+                error: Error::MalformedFlag,
+            } => self.evaluation_error,
+            _ => None,
+        };
+        EvaluationResult {
+            detail,
+            big_segments_status: self.big_segments_status,
+            evaluation_error,
+        }
+    }
+
+    // The trace helpers below take closures so that no trace events are constructed unless the
+    // evaluation is actually being traced.
+
+    pub(crate) fn trace_begin<F: FnOnce() -> TraceEvent>(&mut self, f: F) {
+        if let Some(trace) = self.trace.as_mut() {
+            trace.begin(f());
+        }
+    }
+
+    pub(crate) fn trace_end<F: FnOnce(&mut TraceEvent)>(&mut self, f: F) {
+        if let Some(trace) = self.trace.as_mut() {
+            trace.end(f);
+        }
+    }
+
+    pub(crate) fn trace_record<F: FnOnce() -> TraceEvent>(&mut self, f: F) {
+        if let Some(trace) = self.trace.as_mut() {
+            trace.record(f());
         }
     }
 }
 
-impl Default for EvaluationStack {
+impl Default for EvaluationStack<'_> {
     fn default() -> Self {
-        Self::new()
+        Self::new(EvaluationOptions::default())
+    }
+}
+
+/// Options which change how [evaluate_with_options] and [evaluate_all_with_options] evaluate
+/// flags.
+///
+/// The default options give the same behavior as [evaluate].
+#[derive(Clone, Copy, Default)]
+pub struct EvaluationOptions<'a> {
+    /// Implementations of clause operations which this crate does not recognize. See
+    /// [OperatorRegistry].
+    pub operators: Option<&'a dyn OperatorRegistry>,
+    /// The source of the current time, for clause operations which depend on it and for deciding
+    /// whether a flag is still being debugged. Defaults to [SystemClock].
+    pub clock: Option<&'a dyn Clock>,
+}
+
+impl<'a> EvaluationOptions<'a> {
+    pub(crate) fn now(&self) -> DateTime<Utc> {
+        match self.clock {
+            Some(clock) => clock.now(),
+            None => SystemClock.now(),
+        }
     }
 }
 
@@ -58,20 +232,336 @@ impl Default for EvaluationStack {
 /// analytics events is the responsibility of the caller. The caller can provide an optional
 /// [PrerequisiteEventRecorder] which will be notified if any additional evaluations were done due
 /// to prerequisites.
+///
+/// Use [evaluate_with_options] to also learn the state of any big segments which were queried, or
+/// what was wrong with a malformed flag. The [Detail] returned here only reports
+/// [Error::MalformedFlag]; the [EvaluationError] describing the problem is not carried on the
+/// [Detail] itself, since adding a field to it would break code which constructs or destructures
+/// it, and is instead provided as [EvaluationResult::evaluation_error].
 pub fn evaluate<'a>(
     store: &'a dyn Store,
     flag: &'a Flag,
     context: &'a Context,
     prerequisite_event_recorder: Option<&dyn PrerequisiteEventRecorder>,
 ) -> Detail<&'a FlagValue> {
-    let mut evaluation_stack = EvaluationStack::default();
-    evaluate_internal(
+    evaluate_with_options(
+        store,
+        flag,
+        context,
+        prerequisite_event_recorder,
+        &EvaluationOptions::default(),
+    )
+    .detail
+}
+
+/// Evaluate a feature flag for the specified [Context], as [evaluate] does, using the provided
+/// [EvaluationOptions].
+///
+/// Along with the [Detail], the [EvaluationResult] reports the state of any big segments which
+/// were queried and what was wrong with a malformed flag.
+pub fn evaluate_with_options<'a>(
+    store: &'a dyn Store,
+    flag: &'a Flag,
+    context: &'a Context,
+    prerequisite_event_recorder: Option<&dyn PrerequisiteEventRecorder>,
+    options: &EvaluationOptions,
+) -> EvaluationResult<&'a FlagValue> {
+    let mut evaluation_stack = EvaluationStack::new(*options);
+    let detail = evaluate_internal(
         store,
         flag,
         context,
         prerequisite_event_recorder,
         &mut evaluation_stack,
+    );
+    evaluation_stack.result(detail)
+}
+
+/// Evaluate a feature flag for the specified [Context], recording each step of the evaluation in
+/// the provided [EvaluationTrace].
+///
+/// This behaves exactly like [evaluate], but is slower, since every target list, rule, clause,
+/// segment and rollout bucket that is considered is recorded. It is intended for explaining a
+/// surprising result rather than for routine use.
+pub fn evaluate_with_trace<'a>(
+    store: &'a dyn Store,
+    flag: &'a Flag,
+    context: &'a Context,
+    prerequisite_event_recorder: Option<&dyn PrerequisiteEventRecorder>,
+    trace: &mut EvaluationTrace,
+) -> Detail<&'a FlagValue> {
+    evaluate_with_trace_and_options(
+        store,
+        flag,
+        context,
+        prerequisite_event_recorder,
+        trace,
+        &EvaluationOptions::default(),
     )
+    .detail
+}
+
+/// Evaluate a feature flag for the specified [Context], as [evaluate_with_options] does, recording
+/// each step of the evaluation in the provided [EvaluationTrace].
+///
+/// The trace is the same as that of [evaluate_with_trace], and the result is the same as that of
+/// [evaluate_with_options] with the same [EvaluationOptions].
+pub fn evaluate_with_trace_and_options<'a>(
+    store: &'a dyn Store,
+    flag: &'a Flag,
+    context: &'a Context,
+    prerequisite_event_recorder: Option<&dyn PrerequisiteEventRecorder>,
+    trace: &mut EvaluationTrace,
+    options: &EvaluationOptions,
+) -> EvaluationResult<&'a FlagValue> {
+    let mut evaluation_stack = EvaluationStack {
+        trace: Some(std::mem::take(trace)),
+        ..EvaluationStack::new(*options)
+    };
+    let detail = evaluate_internal(
+        store,
+        flag,
+        context,
+        prerequisite_event_recorder,
+        &mut evaluation_stack,
+    );
+    if let Some(recorded) = evaluation_stack.trace.take() {
+        *trace = recorded;
+    }
+    evaluation_stack.result(detail)
+}
+
+/// Evaluate every flag in the [Store] for the specified [Context].
+///
+/// This is intended for passing the full set of flag values to a front end, such as when
+/// bootstrapping a client-side SDK. No analytics events are generated, and prerequisite
+/// evaluations are not reported.
+///
+/// The [FlagsStateOptions] control which flags are included in the result, and how much detail is
+/// retained for each of them. If the store is not initialized (see [Store::is_initialized]) or
+/// cannot list its flags (see [Store::flag_keys]), the result is not valid.
+pub fn evaluate_all(
+    store: &dyn Store,
+    context: &Context,
+    options: &FlagsStateOptions,
+) -> FlagsState {
+    evaluate_all_with_options(store, context, options, &EvaluationOptions::default())
+}
+
+/// Like [evaluate_all], but every flag is evaluated with the given [EvaluationOptions], whose
+/// clock also decides which flags are still being debugged.
+pub fn evaluate_all_with_options(
+    store: &dyn Store,
+    context: &Context,
+    options: &FlagsStateOptions,
+    evaluation_options: &EvaluationOptions,
+) -> FlagsState {
+    let flag_keys = match store.flag_keys() {
+        Some(flag_keys) if store.is_initialized() => flag_keys,
+        _ => return FlagsState::default(),
+    };
+    let now = evaluation_options.now();
+    let mut flags = HashMap::new();
+
+    for flag_key in flag_keys {
+        let flag = match store.shared_flag(&flag_key) {
+            Some(flag) => flag,
+            None => continue,
+        };
+
+        if options.client_side_only && !flag.using_environment_id() {
+            continue;
+        }
+
+        if options.mobile_only && !flag.using_mobile_key() {
+            continue;
+        }
+
+        let EvaluationResult {
+            detail,
+            big_segments_status,
+            ..
+        } = evaluate_with_options(store, &flag, context, None, evaluation_options);
+        let track_reason = flag.is_experimentation_enabled(&detail.reason);
+        let with_details = !options.details_only_for_tracked_flags
+            || flag.track_events
+            || track_reason
+            || flag.is_debugging(now);
+
+        let state = FlagState {
+            value: detail.value.cloned(),
+            variation: detail.variation_index,
+            version: with_details.then(|| flag.version),
+            reason: (with_details && (options.with_reasons || track_reason))
+                .then(|| detail.reason.clone()),
+            big_segments_status,
+            track_events: flag.track_events || track_reason,
+            track_reason,
+            debug_events_until_date: flag.debug_events_until_date,
+        };
+
+        flags.insert(flag_key, state);
+    }
+
+    FlagsState { flags, valid: true }
+}
+
+/// Options which control the behavior of [evaluate_all].
+#[derive(Clone, Debug, Default)]
+pub struct FlagsStateOptions {
+    /// Only include flags which are available to client-side SDKs using the environment id. See
+    /// [Flag::using_environment_id].
+    pub client_side_only: bool,
+
+    /// Only include flags which are available to clients using a mobile key. See
+    /// [Flag::using_mobile_key].
+    pub mobile_only: bool,
+
+    /// Include the evaluation reason for every flag. Without this, the reason is only included
+    /// for flags whose [FlagState::track_reason] is true.
+    pub with_reasons: bool,
+
+    /// Omit the version and reason for flags which do not have event tracking or debugging
+    /// enabled. This reduces the size of the result when it is sent to a front end.
+    pub details_only_for_tracked_flags: bool,
+}
+
+/// FlagsState is returned from [evaluate_all], and contains the evaluation result of every flag
+/// which matched the provided [FlagsStateOptions].
+///
+/// If the store was not initialized or could not list its flags, the state is empty and
+/// [FlagsState::is_valid] returns false. The default state is likewise not valid.
+///
+/// It serializes in the form used by the other LaunchDarkly SDKs, and expected by the JavaScript
+/// SDK when it is bootstrapped: the value of each flag keyed by flag key, along with a
+/// `$flagsState` object holding the [FlagState] of each flag and a `$valid` property.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FlagsState {
+    flags: HashMap<String, FlagState>,
+    valid: bool,
+}
+
+impl Serialize for FlagsState {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        // Sorted, so that the same state always serializes the same way.
+        let flags: BTreeMap<_, _> = self.flags.iter().collect();
+
+        let mut map = serializer.serialize_map(None)?;
+        for (key, flag) in &flags {
+            map.serialize_entry(key, &flag.value)?;
+        }
+        map.serialize_entry("$flagsState", &flags)?;
+        map.serialize_entry("$valid", &self.valid)?;
+        map.end()
+    }
+}
+
+impl FlagsState {
+    /// Returns true if the state holds the result of evaluating every flag in the store, and
+    /// false if the flags could not be evaluated.
+    pub fn is_valid(&self) -> bool {
+        self.valid
+    }
+
+    /// Retrieve the state of the flag with key `flag_key`, if it was included in the result.
+    pub fn get(&self, flag_key: &str) -> Option<&FlagState> {
+        self.flags.get(flag_key)
+    }
+
+    /// Retrieve the state of every included flag, keyed by flag key.
+    pub fn flags(&self) -> &HashMap<String, FlagState> {
+        &self.flags
+    }
+}
+
+/// FlagState describes the result of evaluating a single flag as part of [evaluate_all].
+///
+/// It serializes as the entry for the flag in the `$flagsState` of a [FlagsState], which leaves
+/// out the value. The [FlagState::big_segments_status] is included in the reason as
+/// `bigSegmentsStatus`, as it is by the other LaunchDarkly SDKs.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FlagState {
+    /// The result of the flag evaluation, or None if no appropriate value could be determined.
+    pub value: Option<FlagValue>,
+
+    /// The index of the returned value within the flag's list of variations.
+    pub variation: Option<VariationIndex>,
+
+    /// The version of the flag. This is None if details were omitted because of
+    /// [FlagsStateOptions::details_only_for_tracked_flags].
+    pub version: Option<u64>,
+
+    /// The reason the value was returned. This is None if details were omitted because of
+    /// [FlagsStateOptions::details_only_for_tracked_flags].
+    pub reason: Option<Reason>,
+
+    /// The state of the big segment data used by the evaluation. See
+    /// [EvaluationResult::big_segments_status].
+    pub big_segments_status: Option<BigSegmentsStatus>,
+
+    /// True if full event data should be sent for evaluations of this flag.
+    pub track_events: bool,
+
+    /// True if the evaluation reason should always be included in events for this flag. See
+    /// [Flag::is_experimentation_enabled].
+    pub track_reason: bool,
+
+    /// See [Flag::debug_events_until_date].
+    pub debug_events_until_date: Option<u64>,
+}
+
+impl Serialize for FlagState {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut map = serializer.serialize_map(None)?;
+        if let Some(variation) = self.variation {
+            map.serialize_entry("variation", &variation)?;
+        }
+        if let Some(version) = self.version {
+            map.serialize_entry("version", &version)?;
+        }
+        if let Some(reason) = &self.reason {
+            map.serialize_entry(
+                "reason",
+                &ReasonWithStatus::new(reason, self.big_segments_status),
+            )?;
+        }
+        if self.track_events {
+            map.serialize_entry("trackEvents", &true)?;
+        }
+        if self.track_reason {
+            map.serialize_entry("trackReason", &true)?;
+        }
+        if let Some(date) = self.debug_events_until_date {
+            map.serialize_entry("debugEventsUntilDate", &date)?;
+        }
+        map.end()
+    }
+}
+
+// A reason as other SDKs serialize it, with the big segments status of the evaluation alongside
+// the kind of reason.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ReasonWithStatus<'a> {
+    #[serde(flatten)]
+    reason: &'a Reason,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    big_segments_status: Option<BigSegmentsStatus>,
+}
+
+impl<'a> ReasonWithStatus<'a> {
+    pub(crate) fn new(reason: &'a Reason, big_segments_status: Option<BigSegmentsStatus>) -> Self {
+        Self {
+            reason,
+            big_segments_status,
+        }
+    }
 }
 
 fn evaluate_internal<'a>(
@@ -80,16 +570,65 @@ fn evaluate_internal<'a>(
     context: &'a Context,
     prerequisite_event_recorder: Option<&dyn PrerequisiteEventRecorder>,
     evaluation_stack: &mut EvaluationStack,
+) -> Detail<&'a FlagValue> {
+    evaluation_stack.trace_begin(|| TraceEvent::Flag {
+        key: flag.key.clone(),
+        version: flag.version,
+        value: None,
+        variation: None,
+        reason: None,
+    });
+
+    let detail = evaluate_flag(
+        store,
+        flag,
+        context,
+        prerequisite_event_recorder,
+        evaluation_stack,
+    );
+
+    evaluation_stack.trace_end(|event| {
+        if let TraceEvent::Flag {
+            value,
+            variation,
+            reason,
+            ..
+        } = event
+        {
+            *value = detail.value.cloned();
+            *variation = detail.variation_index;
+            *reason = Some(detail.reason.clone());
+        }
+    });
+
+    detail
+}
+
+fn evaluate_flag<'a>(
+    store: &'a dyn Store,
+    flag: &'a Flag,
+    context: &'a Context,
+    prerequisite_event_recorder: Option<&dyn PrerequisiteEventRecorder>,
+    evaluation_stack: &mut EvaluationStack,
 ) -> Detail<&'a FlagValue> {
     if !flag.on {
-        return flag.off_value(Reason::Off);
+        return off_value(flag, Reason::Off, evaluation_stack);
     }
 
     if evaluation_stack.prerequisite_flag_chain.contains(&flag.key) {
         warn!("prerequisite relationship to {} caused a circular reference; this is probably a temporary condition due to an incomplete update", flag.key);
-        return flag.off_value(Reason::Error {
-            error: Error::MalformedFlag,
-        });
+        evaluation_stack
+            .evaluation_error
+            .get_or_insert(EvaluationError::CircularPrerequisite {
+                flag_key: flag.key.clone(),
+            });
+        return off_value(
+            flag,
+            Reason::Error {
+                error: Error::MalformedFlag,
+            },
+            evaluation_stack,
+        );
     }
 
     evaluation_stack
@@ -97,14 +636,26 @@ fn evaluate_internal<'a>(
         .insert(flag.key.clone());
 
     for prereq in &flag.prerequisites {
-        if let Some(prereq_flag) = store.flag(&prereq.key) {
+        evaluation_stack.trace_begin(|| TraceEvent::Prerequisite {
+            key: prereq.key.clone(),
+            variation: prereq.variation,
+            satisfied: false,
+        });
+
+        if let Some(prereq_flag) = store.shared_flag(&prereq.key) {
             if evaluation_stack
                 .prerequisite_flag_chain
                 .contains(&prereq_flag.key)
             {
-                return Detail::err(Error::MalformedFlag);
+                evaluation_stack.trace_end(|_| ());
+                return evaluation_stack.malformed(EvaluationError::CircularPrerequisite {
+                    flag_key: prereq_flag.key.clone(),
+                });
             }
 
+            // The prerequisite's big segments status is reported separately from that of the
+            // flag which depends on it, and then merged into it.
+            let outer_big_segments_status = evaluation_stack.big_segments_status.take();
             let prerequisite_result = evaluate_internal(
                 store,
                 &prereq_flag,
@@ -112,52 +663,84 @@ fn evaluate_internal<'a>(
                 prerequisite_event_recorder,
                 evaluation_stack,
             );
+            let big_segments_status = evaluation_stack.big_segments_status;
+            if let Some(status) = outer_big_segments_status {
+                evaluation_stack.merge_big_segments_status(status);
+            }
 
             if let Detail {
                 reason: Reason::Error { .. },
                 ..
             } = prerequisite_result
             {
+                evaluation_stack.trace_end(|_| ());
                 return Detail::err(Error::MalformedFlag);
             }
 
             let variation_index = prerequisite_result.variation_index;
 
             if let Some(recorder) = prerequisite_event_recorder {
-                recorder.record(PrerequisiteEvent {
+                recorder.record_shared(SharedPrerequisiteEvent {
                     target_flag_key: flag.key.clone(),
                     context: context.clone(),
-                    prerequisite_flag: prereq_flag.clone(),
+                    prerequisite_flag: Arc::clone(&prereq_flag),
                     prerequisite_result: prerequisite_result.map(|v| v.clone()),
+                    big_segments_status,
                 });
             }
 
-            if !prereq_flag.on || variation_index != Some(prereq.variation) {
-                return flag.off_value(Reason::PrerequisiteFailed {
+            let satisfied = prereq_flag.on && variation_index == Some(prereq.variation);
+            evaluation_stack.trace_end(|event| {
+                if let TraceEvent::Prerequisite { satisfied: s, .. } = event {
+                    *s = satisfied;
+                }
+            });
+
+            if !satisfied {
+                let reason = Reason::PrerequisiteFailed {
                     prerequisite_key: prereq.key.to_string(),
-                });
+                };
+                return off_value(flag, reason, evaluation_stack);
             }
         } else {
-            return flag.off_value(Reason::PrerequisiteFailed {
+            evaluation_stack.trace_end(|_| ());
+            let reason = Reason::PrerequisiteFailed {
                 prerequisite_key: prereq.key.to_string(),
-            });
+            };
+            return off_value(flag, reason, evaluation_stack);
         }
     }
 
     evaluation_stack.prerequisite_flag_chain.remove(&flag.key);
 
-    if let Some(variation_index) = any_target_match_variation(context, flag) {
-        return flag.variation(variation_index, Reason::TargetMatch);
+    if let Some(variation_index) = any_target_match_variation(context, flag, evaluation_stack) {
+        return variation(flag, variation_index, Reason::TargetMatch, evaluation_stack);
     }
 
     for (rule_index, rule) in flag.rules.iter().enumerate() {
-        match rule.matches(context, store, evaluation_stack) {
+        evaluation_stack.trace_begin(|| TraceEvent::Rule {
+            rule_index,
+            rule_id: rule.id.clone(),
+            matched: false,
+        });
+        let result = rule.matches(context, store, evaluation_stack);
+        evaluation_stack.trace_end(|event| {
+            if let TraceEvent::Rule { matched, .. } = event {
+                *matched = matches!(result, Ok(true));
+            }
+        });
+
+        match result {
             Err(e) => {
-                warn!("{:?}", e);
-                return Detail::err(Error::MalformedFlag);
+                warn!("{}", e);
+                return evaluation_stack.malformed(e);
             }
             Ok(matches) if matches => {
-                let result = flag.resolve_variation_or_rollout(&rule.variation_or_rollout, context);
+                let result = flag.resolve_variation_or_rollout(
+                    &rule.variation_or_rollout,
+                    context,
+                    evaluation_stack,
+                );
                 return match result {
                     Ok(BucketResult {
                         variation_index,
@@ -168,32 +751,65 @@ fn evaluate_internal<'a>(
                             rule_id: rule.id.clone(),
                             in_experiment,
                         };
-                        flag.variation(variation_index, reason)
+                        variation(flag, variation_index, reason, evaluation_stack)
                     }
-                    Err(e) => Detail::err(e),
+                    Err(e) => evaluation_stack.malformed(e),
                 };
             }
             _ => (),
         }
     }
 
-    let result = flag.resolve_variation_or_rollout(&flag.fallthrough, context);
-    return match result {
+    let result = flag.resolve_variation_or_rollout(&flag.fallthrough, context, evaluation_stack);
+    match result {
         Ok(BucketResult {
             variation_index,
             in_experiment,
         }) => {
             let reason = Reason::Fallthrough { in_experiment };
-            flag.variation(variation_index, reason)
+            variation(flag, variation_index, reason, evaluation_stack)
         }
-        Err(e) => Detail::err(e),
-    };
+        Err(e) => evaluation_stack.malformed(e),
+    }
+}
+
+// Like [Flag::variation], but records an index which is out of range as the evaluation error.
+fn variation<'a>(
+    flag: &'a Flag,
+    index: VariationIndex,
+    reason: Reason,
+    evaluation_stack: &mut EvaluationStack,
+) -> Detail<&'a FlagValue> {
+    let detail = flag.variation(index, reason);
+    if detail.value.is_none() {
+        evaluation_stack
+            .evaluation_error
+            .get_or_insert(EvaluationError::VariationOutOfRange { variation: index });
+    }
+    detail
+}
+
+// Like [Flag::off_value], but records an off variation which is out of range as the evaluation
+// error.
+fn off_value<'a>(
+    flag: &'a Flag,
+    reason: Reason,
+    evaluation_stack: &mut EvaluationStack,
+) -> Detail<&'a FlagValue> {
+    match flag.off_variation {
+        Some(index) => variation(flag, index, reason, evaluation_stack),
+        None => Detail::empty(reason),
+    }
 }
 
-fn any_target_match_variation(context: &Context, flag: &Flag) -> Option<VariationIndex> {
+fn any_target_match_variation(
+    context: &Context,
+    flag: &Flag,
+    evaluation_stack: &mut EvaluationStack,
+) -> Option<VariationIndex> {
     if flag.context_targets.is_empty() {
         for target in &flag.targets {
-            if let Some(index) = target_match_variation(context, target) {
+            if let Some(index) = target_match_variation(context, target, evaluation_stack) {
                 return Some(index);
             }
         }
@@ -202,12 +818,16 @@ fn any_target_match_variation(context: &Context, flag: &Flag) -> Option<Variatio
             if context_target.context_kind.is_user() && context_target.values.is_empty() {
                 for target in &flag.targets {
                     if target.variation == context_target.variation {
-                        if let Some(index) = target_match_variation(context, target) {
+                        if let Some(index) =
+                            target_match_variation(context, target, evaluation_stack)
+                        {
                             return Some(index);
                         }
                     }
                 }
-            } else if let Some(index) = target_match_variation(context, context_target) {
+            } else if let Some(index) =
+                target_match_variation(context, context_target, evaluation_stack)
+            {
                 return Some(index);
             }
         }
@@ -216,17 +836,23 @@ fn any_target_match_variation(context: &Context, flag: &Flag) -> Option<Variatio
     None
 }
 
-fn target_match_variation(context: &Context, target: &Target) -> Option<VariationIndex> {
-    if let Some(context) = context.as_kind(&target.context_kind) {
-        let key = context.key();
-        for value in &target.values {
-            if value == key {
-                return Some(target.variation);
-            }
-        }
-    }
+fn target_match_variation(
+    context: &Context,
+    target: &Target,
+    evaluation_stack: &mut EvaluationStack,
+) -> Option<VariationIndex> {
+    let matched = match context.as_kind(&target.context_kind) {
+        Some(context) => target.values.contains(context.key()),
+        None => false,
+    };
 
-    None
+    evaluation_stack.trace_record(|| TraceEvent::Target {
+        context_kind: target.context_kind.clone(),
+        variation: target.variation,
+        matched,
+    });
+
+    matched.then(|| target.variation)
 }
 
 /// A Detail instance is returned from [evaluate], combining the result of a flag evaluation with
@@ -247,6 +873,37 @@ pub struct Detail<T> {
     pub reason: Reason,
 }
 
+/// EvaluationResult is returned by [evaluate_with_options]. Along with the [Detail] of the
+/// evaluation, it describes the evaluation in ways which the [Detail] does not.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EvaluationResult<T> {
+    /// The result of the evaluation, as [evaluate] would return it.
+    pub detail: Detail<T>,
+
+    /// Describes the state of the big segment data used during the evaluation. This is None if
+    /// the evaluation did not reference any big segments.
+    pub big_segments_status: Option<BigSegmentsStatus>,
+
+    /// When the [Detail::reason] is [Error::MalformedFlag], this describes what was wrong with the
+    /// flag data. It is None for any other result.
+    pub evaluation_error: Option<EvaluationError>,
+}
+
+impl<T> EvaluationResult<T> {
+    /// Returns a new instance of this result with the provided function `f` applied to the value
+    /// of its [EvaluationResult::detail].
+    pub fn map<U, F>(self, f: F) -> EvaluationResult<U>
+    where
+        F: FnOnce(T) -> U,
+    {
+        EvaluationResult {
+            detail: self.detail.map(f),
+            big_segments_status: self.big_segments_status,
+            evaluation_error: self.evaluation_error,
+        }
+    }
+}
+
 impl<T> Detail<T> {
     /// Returns a detail with value and variation_index of None.
     ///
@@ -445,14 +1102,90 @@ pub enum Error {
     Exception,
 }
 
+/// EvaluationError describes the problem in the flag data which caused an evaluation to fail with
+/// [Error::MalformedFlag]. It is provided as [EvaluationResult::evaluation_error].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum EvaluationError {
+    /// A clause or rollout referred to a context attribute with an invalid [crate::Reference].
+    InvalidReference {
+        /// The reference as it was given.
+        reference: String,
+        /// Why the reference is not valid.
+        error: String,
+    },
+    /// A segment referred to itself, directly or through other segments.
+    CircularSegmentReference {
+        /// The key of the segment which was reached a second time.
+        segment_key: String,
+    },
+    /// A flag was its own prerequisite, directly or through other flags.
+    CircularPrerequisite {
+        /// The key of the flag which was reached a second time.
+        flag_key: String,
+    },
+    /// The selected variation index does not refer to one of the flag's variations.
+    VariationOutOfRange {
+        /// The variation index.
+        variation: VariationIndex,
+    },
+    /// A rule or fallthrough had neither a variation nor a rollout, or had a rollout with no
+    /// variations.
+    MalformedRollout,
+}
+
+impl EvaluationError {
+    pub(crate) fn invalid_reference(reference: &Reference) -> Self {
+        EvaluationError::InvalidReference {
+            reference: reference.to_string(),
+            error: reference.error(),
+        }
+    }
+}
+
+impl fmt::Display for EvaluationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EvaluationError::InvalidReference { reference, error } => {
+                write!(f, "invalid attribute reference {:?}: {}", reference, error)
+            }
+            EvaluationError::CircularSegmentReference { segment_key } => write!(
+                f,
+                "segment rule referencing segment {} caused a circular reference; this is probably a temporary condition due to an incomplete update",
+                segment_key
+            ),
+            EvaluationError::CircularPrerequisite { flag_key } => write!(
+                f,
+                "prerequisite relationship to {} caused a circular reference; this is probably a temporary condition due to an incomplete update",
+                flag_key
+            ),
+            EvaluationError::VariationOutOfRange { variation } => {
+                write!(f, "variation index {} is out of range", variation)
+            }
+            EvaluationError::MalformedRollout => {
+                write!(f, "rule or fallthrough has no usable variation or rollout")
+            }
+        }
+    }
+}
+
+impl std::error::Error for EvaluationError {}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::contexts::context::Kind;
     use crate::flag_value::FlagValue::{Bool, Str};
+    use crate::rule::Clause;
     use crate::test_common::{InMemoryPrerequisiteEventRecorder, TestStore};
     use crate::variation::VariationOrRollout;
-    use crate::{AttributeValue, ContextBuilder, MultiContextBuilder};
+    use crate::{
+        AttributeValue, ClauseBuilder, ContextBuilder, FixedClock, FlagBuilder, FlagRuleBuilder,
+        MultiContextBuilder, Op, Reference,
+    };
+    use assert_json_diff::assert_json_eq;
+    use chrono::TimeZone;
+    use serde_json::json;
     use spectral::prelude::*;
     use std::cell::RefCell;
     use test_case::test_case;
@@ -601,12 +1334,37 @@ mod tests {
         let event = &recorder.events.borrow()[0];
         assert_eq!("flagWithSatisfiedPrereq", event.target_flag_key);
         assert_eq!("prereq", event.prerequisite_flag.key);
+        assert!(Arc::ptr_eq(
+            &store.shared_flag("prereq").unwrap(),
+            &event.prerequisite_flag
+        ));
 
         let event = &recorder.events.borrow()[1];
         assert_eq!("flagWithNestedPrereq", event.target_flag_key);
         assert_eq!("flagWithSatisfiedPrereq", event.prerequisite_flag.key);
     }
 
+    #[test]
+    fn test_prerequisite_events_are_copied_for_recorders_which_only_record_owned_events() {
+        struct OwnedRecorder(RefCell<Vec<PrerequisiteEvent>>);
+        impl PrerequisiteEventRecorder for OwnedRecorder {
+            fn record(&self, event: PrerequisiteEvent) {
+                self.0.borrow_mut().push(event);
+            }
+        }
+
+        let recorder = OwnedRecorder(RefCell::new(Vec::new()));
+        let store = TestStore::new();
+        let alice = ContextBuilder::new("alice").build().unwrap();
+        let flag = store.flag("flagWithSatisfiedPrereq").unwrap();
+
+        let _ = evaluate(&store, &flag, &alice, Some(&recorder));
+        let events = recorder.0.into_inner();
+        assert_that!(events).has_length(1);
+        assert_eq!("flagWithSatisfiedPrereq", events[0].target_flag_key);
+        assert_eq!("prereq", events[0].prerequisite_flag.key);
+    }
+
     #[test]
     fn test_eval_flag_rules() {
         let store = TestStore::new();
@@ -699,7 +1457,7 @@ mod tests {
         });
 
         // prerequisite off
-        store.update_flag("prereq", |flag| (flag.on = false));
+        store.update_flag("prereq", |flag| flag.on = false);
         for user in &[&alice, &bob] {
             let detail = evaluate(&store, &flag, user, None);
             assert_that!(detail.value).contains_value(&Bool(false));
@@ -832,6 +1590,11 @@ mod tests {
         assert_that!(detail.reason).is_equal_to(Reason::Error {
             error: Error::MalformedFlag,
         });
+        assert_that!(evaluation_error(&store, &flag, &alice)).contains_value(
+            EvaluationError::CircularPrerequisite {
+                flag_key: "flagA".to_string(),
+            },
+        );
     }
 
     // Flag A
@@ -1263,6 +2026,11 @@ mod tests {
         assert_that!(detail.reason).is_equal_to(Reason::Error {
             error: Error::MalformedFlag,
         });
+        assert_that!(evaluation_error(&store, &flag, &alice)).contains_value(
+            EvaluationError::CircularSegmentReference {
+                segment_key: "segmentA".to_string(),
+            },
+        );
     }
 
     // Flag A Segment A
@@ -1477,6 +2245,8 @@ mod tests {
         assert_that!(detail.reason).is_equal_to(Reason::Error {
             error: Error::MalformedFlag,
         });
+        assert_that!(evaluation_error(&store, &flag, &user_b))
+            .contains_value(EvaluationError::MalformedRollout);
     }
 
     #[test]
@@ -1551,6 +2321,270 @@ mod tests {
         }
     }
 
+    #[test]
+    fn evaluate_all_includes_every_flag() {
+        let store = TestStore::new_flags_state();
+        let alice = ContextBuilder::new("alice").build().unwrap();
+
+        let state = evaluate_all(&store, &alice, &FlagsStateOptions::default());
+        assert!(state.is_valid());
+        assert_eq!(3, state.flags().len());
+
+        let json = serde_json::to_value(&state).unwrap();
+        assert_json_eq!(
+            json,
+            json!({
+                "tracked": "b",
+                "untracked": false,
+                "server-only": 1.0,
+                "$flagsState": {
+                    "tracked": {
+                        "variation": 1,
+                        "version": 3,
+                        "reason": {"kind": "FALLTHROUGH"},
+                        "trackEvents": true,
+                        "trackReason": true
+                    },
+                    "untracked": {
+                        "variation": 0,
+                        "version": 7
+                    },
+                    "server-only": {
+                        "variation": 0,
+                        "version": 1
+                    }
+                },
+                "$valid": true
+            })
+        );
+    }
+
+    #[test]
+    fn evaluate_all_can_include_every_reason() {
+        let store = TestStore::new_flags_state();
+        let alice = ContextBuilder::new("alice").build().unwrap();
+
+        let options = FlagsStateOptions {
+            with_reasons: true,
+            ..Default::default()
+        };
+        let state = evaluate_all(&store, &alice, &options);
+        assert_that!(state.get("untracked").unwrap().reason).contains_value(Reason::Off);
+    }
+
+    #[test]
+    fn flag_state_includes_big_segments_status_in_reason() {
+        let state = FlagState {
+            value: Some(Bool(true)),
+            variation: Some(1),
+            version: Some(2),
+            reason: Some(Reason::Fallthrough {
+                in_experiment: false,
+            }),
+            big_segments_status: Some(BigSegmentsStatus::Stale),
+            track_events: false,
+            track_reason: false,
+            debug_events_until_date: None,
+        };
+        assert_json_eq!(
+            serde_json::to_value(&state).unwrap(),
+            json!({
+                "variation": 1,
+                "version": 2,
+                "reason": {"kind": "FALLTHROUGH", "bigSegmentsStatus": "STALE"}
+            })
+        );
+    }
+
+    #[test]
+    fn evaluate_all_is_invalid_if_store_cannot_list_flags() {
+        struct UnlistedStore(TestStore);
+
+        impl Store for UnlistedStore {
+            fn flag(&self, flag_key: &str) -> Option<Flag> {
+                self.0.flag(flag_key)
+            }
+            fn segment(&self, segment_key: &str) -> Option<crate::Segment> {
+                self.0.segment(segment_key)
+            }
+        }
+
+        let store = UnlistedStore(TestStore::new_flags_state());
+        let alice = ContextBuilder::new("alice").build().unwrap();
+
+        let state = evaluate_all(&store, &alice, &FlagsStateOptions::default());
+        assert!(!state.is_valid());
+        assert!(state.flags().is_empty());
+        assert_json_eq!(
+            serde_json::to_value(&state).unwrap(),
+            json!({"$flagsState": {}, "$valid": false})
+        );
+    }
+
+    #[test]
+    fn evaluate_all_can_filter_client_side_flags() {
+        let store = TestStore::new_flags_state();
+        let alice = ContextBuilder::new("alice").build().unwrap();
+
+        let options = FlagsStateOptions {
+            client_side_only: true,
+            ..Default::default()
+        };
+        let state = evaluate_all(&store, &alice, &options);
+        assert_eq!(2, state.flags().len());
+        assert!(state.get("tracked").is_some());
+        assert!(state.get("untracked").is_some());
+        assert!(state.get("server-only").is_none());
+    }
+
+    #[test]
+    fn evaluate_all_can_omit_details_for_untracked_flags() {
+        let store = TestStore::new_flags_state();
+        let alice = ContextBuilder::new("alice").build().unwrap();
+
+        let options = FlagsStateOptions {
+            details_only_for_tracked_flags: true,
+            ..Default::default()
+        };
+        let state = evaluate_all(&store, &alice, &options);
+
+        let tracked = state.get("tracked").unwrap();
+        assert_that!(tracked.version).contains_value(3);
+        assert_that!(tracked.reason).is_some();
+
+        let untracked = state.get("untracked").unwrap();
+        assert_that!(untracked.value).contains_value(Bool(false));
+        assert_that!(untracked.variation).contains_value(0);
+        assert_that!(untracked.version).is_none();
+        assert_that!(untracked.reason).is_none();
+    }
+
+    #[test]
+    fn evaluate_all_decides_debugging_with_clock() {
+        let mut store = TestStore::new_flags_state();
+        store.update_flag("untracked", |flag| {
+            flag.debug_events_until_date = Some(1_000_000)
+        });
+        let alice = ContextBuilder::new("alice").build().unwrap();
+
+        let options = FlagsStateOptions {
+            details_only_for_tracked_flags: true,
+            ..Default::default()
+        };
+        let state_at = |millis| {
+            let clock = FixedClock(Utc.timestamp_millis_opt(millis).unwrap());
+            let evaluation_options = EvaluationOptions {
+                clock: Some(&clock),
+                ..Default::default()
+            };
+            evaluate_all_with_options(&store, &alice, &options, &evaluation_options)
+        };
+
+        assert_that!(state_at(999_999).get("untracked").unwrap().version).contains_value(7);
+        assert_that!(state_at(1_000_000).get("untracked").unwrap().version).is_none();
+        assert_that!(
+            evaluate_all(&store, &alice, &options)
+                .get("untracked")
+                .unwrap()
+                .version
+        )
+        .is_none();
+    }
+
+    #[test]
+    fn evaluate_with_options_uses_clock_for_relative_time() {
+        let store = TestStore::new();
+        let flag = FlagBuilder::new("new-accounts")
+            .boolean_variations()
+            .off_variation(0)
+            .fallthrough_variation(0)
+            .on(true)
+            .add_rule(
+                FlagRuleBuilder::new("rule")
+                    .add_clause(
+                        ClauseBuilder::new("createdAt", Op::NewerThan)
+                            .add_value("30d")
+                            .build()
+                            .unwrap(),
+                    )
+                    .variation(1)
+                    .build()
+                    .unwrap(),
+            )
+            .build()
+            .unwrap();
+        let context = ContextBuilder::new("alice")
+            .set_value("createdAt", "2022-06-01T00:00:00Z".into())
+            .build()
+            .unwrap();
+
+        let value_at = |date: &str| {
+            let clock = FixedClock(date.parse().unwrap());
+            let options = EvaluationOptions {
+                clock: Some(&clock),
+                ..Default::default()
+            };
+            let value = evaluate_with_options(&store, &flag, &context, None, &options)
+                .detail
+                .value
+                .cloned();
+
+            let mut trace = EvaluationTrace::new();
+            let traced = evaluate_with_trace_and_options(
+                &store, &flag, &context, None, &mut trace, &options,
+            );
+            assert_eq!(value.as_ref(), traced.detail.value);
+            assert!(!trace.steps().is_empty());
+            value
+        };
+
+        assert_that!(value_at("2022-06-15T00:00:00Z")).contains_value(Bool(true));
+        assert_that!(value_at("2022-07-15T00:00:00Z")).contains_value(Bool(false));
+    }
+
+    // A store which only hands out shared references, so that any fallback to the owned lookup
+    // methods during evaluation is detected.
+    struct SharedOnlyStore(TestStore);
+
+    impl Store for SharedOnlyStore {
+        fn flag(&self, _flag_key: &str) -> Option<Flag> {
+            panic!("evaluation should not clone flags")
+        }
+        fn segment(&self, _segment_key: &str) -> Option<crate::Segment> {
+            panic!("evaluation should not clone segments")
+        }
+        fn flag_keys(&self) -> Option<Vec<String>> {
+            self.0.flag_keys()
+        }
+        fn shared_flag(&self, flag_key: &str) -> Option<std::sync::Arc<Flag>> {
+            self.0.shared_flag(flag_key)
+        }
+        fn shared_segment(&self, segment_key: &str) -> Option<std::sync::Arc<crate::Segment>> {
+            self.0.shared_segment(segment_key)
+        }
+    }
+
+    #[test]
+    fn evaluation_uses_shared_lookups() {
+        let store = SharedOnlyStore(TestStore::new());
+        let alice = ContextBuilder::new("alice").build().unwrap();
+
+        let flag = store.shared_flag("flagWithNestedPrereq").unwrap();
+        let detail = evaluate(&store, &flag, &alice, None);
+        assert_that!(detail.value).is_some();
+
+        let flag = store.shared_flag("flagWithSegmentMatchRule").unwrap();
+        let detail = evaluate(&store, &flag, &alice, None);
+        assert_that!(detail.reason).is_equal_to(Reason::RuleMatch {
+            rule_index: 0,
+            rule_id: "match-rule".to_string(),
+            in_experiment: false,
+        });
+
+        let state = evaluate_all(&store, &alice, &FlagsStateOptions::default());
+        assert_that!(state.get("flag")).is_some();
+    }
+
     #[test]
     fn get_applicable_context_by_kind_returns_correct_context() {
         let org_kind = Kind::from("org");
@@ -1578,6 +2612,57 @@ mod tests {
         assert!(multi_context.as_kind(&company_kind).is_none());
     }
 
+    #[test]
+    fn evaluation_error_describes_invalid_reference_and_variation() {
+        let store = TestStore::new();
+        let context = ContextBuilder::new("alice").build().unwrap();
+
+        let mut flag = store.flag("flag").unwrap();
+        flag.on = true;
+        flag.rules = vec![FlagRuleBuilder::new("rule")
+            .add_clause(Clause::new(
+                Kind::user(),
+                Reference::new("/"),
+                false,
+                crate::Op::In,
+                vec!["x".into()],
+            ))
+            .variation(0)
+            .build()
+            .unwrap()];
+        let detail = evaluate(&store, &flag, &context, None);
+        assert_that!(detail.reason).is_equal_to(Reason::Error {
+            error: Error::MalformedFlag,
+        });
+        assert!(matches!(
+            evaluation_error(&store, &flag, &context),
+            Some(EvaluationError::InvalidReference { .. })
+        ));
+
+        flag.rules.clear();
+        flag.fallthrough = VariationOrRollout::Variation { variation: 7 };
+        assert_that!(evaluation_error(&store, &flag, &context))
+            .contains_value(EvaluationError::VariationOutOfRange { variation: 7 });
+
+        flag.off_variation = Some(9);
+        flag.on = false;
+        assert_that!(evaluation_error(&store, &flag, &context))
+            .contains_value(EvaluationError::VariationOutOfRange { variation: 9 });
+
+        flag.on = true;
+        flag.fallthrough = VariationOrRollout::Variation { variation: 0 };
+        assert_that!(evaluation_error(&store, &flag, &context)).is_none();
+    }
+
+    fn evaluation_error(
+        store: &dyn Store,
+        flag: &Flag,
+        context: &Context,
+    ) -> Option<EvaluationError> {
+        evaluate_with_options(store, flag, context, None, &EvaluationOptions::default())
+            .evaluation_error
+    }
+
     #[test]
     fn can_create_error_detail() {
         let detail = Detail::err_default(Error::MalformedFlag, true.into());
@@ -0,0 +1,468 @@
+use std::collections::{BTreeMap, HashSet};
+
+use chrono::{DateTime, Utc};
+use serde::ser::{SerializeMap, Serializer};
+use serde::Serialize;
+
+use crate::big_segment::BigSegmentsStatus;
+use crate::clock::Clock;
+use crate::eval::{Detail, EvaluationResult, Reason, ReasonWithStatus, SharedPrerequisiteEvent};
+use crate::flag::Flag;
+use crate::flag_value::FlagValue;
+use crate::summary::SummaryEvent;
+use crate::variation::VariationIndex;
+use crate::{Context, ContextAttributes, Reference};
+
+/// EventOptions control how [EventOptions::evaluation_events] turns the results of flag
+/// evaluations into analytics events.
+#[derive(Clone, Debug, Default)]
+pub struct EventOptions {
+    /// Redact every optional attribute of the contexts included in events.
+    pub all_attributes_private: bool,
+
+    /// Attributes to redact from the contexts included in events, in addition to any which the
+    /// context itself marks as private.
+    pub private_attributes: HashSet<Reference>,
+
+    /// Include the evaluation reason in every feature and debug event. Without this, the reason
+    /// is only included when [Flag::is_experimentation_enabled] is true for it.
+    pub include_reasons: bool,
+}
+
+/// OutputEvent is an analytics event describing flag evaluations, in the form in which it is
+/// sent to LaunchDarkly.
+#[derive(Debug, Serialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+#[non_exhaustive]
+pub enum OutputEvent {
+    /// A full-fidelity record of a single evaluation, which refers to the context only by its keys.
+    Feature(FeatureEvent),
+    /// Like a feature event, but including the whole context. These are sent while a flag is
+    /// being debugged; see [Flag::debug_events_until_date].
+    Debug(FeatureEvent),
+    /// The attributes of a context, which feature events refer to only by key.
+    Index(IndexEvent),
+    /// Counts of the evaluations of every flag over a period of time. See [crate::EventSummarizer].
+    Summary(SummaryEvent),
+}
+
+/// FeatureEvent describes a single evaluation of a flag, in an [OutputEvent::Feature] or
+/// [OutputEvent::Debug] event.
+///
+/// When serialized, the [FeatureEvent::big_segments_status] is included in the reason as
+/// `bigSegmentsStatus`, as it is by the other LaunchDarkly SDKs.
+#[derive(Debug)]
+pub struct FeatureEvent {
+    /// When the evaluation happened, as a Unix millisecond timestamp.
+    pub creation_date: u64,
+    /// The key of the evaluated flag.
+    pub key: String,
+    /// The version of the evaluated flag.
+    pub version: u64,
+    /// The key of each kind of context in the evaluated context. Only present in feature events.
+    pub context_keys: Option<BTreeMap<String, String>>,
+    /// The evaluated context, with private attributes redacted. Only present in debug events.
+    pub context: Option<ContextAttributes>,
+    /// The index of the variation which was returned.
+    pub variation: Option<VariationIndex>,
+    /// The value which was returned.
+    pub value: Option<FlagValue>,
+    /// The default value supplied by the application. This is None for prerequisites, which are
+    /// evaluated without one.
+    pub default: Option<FlagValue>,
+    /// The reason for the result, if reasons are included. See [EventOptions::include_reasons].
+    pub reason: Option<Reason>,
+    /// The state of the big segment data used by the evaluation. See
+    /// [EvaluationResult::big_segments_status].
+    pub big_segments_status: Option<BigSegmentsStatus>,
+    /// If this flag was evaluated as a prerequisite, the key of the flag which depends on it.
+    pub prereq_of: Option<String>,
+}
+
+impl Serialize for FeatureEvent {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut map = serializer.serialize_map(None)?;
+        map.serialize_entry("creationDate", &self.creation_date)?;
+        map.serialize_entry("key", &self.key)?;
+        map.serialize_entry("version", &self.version)?;
+        if let Some(context_keys) = &self.context_keys {
+            map.serialize_entry("contextKeys", context_keys)?;
+        }
+        if let Some(context) = &self.context {
+            map.serialize_entry("context", context)?;
+        }
+        if let Some(variation) = self.variation {
+            map.serialize_entry("variation", &variation)?;
+        }
+        map.serialize_entry("value", &self.value)?;
+        if let Some(default) = &self.default {
+            map.serialize_entry("default", default)?;
+        }
+        if let Some(reason) = &self.reason {
+            map.serialize_entry(
+                "reason",
+                &ReasonWithStatus::new(reason, self.big_segments_status),
+            )?;
+        }
+        if let Some(prereq_of) = &self.prereq_of {
+            map.serialize_entry("prereqOf", prereq_of)?;
+        }
+        map.end()
+    }
+}
+
+/// IndexEvent describes a context, in an [OutputEvent::Index] event.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IndexEvent {
+    /// When the context was seen, as a Unix millisecond timestamp.
+    pub creation_date: u64,
+    /// The context, with private attributes redacted.
+    pub context: ContextAttributes,
+}
+
+impl EventOptions {
+    /// Build the analytics events for a single call to [crate::evaluate_with_options], given the
+    /// flag, context and result of the evaluation, the default value supplied by the application, and the
+    /// prerequisite evaluations reported to its [crate::PrerequisiteEventRecorder::record_shared].
+    ///
+    /// The result starts with an index event for the context. An SDK normally drops these for
+    /// contexts it has seen recently, as decided by a [crate::ContextDeduplicator]. It is followed,
+    /// for each prerequisite and then for the flag itself, by:
+    ///
+    /// * a feature event, if [Flag::track_events] is set or [Flag::is_experimentation_enabled] is
+    ///   true for the reason;
+    /// * a debug event, if the flag is being debugged at the time given by `clock`.
+    ///
+    /// Evaluations which produce neither are only counted in summary events.
+    pub fn evaluation_events(
+        &self,
+        flag: &Flag,
+        context: &Context,
+        result: &EvaluationResult<FlagValue>,
+        default: &FlagValue,
+        prerequisites: &[SharedPrerequisiteEvent],
+        clock: &dyn Clock,
+    ) -> Vec<OutputEvent> {
+        let now = clock.now();
+        let mut events = vec![self.index_event_at(context, now)];
+
+        for prerequisite in prerequisites {
+            events.extend(self.feature_events(
+                &prerequisite.prerequisite_flag,
+                &prerequisite.context,
+                &prerequisite.prerequisite_result,
+                prerequisite.big_segments_status,
+                None,
+                Some(&prerequisite.target_flag_key),
+                now,
+            ));
+        }
+        events.extend(self.feature_events(
+            flag,
+            context,
+            &result.detail,
+            result.big_segments_status,
+            Some(default),
+            None,
+            now,
+        ));
+
+        events
+    }
+
+    /// Build an index event for the context, as of the time given by `clock`.
+    pub fn index_event(&self, context: &Context, clock: &dyn Clock) -> OutputEvent {
+        self.index_event_at(context, clock.now())
+    }
+
+    fn index_event_at(&self, context: &Context, now: DateTime<Utc>) -> OutputEvent {
+        OutputEvent::Index(IndexEvent {
+            creation_date: unix_millis(now),
+            context: self.redact(context),
+        })
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn feature_events(
+        &self,
+        flag: &Flag,
+        context: &Context,
+        detail: &Detail<FlagValue>,
+        big_segments_status: Option<BigSegmentsStatus>,
+        default: Option<&FlagValue>,
+        prereq_of: Option<&str>,
+        now: DateTime<Utc>,
+    ) -> impl Iterator<Item = OutputEvent> {
+        let track_reason = flag.is_experimentation_enabled(&detail.reason);
+        let event = || FeatureEvent {
+            creation_date: unix_millis(now),
+            key: flag.key.clone(),
+            version: flag.version,
+            context_keys: None,
+            context: None,
+            variation: detail.variation_index,
+            value: detail.value.clone(),
+            default: default.cloned(),
+            reason: (self.include_reasons || track_reason).then(|| detail.reason.clone()),
+            big_segments_status,
+            prereq_of: prereq_of.map(String::from),
+        };
+
+        let feature = (flag.track_events || track_reason).then(|| {
+            OutputEvent::Feature(FeatureEvent {
+                context_keys: Some(
+                    context
+                        .context_keys()
+                        .into_iter()
+                        .map(|(kind, key)| (kind.to_string(), key.to_string()))
+                        .collect(),
+                ),
+                ..event()
+            })
+        });
+        let debug = flag.is_debugging(now).then(|| {
+            OutputEvent::Debug(FeatureEvent {
+                context: Some(self.redact(context)),
+                ..event()
+            })
+        });
+
+        feature.into_iter().chain(debug)
+    }
+
+    fn redact(&self, context: &Context) -> ContextAttributes {
+        ContextAttributes::from_context(
+            context.clone(),
+            self.all_attributes_private,
+            self.private_attributes.clone(),
+        )
+    }
+}
+
+pub(crate) fn unix_millis(time: DateTime<Utc>) -> u64 {
+    u64::try_from(time.timestamp_millis()).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eval::{evaluate_with_options, EvaluationOptions};
+    use crate::store::Store;
+    use crate::test_common::{InMemoryPrerequisiteEventRecorder, TestStore};
+    use crate::{ContextBuilder, FixedClock, FlagBuilder};
+    use assert_json_diff::assert_json_eq;
+    use chrono::TimeZone;
+    use serde_json::json;
+    use std::cell::RefCell;
+
+    const NOW: i64 = 1_000_000;
+
+    fn clock() -> FixedClock {
+        FixedClock(Utc.timestamp_millis_opt(NOW).unwrap())
+    }
+
+    fn flag(key: &str) -> FlagBuilder {
+        let mut builder = FlagBuilder::new(key);
+        builder
+            .version(2)
+            .boolean_variations()
+            .off_variation(0)
+            .fallthrough_variation(1)
+            .on(true);
+        builder
+    }
+
+    fn events_for(options: &EventOptions, flag: &Flag, context: &Context) -> serde_json::Value {
+        let result = evaluate_with_options(
+            &TestStore::new(),
+            flag,
+            context,
+            None,
+            &EvaluationOptions::default(),
+        )
+        .map(|value| value.clone());
+        let events = options.evaluation_events(
+            flag,
+            context,
+            &result,
+            &FlagValue::Bool(false),
+            &[],
+            &clock(),
+        );
+        serde_json::to_value(events).unwrap()
+    }
+
+    #[test]
+    fn untracked_evaluations_only_produce_an_index_event() {
+        let context = ContextBuilder::new("alice").build().unwrap();
+        let events = events_for(
+            &EventOptions::default(),
+            &flag("f").build().unwrap(),
+            &context,
+        );
+        assert_json_eq!(
+            events,
+            json!([{
+                "kind": "index",
+                "creationDate": NOW,
+                "context": {"kind": "user", "key": "alice"}
+            }])
+        );
+    }
+
+    #[test]
+    fn tracked_flags_produce_feature_events() {
+        let context = ContextBuilder::new("alice").build().unwrap();
+        let flag = flag("f").track_events(true).build().unwrap();
+
+        let events = events_for(&EventOptions::default(), &flag, &context);
+        assert_json_eq!(
+            events[1],
+            json!({
+                "kind": "feature",
+                "creationDate": NOW,
+                "key": "f",
+                "version": 2,
+                "contextKeys": {"user": "alice"},
+                "variation": 1,
+                "value": true,
+                "default": false
+            })
+        );
+
+        let options = EventOptions {
+            include_reasons: true,
+            ..Default::default()
+        };
+        let events = events_for(&options, &flag, &context);
+        assert_json_eq!(events[1]["reason"], json!({"kind": "FALLTHROUGH"}));
+    }
+
+    #[test]
+    fn big_segments_status_is_included_in_the_reason() {
+        let context = ContextBuilder::new("alice").build().unwrap();
+        let flag = flag("f").track_events(true).build().unwrap();
+        let result = EvaluationResult {
+            detail: Detail {
+                value: Some(FlagValue::Bool(true)),
+                variation_index: Some(1),
+                reason: Reason::Fallthrough {
+                    in_experiment: false,
+                },
+            },
+            big_segments_status: Some(BigSegmentsStatus::Stale),
+            evaluation_error: None,
+        };
+        let options = EventOptions {
+            include_reasons: true,
+            ..Default::default()
+        };
+        let events = options.evaluation_events(
+            &flag,
+            &context,
+            &result,
+            &FlagValue::Bool(false),
+            &[],
+            &clock(),
+        );
+        assert_json_eq!(
+            serde_json::to_value(events).unwrap()[1]["reason"],
+            json!({"kind": "FALLTHROUGH", "bigSegmentsStatus": "STALE"})
+        );
+    }
+
+    #[test]
+    fn experiments_produce_feature_events_with_reasons() {
+        let context = ContextBuilder::new("alice").build().unwrap();
+        let flag = flag("f").track_events_fallthrough(true).build().unwrap();
+
+        let events = events_for(&EventOptions::default(), &flag, &context);
+        assert_eq!(2, events.as_array().unwrap().len());
+        assert_eq!("feature", events[1]["kind"]);
+        assert_json_eq!(events[1]["reason"], json!({"kind": "FALLTHROUGH"}));
+    }
+
+    #[test]
+    fn debugged_flags_produce_debug_events_with_redacted_contexts() {
+        let context = ContextBuilder::new("alice")
+            .name("Alice")
+            .set_value("email", "alice@example.com".into())
+            .build()
+            .unwrap();
+        let options = EventOptions {
+            private_attributes: HashSet::from([Reference::new("email")]),
+            ..Default::default()
+        };
+
+        let debugged = flag("f")
+            .debug_events_until_date(NOW as u64 + 1)
+            .build()
+            .unwrap();
+        let events = events_for(&options, &debugged, &context);
+        assert_json_eq!(
+            events[1],
+            json!({
+                "kind": "debug",
+                "creationDate": NOW,
+                "key": "f",
+                "version": 2,
+                "context": {
+                    "kind": "user",
+                    "key": "alice",
+                    "name": "Alice",
+                    "_meta": {"redactedAttributes": ["email"]}
+                },
+                "variation": 1,
+                "value": true,
+                "default": false
+            })
+        );
+
+        let expired = flag("f")
+            .debug_events_until_date(NOW as u64)
+            .build()
+            .unwrap();
+        let events = events_for(&options, &expired, &context);
+        assert_eq!(1, events.as_array().unwrap().len());
+    }
+
+    #[test]
+    fn prerequisites_produce_events_before_the_flag() {
+        let mut store = TestStore::new();
+        store.update_flag("prereq", |flag| flag.track_events = true);
+        let parent = store.flag("flagWithSatisfiedPrereq").unwrap();
+        let context = ContextBuilder::new("alice").build().unwrap();
+
+        let recorder = InMemoryPrerequisiteEventRecorder {
+            events: RefCell::new(Vec::new()),
+        };
+        let result = evaluate_with_options(
+            &store,
+            &parent,
+            &context,
+            Some(&recorder),
+            &EvaluationOptions::default(),
+        )
+        .map(|v| v.clone());
+        let prerequisites = recorder.events.into_inner();
+        let events = EventOptions::default().evaluation_events(
+            &parent,
+            &context,
+            &result,
+            &FlagValue::Bool(false),
+            &prerequisites,
+            &clock(),
+        );
+
+        let events = serde_json::to_value(events).unwrap();
+        assert_eq!(2, events.as_array().unwrap().len());
+        assert_eq!("feature", events[1]["kind"]);
+        assert_eq!("prereq", events[1]["key"]);
+        assert_eq!("flagWithSatisfiedPrereq", events[1]["prereqOf"]);
+        assert!(events[1].get("default").is_none());
+    }
+}
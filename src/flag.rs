@@ -1,6 +1,7 @@
 use std::convert::TryFrom;
 use std::fmt;
 
+use chrono::{DateTime, Utc};
 use log::warn;
 use serde::de::{MapAccess, Visitor};
 use serde::{
@@ -9,11 +10,14 @@ use serde::{
 };
 
 use crate::contexts::context::Kind;
-use crate::eval::{self, Detail, Reason};
+use crate::eval::{self, Detail, EvaluationError, Reason};
 use crate::flag_value::FlagValue;
 use crate::rule::FlagRule;
+use crate::store::Store;
+use crate::util::KeySet;
+use crate::validation::{Diagnostic, Diagnostics};
 use crate::variation::{VariationIndex, VariationOrRollout};
-use crate::{BucketResult, Context, Versioned};
+use crate::{BucketResult, Context, EvaluationStack, Versioned};
 
 /// Flag describes an individual feature flag.
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -38,13 +42,13 @@ pub struct Flag {
 
     pub(crate) fallthrough: VariationOrRollout,
     pub(crate) off_variation: Option<VariationIndex>,
-    variations: Vec<FlagValue>,
+    pub(crate) variations: Vec<FlagValue>,
 
     /// Indicates whether a flag is available using each of the client-side authentication methods.
     #[serde(flatten)]
-    client_visibility: ClientVisibility,
+    pub(crate) client_visibility: ClientVisibility,
 
-    salt: String,
+    pub(crate) salt: String,
 
     /// Used internally by the SDK analytics event system.
     ///
@@ -52,8 +56,8 @@ pub struct Flag {
     /// the "send detailed event information for this flag" option for this flag. This tells the SDK to
     /// send full event data for each flag evaluation, rather than only aggregate data in a summary event.
     ///
-    /// The launchdarkly-server-sdk-evaluation crate does not implement that behavior; it is only
-    /// in the data model for use by the SDK.
+    /// [crate::EventOptions::evaluation_events] produces a feature event for every evaluation of
+    /// the flag when this is set.
     #[serde(default)]
     pub track_events: bool,
 
@@ -64,8 +68,8 @@ pub struct Flag {
     /// SDK to send full event data for any evaluation where this flag had targeting turned on but the
     /// context did not match any targets or rules.
     ///
-    /// The launchdarkly-server-sdk-evaluation package does not implement that behavior; it is only
-    /// in the data model for use by the SDK.
+    /// [crate::EventOptions::evaluation_events] produces a feature event, with the reason, for
+    /// fallthrough evaluations when this is set, through [Flag::is_experimentation_enabled].
     #[serde(default)]
     pub track_events_fallthrough: bool,
 
@@ -76,8 +80,8 @@ pub struct Flag {
     /// millisecond timestamp when this mode should expire. Until then, the SDK will send full event data
     /// for each evaluation of this flag.
     ///
-    /// The launchdarkly-server-sdk-evaluation package does not implement that behavior; it is only in the data
-    /// model for use by the SDK.
+    /// [crate::EventOptions::evaluation_events] produces a debug event for each evaluation made
+    /// before this time, according to the evaluation's clock.
     #[serde(default)]
     pub debug_events_until_date: Option<u64>,
 }
@@ -89,8 +93,8 @@ impl Versioned for Flag {
 }
 
 #[derive(Clone, Debug)]
-struct ClientVisibility {
-    client_side_availability: ClientSideAvailability,
+pub(crate) struct ClientVisibility {
+    pub(crate) client_side_availability: ClientSideAvailability,
 }
 
 impl<'de> Deserialize<'de> for ClientVisibility {
@@ -183,16 +187,50 @@ pub struct Prereq {
     pub(crate) variation: VariationIndex,
 }
 
+impl Prereq {
+    /// The key of the flag which must return the variation.
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// The index of the variation which the prerequisite flag must return.
+    pub fn variation(&self) -> VariationIndex {
+        self.variation
+    }
+}
+
+/// Target describes a set of contexts, of a single kind, which will receive a specific variation
+/// of a flag based on their keys.
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
-pub(crate) struct Target {
+pub struct Target {
     #[serde(default)]
     pub(crate) context_kind: Kind,
 
-    pub(crate) values: Vec<String>,
+    pub(crate) values: KeySet,
     pub(crate) variation: VariationIndex,
 }
 
+impl Target {
+    /// The kind of context which this target applies to.
+    pub fn context_kind(&self) -> &Kind {
+        &self.context_kind
+    }
+
+    /// The keys of the targeted contexts.
+    ///
+    /// In a flag's [Flag::context_targets], a user target with no values indicates that the keys
+    /// for that variation are held in [Flag::targets] instead.
+    pub fn values(&self) -> &[String] {
+        self.values.as_slice()
+    }
+
+    /// The index of the variation returned to the targeted contexts.
+    pub fn variation(&self) -> VariationIndex {
+        self.variation
+    }
+}
+
 /// ClientSideAvailability describes whether a flag is available to client-side SDKs.
 ///
 /// This field can be used by a server-side client to determine whether to include an individual flag in
@@ -212,10 +250,139 @@ pub struct ClientSideAvailability {
     // If it was, we will use the properities of this new schema over the dated
     // [ClientVisibility::client_side] field.
     #[serde(skip)]
-    explicit: bool,
+    pub(crate) explicit: bool,
 }
 
 impl Flag {
+    /// True if targeting is turned on for the flag. If it is off, the flag always returns its
+    /// off variation.
+    pub fn on(&self) -> bool {
+        self.on
+    }
+
+    /// The lists of user keys which are individually targeted by the flag.
+    ///
+    /// These are only meaningful for the user context kind. Targets for other kinds are held in
+    /// [Flag::context_targets].
+    pub fn targets(&self) -> &[Target] {
+        &self.targets
+    }
+
+    /// The lists of context keys, of any kind, which are individually targeted by the flag.
+    pub fn context_targets(&self) -> &[Target] {
+        &self.context_targets
+    }
+
+    /// The flag's rules, in the order in which they are checked.
+    pub fn rules(&self) -> &[FlagRule] {
+        &self.rules
+    }
+
+    /// The flags which must return particular variations for this flag to be evaluated normally.
+    pub fn prerequisites(&self) -> &[Prereq] {
+        &self.prerequisites
+    }
+
+    /// The variation or rollout used when targeting is on but the context does not match any
+    /// targets or rules.
+    pub fn fallthrough(&self) -> &VariationOrRollout {
+        &self.fallthrough
+    }
+
+    /// The index of the variation returned when targeting is off, if any.
+    pub fn off_variation(&self) -> Option<VariationIndex> {
+        self.off_variation
+    }
+
+    /// The values which the flag can return.
+    pub fn variations(&self) -> &[FlagValue] {
+        &self.variations
+    }
+
+    /// The salt used when computing rollout buckets for the flag.
+    pub fn salt(&self) -> &str {
+        &self.salt
+    }
+
+    /// Describes whether the flag is available to client-side SDKs.
+    pub fn client_side_availability(&self) -> &ClientSideAvailability {
+        &self.client_visibility.client_side_availability
+    }
+
+    /// Checks the flag for malformed data, such as variation indices which are out of range,
+    /// invalid attribute references, and clause values which can never match.
+    ///
+    /// Whether a prerequisite's variation is in range depends on the prerequisite flag, so only
+    /// negative prerequisite variations are reported here; use [Flag::validate_with_store] to
+    /// check them against the prerequisite flags.
+    ///
+    /// Returns an empty list if no problems were found.
+    pub fn validate(&self) -> Vec<Diagnostic> {
+        let mut diagnostics = Diagnostics::default();
+        let count = self.variations.len();
+
+        if let Some(off_variation) = self.off_variation {
+            diagnostics.check_variation("offVariation", off_variation, count);
+        }
+        diagnostics.check_variation_or_rollout("fallthrough", &self.fallthrough, count);
+
+        for (index, target) in self.targets.iter().enumerate() {
+            diagnostics.check_variation(
+                format!("targets[{}].variation", index),
+                target.variation,
+                count,
+            );
+        }
+        for (index, target) in self.context_targets.iter().enumerate() {
+            diagnostics.check_variation(
+                format!("contextTargets[{}].variation", index),
+                target.variation,
+                count,
+            );
+        }
+
+        for (index, prereq) in self.prerequisites.iter().enumerate() {
+            if prereq.variation < 0 {
+                diagnostics.check_variation(
+                    format!("prerequisites[{}].variation", index),
+                    prereq.variation,
+                    0,
+                );
+            }
+        }
+
+        for (index, rule) in self.rules.iter().enumerate() {
+            let location = format!("rules[{}]", index);
+            diagnostics.check_variation_or_rollout(&location, &rule.variation_or_rollout, count);
+            diagnostics.check_clauses(&location, &rule.clauses);
+        }
+
+        diagnostics.into_vec()
+    }
+
+    /// Checks the flag as [Flag::validate] does, and also checks that each prerequisite's
+    /// variation is in range for the prerequisite flag held in `store`.
+    ///
+    /// Prerequisites which are not in the store are not reported.
+    pub fn validate_with_store(&self, store: &dyn Store) -> Vec<Diagnostic> {
+        let mut diagnostics = Diagnostics::default();
+        for (index, prereq) in self.prerequisites.iter().enumerate() {
+            if let Some(prereq_flag) = store.shared_flag(&prereq.key) {
+                if prereq.variation >= 0 {
+                    diagnostics.check_variation(
+                        format!("prerequisites[{}].variation", index),
+                        prereq.variation,
+                        prereq_flag.variations.len(),
+                    );
+                }
+            }
+        }
+
+        let mut all = self.validate();
+        all.extend(diagnostics.into_vec());
+        all
+    }
+
     /// Generate a [crate::Detail] response with the given variation and reason.
     pub fn variation(&self, index: VariationIndex, reason: Reason) -> Detail<&FlagValue> {
         let (value, variation_index) = match usize::try_from(index) {
@@ -269,10 +436,10 @@ impl Flag {
         &self,
         vr: &VariationOrRollout,
         context: &Context,
-    ) -> Result<BucketResult, eval::Error> {
-        vr.variation(&self.key, context, &self.salt)
-            .map_err(|_| eval::Error::MalformedFlag)?
-            .ok_or(eval::Error::MalformedFlag)
+        evaluation_stack: &mut EvaluationStack,
+    ) -> Result<BucketResult, EvaluationError> {
+        vr.variation(&self.key, context, &self.salt, evaluation_stack)?
+            .ok_or(EvaluationError::MalformedRollout)
     }
 
     /// Returns true if, based on the [crate::Reason] returned by the flag evaluation, an event for
@@ -292,6 +459,13 @@ impl Flag {
         }
     }
 
+    // Returns true if debugging has been temporarily enabled for this flag and has not yet expired.
+    pub(crate) fn is_debugging(&self, now: DateTime<Utc>) -> bool {
+        self.debug_events_until_date
+            .map(|until| i128::from(until) > i128::from(now.timestamp_millis()))
+            .unwrap_or(false)
+    }
+
     #[cfg(test)]
     pub(crate) fn new_boolean_flag_with_segment_match(segment_keys: Vec<&str>, kind: Kind) -> Self {
         Self {
@@ -328,6 +502,7 @@ mod tests {
 
     use super::Flag;
     use crate::eval::Reason::*;
+    use crate::{AttributeValue, Op, Problem, Reference, RolloutKind, VariationOrRollout};
     use test_case::test_case;
 
     #[test_case(true)]
@@ -528,6 +703,68 @@ mod tests {
         assert_eq!(json, &restored);
     }
 
+    #[test]
+    fn flag_model_can_be_inspected() {
+        let flag: Flag = serde_json::from_value(serde_json::json!({
+            "key": "flag",
+            "version": 1,
+            "on": true,
+            "targets": [{"values": ["alice"], "variation": 0}],
+            "contextTargets": [{"contextKind": "org", "values": ["acme"], "variation": 1}],
+            "rules": [{
+                "id": "rule",
+                "clauses": [{
+                    "attribute": "name",
+                    "op": "startsWith",
+                    "values": ["B"],
+                    "negate": true
+                }],
+                "rollout": {
+                    "kind": "experiment",
+                    "bucketBy": "email",
+                    "variations": [{"variation": 0, "weight": 40000}, {"variation": 1, "weight": 60000}],
+                    "seed": 61
+                },
+                "trackEvents": false
+            }],
+            "prerequisites": [{"key": "prereq", "variation": 1}],
+            "fallthrough": {"variation": 1},
+            "offVariation": 0,
+            "variations": [false, true],
+            "clientSide": true,
+            "salt": "salty"
+        }))
+        .unwrap();
+
+        assert!(flag.on());
+        assert_eq!(["alice"], flag.targets()[0].values());
+        assert_eq!("org", flag.context_targets()[0].context_kind().as_ref());
+        assert_eq!(1, flag.context_targets()[0].variation());
+        assert_eq!("prereq", flag.prerequisites()[0].key());
+        assert_eq!(1, flag.prerequisites()[0].variation());
+        assert_eq!(Some(0), flag.off_variation());
+        assert_eq!(2, flag.variations().len());
+        assert_eq!("salty", flag.salt());
+        assert!(flag.client_side_availability().using_environment_id);
+
+        let clause = &flag.rules()[0].clauses()[0];
+        assert_eq!(Op::StartsWith, clause.op());
+        assert_eq!("name", clause.attribute().to_string());
+        assert!(clause.negate());
+        assert_eq!(&[AttributeValue::from("B")], clause.values());
+
+        match &flag.rules()[0].variation_or_rollout {
+            VariationOrRollout::Rollout { rollout } => {
+                assert_eq!(RolloutKind::Experiment, rollout.kind());
+                assert_eq!(Some(&Reference::new("email")), rollout.bucket_by());
+                assert_eq!(2, rollout.variations().len());
+                assert_eq!(Some(61), rollout.seed());
+                assert_eq!(None, rollout.context_kind());
+            }
+            other => panic!("unexpected variation or rollout {:?}", other),
+        }
+    }
+
     #[test]
     fn is_experimentation_enabled() {
         let store = TestStore::new();
@@ -551,7 +788,7 @@ mod tests {
         asserting!("true for rule if rule.trackEvents is true")
             .that(&flag.is_experimentation_enabled(&RuleMatch {
                 rule_index: 0,
-                rule_id: flag.rules.get(0).unwrap().id.clone(),
+                rule_id: flag.rules.first().unwrap().id.clone(),
                 in_experiment: false,
             }))
             .is_true();
@@ -583,4 +820,89 @@ mod tests {
             }))
             .is_false();
     }
+
+    #[test]
+    fn validate_reports_malformed_data() {
+        let flag: Flag = serde_json::from_value(serde_json::json!({
+            "key": "flag",
+            "version": 1,
+            "on": true,
+            "targets": [{"values": ["alice"], "variation": 2}],
+            "rules": [
+                {
+                    "clauses": [
+                        {"attribute": "name", "op": "matches", "values": ["("]},
+                        {"contextKind": "user", "attribute": "/", "op": "in", "values": ["x"]},
+                        {"attribute": "version", "op": "semVerEqual", "values": ["not-a-version"]},
+                        {"attribute": "name", "op": "somethingNew", "values": []}
+                    ],
+                    "rollout": {
+                        "variations": [
+                            {"variation": 0, "weight": 60000},
+                            {"variation": 1, "weight": 30000}
+                        ],
+                        "contextKind": "user",
+                        "bucketBy": ""
+                    },
+                    "trackEvents": false
+                },
+                {"clauses": [], "trackEvents": false}
+            ],
+            "prerequisites": [{"key": "prereq", "variation": -1}],
+            "fallthrough": {"variation": 0},
+            "offVariation": 5,
+            "variations": [false, true],
+            "salt": "salty"
+        }))
+        .unwrap();
+
+        let locations: Vec<_> = flag.validate().into_iter().map(|d| d.location).collect();
+        assert_eq!(
+            vec![
+                "offVariation",
+                "targets[0].variation",
+                "prerequisites[0].variation",
+                "rules[0].rollout",
+                "rules[0].rollout.bucketBy",
+                "rules[0].clauses[0]",
+                "rules[0].clauses[1].attribute",
+                "rules[0].clauses[2]",
+                "rules[0].clauses[3]",
+                "rules[1]",
+            ],
+            locations
+        );
+
+        let problems: Vec<_> = flag.validate().into_iter().map(|d| d.problem).collect();
+        assert_eq!(
+            Problem::VariationOutOfRange {
+                variation: 5,
+                count: 2
+            },
+            problems[0]
+        );
+        assert_eq!(Problem::RolloutWeights { total: 90000 }, problems[3]);
+        assert_eq!(
+            Problem::UnknownOperator {
+                name: "somethingNew".to_string()
+            },
+            problems[8]
+        );
+        assert_eq!(Problem::MalformedVariationOrRollout, problems[9]);
+    }
+
+    #[test]
+    fn validate_checks_prerequisite_variations_against_store() {
+        let store = TestStore::new();
+        assert!(store.flag("flag").unwrap().validate().is_empty());
+
+        let mut flag = store.flag("flagWithOffPrereq").unwrap();
+        assert!(flag.validate_with_store(&store).is_empty());
+
+        flag.prerequisites[0].variation = 10;
+        assert!(flag.validate().is_empty());
+        let diagnostics = flag.validate_with_store(&store);
+        assert_eq!(1, diagnostics.len());
+        assert_eq!("prerequisites[0].variation", diagnostics[0].location);
+    }
 }
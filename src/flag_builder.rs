@@ -0,0 +1,610 @@
+use crate::contexts::context::Kind;
+use crate::flag::{ClientSideAvailability, ClientVisibility, Flag, Prereq, Target};
+use crate::flag_value::FlagValue;
+use crate::rule::{Clause, FlagRule, Op};
+use crate::validation::check_built;
+use crate::variation::{VariationIndex, VariationOrRollout};
+use crate::{AttributeValue, Reference};
+
+/// Contains methods for building a [Flag] with a specified key.
+///
+/// This is intended for constructing flags in tests and tools, without having to write out their
+/// JSON representation. If you do not change any values, the defaults for the [Flag] are:
+/// - its key is set to whatever value you passed to [FlagBuilder::new]
+/// - its version is 1
+/// - it is off, and has no variations, targets, rules or prerequisites
+/// - it has no off variation and no fallthrough, both of which must be set if the flag has any
+///   variations
+///
+/// Every variation index used by the flag is checked against its variations when
+/// [FlagBuilder::build] is called.
+pub struct FlagBuilder {
+    key: String,
+    version: u64,
+    on: bool,
+    variations: Vec<FlagValue>,
+    off_variation: Option<VariationIndex>,
+    fallthrough: Option<VariationOrRollout>,
+    targets: Vec<Target>,
+    context_targets: Vec<Target>,
+    prerequisites: Vec<Prereq>,
+    rules: Vec<FlagRule>,
+    salt: String,
+    client_side_availability: ClientSideAvailability,
+    track_events: bool,
+    track_events_fallthrough: bool,
+    debug_events_until_date: Option<u64>,
+}
+
+impl FlagBuilder {
+    /// Create a new flag builder for a flag with the provided key.
+    pub fn new(key: impl Into<String>) -> Self {
+        Self {
+            key: key.into(),
+            version: 1,
+            on: false,
+            variations: Vec::new(),
+            off_variation: None,
+            fallthrough: None,
+            targets: Vec::new(),
+            context_targets: Vec::new(),
+            prerequisites: Vec::new(),
+            rules: Vec::new(),
+            salt: String::new(),
+            client_side_availability: ClientSideAvailability {
+                using_mobile_key: false,
+                using_environment_id: false,
+                explicit: true,
+            },
+            track_events: false,
+            track_events_fallthrough: false,
+            debug_events_until_date: None,
+        }
+    }
+
+    /// Sets the flag's version.
+    pub fn version(&mut self, version: u64) -> &mut Self {
+        self.version = version;
+        self
+    }
+
+    /// Sets whether targeting is turned on for the flag.
+    pub fn on(&mut self, on: bool) -> &mut Self {
+        self.on = on;
+        self
+    }
+
+    /// Sets the flag's variations, replacing any that were previously set.
+    pub fn variations(&mut self, variations: Vec<FlagValue>) -> &mut Self {
+        self.variations = variations;
+        self
+    }
+
+    /// Sets the flag to have two boolean variations, `false` and `true`, in that order.
+    pub fn boolean_variations(&mut self) -> &mut Self {
+        self.variations(vec![FlagValue::Bool(false), FlagValue::Bool(true)])
+    }
+
+    /// Sets the variation returned when targeting is off.
+    pub fn off_variation(&mut self, variation: VariationIndex) -> &mut Self {
+        self.off_variation = Some(variation);
+        self
+    }
+
+    /// Sets the variation returned when targeting is on but the context does not match any
+    /// targets or rules.
+    pub fn fallthrough_variation(&mut self, variation: VariationIndex) -> &mut Self {
+        self.fallthrough(VariationOrRollout::Variation { variation })
+    }
+
+    /// Sets the fixed variation or rollout used when targeting is on but the context does not
+    /// match any targets or rules.
+    pub fn fallthrough(&mut self, fallthrough: VariationOrRollout) -> &mut Self {
+        self.fallthrough = Some(fallthrough);
+        self
+    }
+
+    /// Adds a list of user keys which will receive the given variation.
+    pub fn add_target(
+        &mut self,
+        variation: VariationIndex,
+        keys: impl IntoIterator<Item = impl Into<String>>,
+    ) -> &mut Self {
+        self.targets.push(Target {
+            context_kind: Kind::user(),
+            values: keys.into_iter().map(Into::into).collect::<Vec<_>>().into(),
+            variation,
+        });
+        self
+    }
+
+    /// Adds a list of keys of contexts of the given kind which will receive the given variation.
+    ///
+    /// If `kind` is user, this is equivalent to [FlagBuilder::add_target], except that the
+    /// target is checked in the order in which it was added relative to targets for other kinds.
+    pub fn add_context_target(
+        &mut self,
+        kind: Kind,
+        variation: VariationIndex,
+        keys: impl IntoIterator<Item = impl Into<String>>,
+    ) -> &mut Self {
+        if kind.is_user() {
+            // User keys are held in the old-style target list, and referenced from the context
+            // target list by an entry with no values.
+            self.add_target(variation, keys);
+            self.context_targets.push(Target {
+                context_kind: kind,
+                values: Vec::new().into(),
+                variation,
+            });
+        } else {
+            self.context_targets.push(Target {
+                context_kind: kind,
+                values: keys.into_iter().map(Into::into).collect::<Vec<_>>().into(),
+                variation,
+            });
+        }
+        self
+    }
+
+    /// Adds a requirement that the flag with key `key` return the variation `variation`.
+    pub fn add_prerequisite(
+        &mut self,
+        key: impl Into<String>,
+        variation: VariationIndex,
+    ) -> &mut Self {
+        self.prerequisites.push(Prereq {
+            key: key.into(),
+            variation,
+        });
+        self
+    }
+
+    /// Adds a rule to the flag. Rules are checked in the order in which they are added.
+    ///
+    /// See [FlagRuleBuilder] for constructing a rule.
+    pub fn add_rule(&mut self, rule: FlagRule) -> &mut Self {
+        self.rules.push(rule);
+        self
+    }
+
+    /// Sets the salt used when computing rollout buckets for the flag.
+    pub fn salt(&mut self, salt: impl Into<String>) -> &mut Self {
+        self.salt = salt.into();
+        self
+    }
+
+    /// Sets whether the flag is available to client-side SDKs using an environment id and using a
+    /// mobile key, respectively.
+    pub fn client_side_availability(
+        &mut self,
+        using_environment_id: bool,
+        using_mobile_key: bool,
+    ) -> &mut Self {
+        self.client_side_availability.using_environment_id = using_environment_id;
+        self.client_side_availability.using_mobile_key = using_mobile_key;
+        self
+    }
+
+    /// Sets the flag's [Flag::track_events] property.
+    pub fn track_events(&mut self, track_events: bool) -> &mut Self {
+        self.track_events = track_events;
+        self
+    }
+
+    /// Sets the flag's [Flag::track_events_fallthrough] property.
+    pub fn track_events_fallthrough(&mut self, track_events_fallthrough: bool) -> &mut Self {
+        self.track_events_fallthrough = track_events_fallthrough;
+        self
+    }
+
+    /// Sets the flag's [Flag::debug_events_until_date] property, as a Unix millisecond timestamp.
+    pub fn debug_events_until_date(&mut self, date: u64) -> &mut Self {
+        self.debug_events_until_date = Some(date);
+        self
+    }
+
+    /// Creates a flag from the current builder properties.
+    ///
+    /// An error is returned if the key is empty, if the flag has variations but no off variation
+    /// or fallthrough, or if [Flag::validate] finds any problems with the flag, such as a target,
+    /// rule, rollout, off variation or fallthrough which refers to a variation that does not
+    /// exist.
+    pub fn build(&self) -> Result<Flag, String> {
+        if self.key.is_empty() {
+            return Err("flag key cannot be empty".to_string());
+        }
+
+        let count = self.variations.len();
+        let fallthrough = match (&self.fallthrough, count) {
+            (Some(fallthrough), _) => fallthrough.clone(),
+            // A flag without variations can only ever return no value, so there is nothing for
+            // the fallthrough to refer to.
+            (None, 0) => VariationOrRollout::Variation { variation: 0 },
+            (None, _) => return Err(format!("flag {} has no fallthrough", self.key)),
+        };
+        if self.off_variation.is_none() && count > 0 {
+            return Err(format!("flag {} has no off variation", self.key));
+        }
+
+        let flag = Flag {
+            key: self.key.clone(),
+            version: self.version,
+            on: self.on,
+            targets: self.targets.clone(),
+            context_targets: self.context_targets.clone(),
+            rules: self.rules.clone(),
+            prerequisites: self.prerequisites.clone(),
+            fallthrough,
+            off_variation: self.off_variation,
+            variations: self.variations.clone(),
+            client_visibility: ClientVisibility {
+                client_side_availability: self.client_side_availability.clone(),
+            },
+            salt: self.salt.clone(),
+            track_events: self.track_events,
+            track_events_fallthrough: self.track_events_fallthrough,
+            debug_events_until_date: self.debug_events_until_date,
+        };
+
+        // The placeholder fallthrough of a flag without variations is never used.
+        let diagnostics = flag
+            .validate()
+            .into_iter()
+            .filter(|d| self.fallthrough.is_some() || d.location != "fallthrough.variation");
+        check_built("flag", &self.key, diagnostics)?;
+
+        Ok(flag)
+    }
+}
+
+/// Contains methods for building a [FlagRule], to be added to a flag with
+/// [FlagBuilder::add_rule].
+///
+/// A rule must be given either a variation or a rollout before it is built. Whether that
+/// variation exists is checked when the flag itself is built.
+pub struct FlagRuleBuilder {
+    id: String,
+    clauses: Vec<Clause>,
+    variation_or_rollout: Option<VariationOrRollout>,
+    track_events: bool,
+}
+
+impl FlagRuleBuilder {
+    /// Create a new rule builder for a rule with the provided id.
+    pub fn new(id: impl Into<String>) -> Self {
+        Self {
+            id: id.into(),
+            clauses: Vec::new(),
+            variation_or_rollout: None,
+            track_events: false,
+        }
+    }
+
+    /// Adds a clause to the rule. A context matches the rule only if it matches every clause.
+    ///
+    /// See [ClauseBuilder] for constructing a clause.
+    pub fn add_clause(&mut self, clause: Clause) -> &mut Self {
+        self.clauses.push(clause);
+        self
+    }
+
+    /// Sets the variation returned when a context matches the rule.
+    pub fn variation(&mut self, variation: VariationIndex) -> &mut Self {
+        self.variation_or_rollout(VariationOrRollout::Variation { variation })
+    }
+
+    /// Sets the fixed variation or rollout used when a context matches the rule.
+    pub fn variation_or_rollout(&mut self, variation_or_rollout: VariationOrRollout) -> &mut Self {
+        self.variation_or_rollout = Some(variation_or_rollout);
+        self
+    }
+
+    /// Sets the rule's [FlagRule::track_events] property.
+    pub fn track_events(&mut self, track_events: bool) -> &mut Self {
+        self.track_events = track_events;
+        self
+    }
+
+    /// Creates a rule from the current builder properties.
+    ///
+    /// An error is returned if no variation or rollout was set.
+    pub fn build(&self) -> Result<FlagRule, String> {
+        let variation_or_rollout = match &self.variation_or_rollout {
+            Some(VariationOrRollout::Malformed(_)) | None => {
+                return Err(format!("rule {} has no variation or rollout", self.id))
+            }
+            Some(variation_or_rollout) => variation_or_rollout.clone(),
+        };
+
+        Ok(FlagRule {
+            id: self.id.clone(),
+            clauses: self.clauses.clone(),
+            variation_or_rollout,
+            track_events: self.track_events,
+        })
+    }
+}
+
+/// Contains methods for building a [Clause], to be added to a rule with
+/// [FlagRuleBuilder::add_clause] or [crate::SegmentBuilder::add_rule].
+///
+/// If you do not change any values, the clause applies to the user context kind, is not negated,
+/// and has no values.
+pub struct ClauseBuilder {
+    context_kind: Kind,
+    attribute: Reference,
+    negate: bool,
+    op: Op,
+    op_name: Option<String>,
+    values: Vec<AttributeValue>,
+}
+
+impl ClauseBuilder {
+    /// Create a new clause builder which tests the attribute `attribute` with the operation `op`.
+    pub fn new(attribute: impl Into<Reference>, op: Op) -> Self {
+        Self {
+            context_kind: Kind::user(),
+            attribute: attribute.into(),
+            negate: false,
+            op,
+            op_name: None,
+            values: Vec::new(),
+        }
+    }
+
+    /// Create a new clause builder which tests the attribute `attribute` with the operation named
+    /// `op_name`.
+    ///
+    /// This is for operations which are not built into this crate, and are implemented by a
+    /// [crate::CustomOperator] at evaluation time. If `op_name` is the name of a built-in
+    /// operation, the built-in operation is used.
+    pub fn custom(attribute: impl Into<Reference>, op_name: impl Into<String>) -> Self {
+        Self {
+            op_name: Some(op_name.into()),
+            ..Self::new(attribute, Op::Unknown)
+        }
+    }
+
+    /// Create a new clause builder for a clause which matches contexts in any of the segments
+    /// whose keys are provided.
+    pub fn segment_match(segment_keys: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        let mut builder = Self::new("", Op::SegmentMatch);
+        for key in segment_keys {
+            builder.add_value(AttributeValue::String(key.into()));
+        }
+        builder
+    }
+
+    /// Sets the kind of context whose attribute is tested, which is user by default.
+    pub fn context_kind(&mut self, kind: Kind) -> &mut Self {
+        self.context_kind = kind;
+        self
+    }
+
+    /// Sets whether the result of the clause is negated.
+    pub fn negate(&mut self, negate: bool) -> &mut Self {
+        self.negate = negate;
+        self
+    }
+
+    /// Adds a value to test the attribute against.
+    pub fn add_value(&mut self, value: impl Into<AttributeValue>) -> &mut Self {
+        self.values.push(value.into());
+        self
+    }
+
+    /// Creates a clause from the current builder properties.
+    ///
+    /// An error is returned if the attribute is not a valid reference, unless the clause is a
+    /// segment match, which does not use its attribute.
+    pub fn build(&self) -> Result<Clause, String> {
+        if self.op != Op::SegmentMatch && !self.attribute.is_valid() {
+            return Err(self.attribute.error());
+        }
+
+        Ok(match &self.op_name {
+            Some(op_name) => Clause::with_op_name(
+                self.context_kind.clone(),
+                self.attribute.clone(),
+                self.negate,
+                op_name.clone(),
+                self.values.clone(),
+            ),
+            None => Clause::new(
+                self.context_kind.clone(),
+                self.attribute.clone(),
+                self.negate,
+                self.op,
+                self.values.clone(),
+            ),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eval::{evaluate, Reason};
+    use crate::store::Store;
+    use crate::test_common::TestStore;
+    use crate::ContextBuilder;
+    use assert_json_diff::assert_json_eq;
+    use serde_json::json;
+    use test_case::test_case;
+
+    fn boolean_flag() -> FlagBuilder {
+        let mut builder = FlagBuilder::new("flag");
+        builder
+            .boolean_variations()
+            .off_variation(0)
+            .fallthrough_variation(1);
+        builder
+    }
+
+    #[test]
+    fn builds_flag_equivalent_to_json() {
+        let rule = FlagRuleBuilder::new("rule")
+            .add_clause(
+                ClauseBuilder::new("name", Op::In)
+                    .add_value("Alice")
+                    .build()
+                    .unwrap(),
+            )
+            .variation(1)
+            .build()
+            .unwrap();
+        let flag = boolean_flag()
+            .version(2)
+            .on(true)
+            .salt("salty")
+            .add_target(1, vec!["bob"])
+            .add_prerequisite("prereq", 0)
+            .add_rule(rule)
+            .client_side_availability(true, false)
+            .build()
+            .unwrap();
+
+        assert_json_eq!(
+            json!(flag),
+            json!({
+                "key": "flag",
+                "version": 2,
+                "on": true,
+                "targets": [{"contextKind": "user", "values": ["bob"], "variation": 1}],
+                "contextTargets": [],
+                "rules": [{
+                    "id": "rule",
+                    "clauses": [{
+                        "contextKind": "user",
+                        "attribute": "name",
+                        "op": "in",
+                        "values": ["Alice"]
+                    }],
+                    "variation": 1,
+                    "trackEvents": false
+                }],
+                "prerequisites": [{"key": "prereq", "variation": 0}],
+                "fallthrough": {"variation": 1},
+                "offVariation": 0,
+                "variations": [false, true],
+                "clientSideAvailability": {
+                    "usingMobileKey": false,
+                    "usingEnvironmentId": true
+                },
+                "salt": "salty",
+                "trackEvents": false,
+                "trackEventsFallthrough": false,
+                "debugEventsUntilDate": null
+            })
+        );
+    }
+
+    #[test]
+    fn built_flag_can_be_evaluated() {
+        let flag = boolean_flag()
+            .on(true)
+            .add_context_target(Kind::from("org"), 0, vec!["acme"])
+            .add_context_target(Kind::user(), 0, vec!["alice"])
+            .build()
+            .unwrap();
+        let store = TestStore::new();
+
+        let alice = ContextBuilder::new("alice").build().unwrap();
+        let detail = evaluate(&store, &flag, &alice, None);
+        assert_eq!(Some(0), detail.variation_index);
+        assert_eq!(Reason::TargetMatch, detail.reason);
+
+        let acme = ContextBuilder::new("acme").kind("org").build().unwrap();
+        assert_eq!(
+            Some(0),
+            evaluate(&store, &flag, &acme, None).variation_index
+        );
+
+        let bob = ContextBuilder::new("bob").build().unwrap();
+        assert_eq!(Some(1), evaluate(&store, &flag, &bob, None).variation_index);
+    }
+
+    #[test]
+    fn built_segment_match_clause_can_be_evaluated() {
+        let store = TestStore::new();
+        let rule = FlagRuleBuilder::new("rule")
+            .add_clause(
+                ClauseBuilder::segment_match(vec!["segment"])
+                    .build()
+                    .unwrap(),
+            )
+            .variation(0)
+            .build()
+            .unwrap();
+        let flag = boolean_flag().on(true).add_rule(rule).build().unwrap();
+        assert!(store.segment("segment").is_some());
+
+        let alice = ContextBuilder::new("alice").build().unwrap();
+        assert_eq!(
+            Some(0),
+            evaluate(&store, &flag, &alice, None).variation_index
+        );
+    }
+
+    #[test_case(|b| { b.off_variation(2); }; "off variation")]
+    #[test_case(|b| { b.fallthrough_variation(-1); }; "fallthrough")]
+    #[test_case(|b| { b.add_target(2, vec!["alice"]); }; "target")]
+    #[test_case(|b| { b.add_context_target(Kind::from("org"), 3, vec!["acme"]); }; "context target")]
+    #[test_case(|b| { b.add_rule(FlagRuleBuilder::new("r").variation(5).build().unwrap()); }; "rule")]
+    fn build_rejects_invalid_variation_index(f: fn(&mut FlagBuilder)) {
+        let mut builder = boolean_flag();
+        f(&mut builder);
+        assert!(builder.build().is_err());
+    }
+
+    #[test]
+    fn build_error_describes_validation_problems() {
+        let error = boolean_flag().off_variation(2).build().unwrap_err();
+        assert_eq!(
+            "flag flag: offVariation: variation index 2 is out of range for 2 variations",
+            error
+        );
+    }
+
+    #[test]
+    fn build_allows_rollout_weights_which_do_not_add_up() {
+        let rollout = serde_json::from_value(json!({
+            "rollout": {"variations": [
+                {"variation": 0, "weight": 30000},
+                {"variation": 1, "weight": 60000}
+            ]}
+        }))
+        .unwrap();
+        assert!(boolean_flag().fallthrough(rollout).build().is_ok());
+    }
+
+    #[test]
+    fn build_requires_off_variation_and_fallthrough_when_flag_has_variations() {
+        assert!(FlagBuilder::new("flag")
+            .boolean_variations()
+            .fallthrough_variation(0)
+            .build()
+            .is_err());
+        assert!(FlagBuilder::new("flag")
+            .boolean_variations()
+            .off_variation(0)
+            .build()
+            .is_err());
+        assert!(FlagBuilder::new("flag").build().is_ok());
+        assert!(FlagBuilder::new("").build().is_err());
+    }
+
+    #[test]
+    fn rule_requires_variation_or_rollout() {
+        assert!(FlagRuleBuilder::new("rule").build().is_err());
+    }
+
+    #[test]
+    fn clause_requires_valid_attribute_unless_segment_match() {
+        assert!(ClauseBuilder::new("/", Op::In).build().is_err());
+        assert!(ClauseBuilder::segment_match(vec!["segment"])
+            .build()
+            .is_ok());
+    }
+}
@@ -0,0 +1,424 @@
+use std::collections::{BTreeSet, HashMap};
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use crate::dependency::{DependencyIndex, DependencyKey};
+use crate::flag::Flag;
+use crate::segment::Segment;
+use crate::store::Store;
+use crate::Versioned;
+
+/// StorageItem is an entry in an [InMemoryStore]: either a flag or segment, or a record that one
+/// was deleted.
+#[derive(Clone, Debug)]
+pub enum StorageItem<T> {
+    /// A flag or segment which is present in the store.
+    Item(Arc<T>),
+    /// A placeholder for a deleted flag or segment, retaining the version at which it was deleted
+    /// so that older updates received later are ignored.
+    Tombstone(u64),
+}
+
+impl<T: Versioned> Versioned for StorageItem<T> {
+    fn version(&self) -> u64 {
+        match self {
+            StorageItem::Item(item) => item.version(),
+            StorageItem::Tombstone(version) => *version,
+        }
+    }
+}
+
+impl<T> StorageItem<T> {
+    fn item(&self) -> Option<&Arc<T>> {
+        match self {
+            StorageItem::Item(item) => Some(item),
+            StorageItem::Tombstone(_) => None,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Data {
+    flags: HashMap<String, StorageItem<Flag>>,
+    segments: HashMap<String, StorageItem<Segment>>,
+    index: DependencyIndex,
+    initialized: bool,
+}
+
+/// InMemoryStore is a [Store] which holds flags and segments in memory, and can be shared between
+/// threads.
+///
+/// The store is populated with a full data set by [InMemoryStore::init], and kept up to date with
+/// individual changes by the upsert and delete methods. Changes are versioned: an update is only
+/// applied if its version is greater than that of the flag or segment already held under the same
+/// key, including one which has been deleted. This means that updates which arrive out of order
+/// cannot replace newer data.
+///
+/// The store also keeps a [DependencyIndex] up to date as flags and segments change, so that
+/// [InMemoryStore::dependents] can report what would be affected by changing or deleting an item.
+#[derive(Default)]
+pub struct InMemoryStore {
+    data: RwLock<Data>,
+}
+
+impl InMemoryStore {
+    /// Create an empty store, which is not yet initialized.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replace the entire contents of the store with the provided flags and segments, and mark
+    /// the store as initialized.
+    ///
+    /// Any tombstones for deleted items are discarded, since the new data set is complete.
+    pub fn init(
+        &self,
+        flags: impl IntoIterator<Item = Flag>,
+        segments: impl IntoIterator<Item = Segment>,
+    ) {
+        let mut index = DependencyIndex::default();
+        let flags = flags
+            .into_iter()
+            .map(|flag| {
+                index.update_flag(&flag);
+                (flag.key.clone(), StorageItem::Item(Arc::new(flag)))
+            })
+            .collect();
+        let segments = segments
+            .into_iter()
+            .map(|segment| {
+                index.update_segment(&segment);
+                (segment.key.clone(), StorageItem::Item(Arc::new(segment)))
+            })
+            .collect();
+
+        *self.write() = Data {
+            flags,
+            segments,
+            index,
+            initialized: true,
+        };
+    }
+
+    /// Returns true if [InMemoryStore::init] has been called.
+    pub fn is_initialized(&self) -> bool {
+        self.read().initialized
+    }
+
+    /// Add or replace the flag with the same key as `flag`.
+    ///
+    /// Returns false, leaving the store unchanged, if the store already holds a flag or tombstone
+    /// for that key whose version is greater than or equal to the flag's version.
+    pub fn upsert_flag(&self, flag: Flag) -> bool {
+        let key = flag.key.clone();
+        let flag = Arc::new(flag);
+        let mut data = self.write();
+        let updated = upsert(&mut data.flags, key, StorageItem::Item(flag.clone()));
+        if updated {
+            data.index.update_flag(&flag);
+        }
+        updated
+    }
+
+    /// Add or replace the segment with the same key as `segment`.
+    ///
+    /// Returns false, leaving the store unchanged, if the store already holds a segment or
+    /// tombstone for that key whose version is greater than or equal to the segment's version.
+    pub fn upsert_segment(&self, segment: Segment) -> bool {
+        let key = segment.key.clone();
+        let segment = Arc::new(segment);
+        let mut data = self.write();
+        let updated = upsert(&mut data.segments, key, StorageItem::Item(segment.clone()));
+        if updated {
+            data.index.update_segment(&segment);
+        }
+        updated
+    }
+
+    /// Delete the flag with key `key`, leaving a tombstone with the given version.
+    ///
+    /// Returns false, leaving the store unchanged, if the store already holds a flag or tombstone
+    /// for that key whose version is greater than or equal to `version`.
+    pub fn delete_flag(&self, key: &str, version: u64) -> bool {
+        let mut data = self.write();
+        let updated = upsert(
+            &mut data.flags,
+            key.to_string(),
+            StorageItem::Tombstone(version),
+        );
+        if updated {
+            data.index.remove(&DependencyKey::Flag(key.to_string()));
+        }
+        updated
+    }
+
+    /// Delete the segment with key `key`, leaving a tombstone with the given version.
+    ///
+    /// Returns false, leaving the store unchanged, if the store already holds a segment or
+    /// tombstone for that key whose version is greater than or equal to `version`.
+    pub fn delete_segment(&self, key: &str, version: u64) -> bool {
+        let mut data = self.write();
+        let updated = upsert(
+            &mut data.segments,
+            key.to_string(),
+            StorageItem::Tombstone(version),
+        );
+        if updated {
+            data.index.remove(&DependencyKey::Segment(key.to_string()));
+        }
+        updated
+    }
+
+    /// Retrieve the entry for the flag with key `flag_key`, including a tombstone if the flag was
+    /// deleted.
+    pub fn flag_item(&self, flag_key: &str) -> Option<StorageItem<Flag>> {
+        self.read().flags.get(flag_key).cloned()
+    }
+
+    /// Retrieve the entry for the segment with key `segment_key`, including a tombstone if the
+    /// segment was deleted.
+    pub fn segment_item(&self, segment_key: &str) -> Option<StorageItem<Segment>> {
+        self.read().segments.get(segment_key).cloned()
+    }
+
+    /// Every flag and segment which depends on `key`, directly or indirectly, as a prerequisite or
+    /// through a segment match clause. See [DependencyIndex::dependents].
+    pub fn dependents(&self, key: &DependencyKey) -> BTreeSet<DependencyKey> {
+        self.read().index.dependents(key)
+    }
+
+    // Nothing done while holding the lock can panic, since changing an item and the index only
+    // inserts into and removes from maps, so a poisoned lock is safe to keep using.
+    fn read(&self) -> RwLockReadGuard<'_, Data> {
+        self.data.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, Data> {
+        self.data.write().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+fn upsert<T: Versioned>(
+    items: &mut HashMap<String, StorageItem<T>>,
+    key: String,
+    item: StorageItem<T>,
+) -> bool {
+    if let Some(existing) = items.get(&key) {
+        if existing.is_greater_than_or_equal(item.version()) {
+            return false;
+        }
+    }
+
+    items.insert(key, item);
+    true
+}
+
+impl Store for InMemoryStore {
+    fn flag(&self, flag_key: &str) -> Option<Flag> {
+        self.shared_flag(flag_key).map(|flag| flag.as_ref().clone())
+    }
+
+    fn segment(&self, segment_key: &str) -> Option<Segment> {
+        self.shared_segment(segment_key)
+            .map(|segment| segment.as_ref().clone())
+    }
+
+    fn is_initialized(&self) -> bool {
+        self.read().initialized
+    }
+
+    fn flag_keys(&self) -> Option<Vec<String>> {
+        let keys = self
+            .read()
+            .flags
+            .iter()
+            .filter(|(_, item)| item.item().is_some())
+            .map(|(key, _)| key.clone())
+            .collect();
+        Some(keys)
+    }
+
+    fn segment_keys(&self) -> Option<Vec<String>> {
+        let keys = self
+            .read()
+            .segments
+            .iter()
+            .filter(|(_, item)| item.item().is_some())
+            .map(|(key, _)| key.clone())
+            .collect();
+        Some(keys)
+    }
+
+    fn shared_flag(&self, flag_key: &str) -> Option<Arc<Flag>> {
+        self.read().flags.get(flag_key)?.item().cloned()
+    }
+
+    fn shared_segment(&self, segment_key: &str) -> Option<Arc<Segment>> {
+        self.read().segments.get(segment_key)?.item().cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eval::evaluate;
+    use crate::{
+        ClauseBuilder, ContextBuilder, FlagBuilder, FlagRuleBuilder, FlagValue, SegmentBuilder,
+    };
+    use std::thread;
+
+    fn flag(key: &str, version: u64) -> Flag {
+        FlagBuilder::new(key)
+            .version(version)
+            .boolean_variations()
+            .off_variation(0)
+            .fallthrough_variation(1)
+            .on(true)
+            .build()
+            .unwrap()
+    }
+
+    fn segment(key: &str, version: u64) -> Segment {
+        SegmentBuilder::new(key).version(version).build().unwrap()
+    }
+
+    #[test]
+    fn init_replaces_all_data() {
+        let store = InMemoryStore::new();
+        assert!(!store.is_initialized());
+
+        store.upsert_flag(flag("old", 1));
+        store.init(vec![flag("a", 1), flag("b", 1)], vec![segment("s", 1)]);
+
+        assert!(store.is_initialized());
+        let mut keys = store.flag_keys().unwrap();
+        keys.sort();
+        assert_eq!(vec!["a", "b"], keys);
+        assert!(store.flag("old").is_none());
+        assert!(store.segment("s").is_some());
+    }
+
+    #[test]
+    fn upsert_only_applies_newer_versions() {
+        let store = InMemoryStore::new();
+        assert!(store.upsert_flag(flag("a", 2)));
+        assert!(!store.upsert_flag(flag("a", 1)));
+        assert!(!store.upsert_flag(flag("a", 2)));
+        assert_eq!(2, store.flag("a").unwrap().version);
+
+        assert!(store.upsert_flag(flag("a", 3)));
+        assert_eq!(3, store.flag("a").unwrap().version);
+
+        assert!(store.upsert_segment(segment("s", 5)));
+        assert!(!store.upsert_segment(segment("s", 4)));
+        assert_eq!(5, store.segment("s").unwrap().version);
+    }
+
+    #[test]
+    fn delete_leaves_tombstone_which_blocks_older_updates() {
+        let store = InMemoryStore::new();
+        store.init(vec![flag("a", 1)], vec![segment("s", 1)]);
+
+        assert!(store.delete_flag("a", 3));
+        assert!(store.flag("a").is_none());
+        assert_eq!(Some(vec![]), store.flag_keys());
+        assert!(matches!(
+            store.flag_item("a"),
+            Some(StorageItem::Tombstone(3))
+        ));
+
+        assert!(!store.upsert_flag(flag("a", 2)));
+        assert!(store.flag("a").is_none());
+        assert!(store.upsert_flag(flag("a", 4)));
+        assert!(store.flag("a").is_some());
+
+        assert!(!store.delete_segment("s", 1));
+        assert!(store.delete_segment("s", 2));
+        assert!(store.segment("s").is_none());
+        assert!(matches!(
+            store.segment_item("s"),
+            Some(StorageItem::Tombstone(2))
+        ));
+    }
+
+    #[test]
+    fn delete_before_upsert_is_remembered() {
+        let store = InMemoryStore::new();
+        assert!(store.delete_flag("a", 2));
+        assert!(!store.upsert_flag(flag("a", 1)));
+        assert!(store.flag("a").is_none());
+    }
+
+    #[test]
+    fn dependents_are_kept_up_to_date() {
+        let rule = |segment_key: &str| {
+            FlagRuleBuilder::new("rule")
+                .add_clause(
+                    ClauseBuilder::segment_match(vec![segment_key])
+                        .build()
+                        .unwrap(),
+                )
+                .variation(1)
+                .build()
+                .unwrap()
+        };
+        let mut uses_segment = flag("a", 1);
+        uses_segment.rules.push(rule("s"));
+        let mut has_prereq = FlagBuilder::new("b");
+        has_prereq
+            .boolean_variations()
+            .off_variation(0)
+            .fallthrough_variation(1)
+            .add_prerequisite("a", 1);
+
+        let store = InMemoryStore::new();
+        store.init(vec![uses_segment], vec![segment("s", 1)]);
+        let segment_key = DependencyKey::Segment("s".to_string());
+        let flag_key = |key: &str| DependencyKey::Flag(key.to_string());
+        assert_eq!(
+            BTreeSet::from([flag_key("a")]),
+            store.dependents(&segment_key)
+        );
+
+        store.upsert_flag(has_prereq.build().unwrap());
+        assert_eq!(
+            BTreeSet::from([flag_key("a"), flag_key("b")]),
+            store.dependents(&segment_key)
+        );
+
+        assert!(!store.upsert_flag(flag("a", 1)));
+        assert_eq!(2, store.dependents(&segment_key).len());
+        assert!(store.upsert_flag(flag("a", 2)));
+        assert!(store.dependents(&segment_key).is_empty());
+        assert_eq!(
+            BTreeSet::from([flag_key("b")]),
+            store.dependents(&flag_key("a"))
+        );
+
+        assert!(store.delete_flag("b", 2));
+        assert!(store.dependents(&flag_key("a")).is_empty());
+    }
+
+    #[test]
+    fn store_can_be_shared_between_threads() {
+        let store = Arc::new(InMemoryStore::new());
+        store.init(vec![flag("a", 0)], vec![]);
+
+        let writers: Vec<_> = (1..=8)
+            .map(|version| {
+                let store = store.clone();
+                thread::spawn(move || {
+                    store.upsert_flag(flag("a", version));
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        assert_eq!(8, store.flag("a").unwrap().version);
+
+        let context = ContextBuilder::new("alice").build().unwrap();
+        let flag = store.shared_flag("a").unwrap();
+        let detail = evaluate(store.as_ref(), &flag, &context, None);
+        assert_eq!(Some(&FlagValue::Bool(true)), detail.value);
+    }
+}
@@ -8,30 +8,62 @@
 #![deny(missing_docs)]
 
 mod attribute_value;
+mod big_segment;
+mod bootstrap;
+mod clock;
+mod context_deduplicator;
 mod contexts;
+mod dependency;
 mod eval;
+mod events;
 mod flag;
+mod flag_builder;
 mod flag_value;
+mod in_memory_store;
+mod operator;
+mod payload;
 mod rule;
+mod secure_mode;
 mod segment;
+mod segment_builder;
 mod store;
+mod summary;
 mod test_common;
+mod trace;
 mod util;
+mod validation;
 mod variation;
 
 pub use attribute_value::AttributeValue;
+pub use big_segment::*;
+pub use bootstrap::*;
+pub use clock::*;
+pub use context_deduplicator::*;
 pub use contexts::attribute_reference::Reference;
 pub use contexts::context::{Context, ContextAttributes, Kind};
 pub use contexts::context_builder::{ContextBuilder, MultiContextBuilder};
+pub use dependency::*;
 pub use eval::*;
+pub use events::*;
 pub use flag::*;
+pub use flag_builder::*;
 pub use flag_value::*;
+pub use in_memory_store::*;
+pub use operator::*;
+pub use payload::*;
 pub use rule::*;
+pub use secure_mode::*;
 pub use segment::*;
+pub use segment_builder::*;
 pub use store::*;
+pub use summary::*;
+pub use trace::*;
+pub use validation::{Diagnostic, Problem};
 pub use variation::*;
 
+// This only gathers the test generators of other modules, so items may follow it.
 #[cfg(test)]
+#[allow(clippy::items_after_test_module)]
 pub(crate) mod proptest_generators {
     pub(crate) use crate::contexts::attribute_reference::proptest_generators::*;
     pub(crate) use crate::contexts::context::proptest_generators::*;
@@ -0,0 +1,38 @@
+use std::collections::HashMap;
+
+use crate::attribute_value::AttributeValue;
+
+/// CustomOperator implements a clause operation which is not built into this crate.
+///
+/// Like the built-in operations, a custom operation is applied to each pair of a context value and
+/// a clause value, and the clause matches if any pair matches. Negation is applied afterwards.
+pub trait CustomOperator {
+    /// Returns true if `context_value` matches `clause_value`.
+    fn matches(&self, context_value: &AttributeValue, clause_value: &AttributeValue) -> bool;
+}
+
+impl<F> CustomOperator for F
+where
+    F: Fn(&AttributeValue, &AttributeValue) -> bool,
+{
+    fn matches(&self, context_value: &AttributeValue, clause_value: &AttributeValue) -> bool {
+        self(context_value, clause_value)
+    }
+}
+
+/// OperatorRegistry supplies [CustomOperator]s by name, for clauses whose operation is not
+/// recognized by this crate and so was deserialized as [crate::Op::Unknown].
+///
+/// A registry is supplied to [crate::evaluate_with_options] through [crate::EvaluationOptions].
+/// Without one, or if the registry has no operator with the clause's
+/// [crate::Clause::op_name], the clause never matches.
+pub trait OperatorRegistry {
+    /// Retrieve the operator with the given name, if there is one.
+    fn operator(&self, name: &str) -> Option<&dyn CustomOperator>;
+}
+
+impl OperatorRegistry for HashMap<String, Box<dyn CustomOperator>> {
+    fn operator(&self, name: &str) -> Option<&dyn CustomOperator> {
+        self.get(name).map(|operator| operator.as_ref())
+    }
+}
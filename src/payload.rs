@@ -0,0 +1,323 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::flag::Flag;
+use crate::in_memory_store::InMemoryStore;
+use crate::segment::Segment;
+
+const FLAGS_PATH_PREFIX: &str = "/flags/";
+const SEGMENTS_PATH_PREFIX: &str = "/segments/";
+
+/// AllData is a complete set of flags and segments, keyed by their keys.
+///
+/// This is the body of a polling `latest-all` response, and the `data` of a streaming `put` event.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct AllData {
+    /// Every flag in the environment.
+    #[serde(default)]
+    pub flags: HashMap<String, Flag>,
+    /// Every segment in the environment.
+    #[serde(default)]
+    pub segments: HashMap<String, Segment>,
+}
+
+impl AllData {
+    /// Parse the body of a polling `latest-all` response.
+    pub fn from_polling_response(body: &str) -> Result<Self, String> {
+        serde_json::from_str(body).map_err(|e| format!("malformed polling response: {}", e))
+    }
+}
+
+/// DataUpdate is a change to the flags and segments held in a store, received from LaunchDarkly.
+#[derive(Clone, Debug)]
+pub enum DataUpdate {
+    /// Replace all data in the store.
+    Init(AllData),
+    /// Add or replace a single flag.
+    UpsertFlag(Flag),
+    /// Add or replace a single segment.
+    UpsertSegment(Segment),
+    /// Delete a single flag.
+    DeleteFlag {
+        /// The key of the deleted flag.
+        key: String,
+        /// The version of the flag at which it was deleted.
+        version: u64,
+    },
+    /// Delete a single segment.
+    DeleteSegment {
+        /// The key of the deleted segment.
+        key: String,
+        /// The version of the segment at which it was deleted.
+        version: u64,
+    },
+}
+
+#[derive(Deserialize)]
+struct PutEvent {
+    #[serde(default)]
+    path: Option<String>,
+    data: AllData,
+}
+
+#[derive(Deserialize)]
+struct PatchEvent {
+    path: String,
+    data: serde_json::Value,
+}
+
+#[derive(Deserialize)]
+struct DeleteEvent {
+    path: String,
+    version: u64,
+}
+
+// The kind of item identified by the path of a patch or delete event.
+enum ItemPath<'a> {
+    Flag(&'a str),
+    Segment(&'a str),
+}
+
+impl<'a> ItemPath<'a> {
+    fn parse(path: &'a str) -> Option<Self> {
+        if let Some(key) = path.strip_prefix(FLAGS_PATH_PREFIX) {
+            Some(ItemPath::Flag(key))
+        } else {
+            path.strip_prefix(SEGMENTS_PATH_PREFIX)
+                .map(ItemPath::Segment)
+        }
+    }
+}
+
+impl DataUpdate {
+    /// Parse the data of a streaming event with the given event name.
+    ///
+    /// Returns None if the event is not one which changes flags or segments, or if it refers to a
+    /// kind of data which this crate does not know about; such events should be ignored. An error
+    /// is returned if the event is malformed.
+    pub fn from_stream_event(event_name: &str, data: &str) -> Result<Option<Self>, String> {
+        match event_name {
+            "put" => Self::from_put(data).map(Some),
+            "patch" => Self::from_patch(data),
+            "delete" => Self::from_delete(data),
+            _ => Ok(None),
+        }
+    }
+
+    /// Parse the data of a streaming `put` event, which replaces all data.
+    pub fn from_put(data: &str) -> Result<Self, String> {
+        let event: PutEvent =
+            serde_json::from_str(data).map_err(|e| format!("malformed put event: {}", e))?;
+        match event.path.as_deref() {
+            None | Some("/") | Some("") => Ok(DataUpdate::Init(event.data)),
+            Some(path) => Err(format!("unsupported put event path {}", path)),
+        }
+    }
+
+    /// Parse the data of a streaming `patch` event, which adds or replaces a single flag or
+    /// segment.
+    ///
+    /// Returns None if the event's path does not refer to a flag or segment.
+    pub fn from_patch(data: &str) -> Result<Option<Self>, String> {
+        let event: PatchEvent =
+            serde_json::from_str(data).map_err(|e| format!("malformed patch event: {}", e))?;
+
+        let update = match ItemPath::parse(&event.path) {
+            Some(ItemPath::Flag(key)) => {
+                let mut flag: Flag = serde_json::from_value(event.data)
+                    .map_err(|e| format!("malformed flag {} in patch event: {}", key, e))?;
+                flag.key = key.to_string();
+                DataUpdate::UpsertFlag(flag)
+            }
+            Some(ItemPath::Segment(key)) => {
+                let mut segment: Segment = serde_json::from_value(event.data)
+                    .map_err(|e| format!("malformed segment {} in patch event: {}", key, e))?;
+                segment.key = key.to_string();
+                DataUpdate::UpsertSegment(segment)
+            }
+            None => return Ok(None),
+        };
+
+        Ok(Some(update))
+    }
+
+    /// Parse the data of a streaming `delete` event, which deletes a single flag or segment.
+    ///
+    /// Returns None if the event's path does not refer to a flag or segment.
+    pub fn from_delete(data: &str) -> Result<Option<Self>, String> {
+        let event: DeleteEvent =
+            serde_json::from_str(data).map_err(|e| format!("malformed delete event: {}", e))?;
+
+        let update = match ItemPath::parse(&event.path) {
+            Some(ItemPath::Flag(key)) => DataUpdate::DeleteFlag {
+                key: key.to_string(),
+                version: event.version,
+            },
+            Some(ItemPath::Segment(key)) => DataUpdate::DeleteSegment {
+                key: key.to_string(),
+                version: event.version,
+            },
+            None => return Ok(None),
+        };
+
+        Ok(Some(update))
+    }
+
+    /// Apply this update to the store.
+    ///
+    /// Returns false if the update was ignored because the store already holds a newer version of
+    /// the flag or segment. See [InMemoryStore] for how versions are compared.
+    pub fn apply(self, store: &InMemoryStore) -> bool {
+        match self {
+            DataUpdate::Init(data) => {
+                store.init(data.flags.into_values(), data.segments.into_values());
+                true
+            }
+            DataUpdate::UpsertFlag(flag) => store.upsert_flag(flag),
+            DataUpdate::UpsertSegment(segment) => store.upsert_segment(segment),
+            DataUpdate::DeleteFlag { key, version } => store.delete_flag(&key, version),
+            DataUpdate::DeleteSegment { key, version } => store.delete_segment(&key, version),
+        }
+    }
+}
+
+impl From<AllData> for DataUpdate {
+    fn from(data: AllData) -> Self {
+        DataUpdate::Init(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::Store;
+    use test_case::test_case;
+
+    const FLAG_JSON: &str = r#"{
+        "key": "flag",
+        "version": 2,
+        "on": true,
+        "targets": [],
+        "rules": [],
+        "prerequisites": [],
+        "fallthrough": {"variation": 0},
+        "offVariation": 0,
+        "variations": [true],
+        "salt": "salty"
+    }"#;
+
+    const SEGMENT_JSON: &str = r#"{
+        "key": "segment",
+        "version": 3,
+        "included": ["alice"],
+        "excluded": [],
+        "rules": [],
+        "salt": "salty"
+    }"#;
+
+    fn put_event() -> String {
+        format!(
+            r#"{{"path": "/", "data": {{"flags": {{"flag": {}}}, "segments": {{"segment": {}}}}}}}"#,
+            FLAG_JSON, SEGMENT_JSON
+        )
+    }
+
+    #[test]
+    fn parses_put_event() {
+        let update = DataUpdate::from_stream_event("put", &put_event())
+            .unwrap()
+            .unwrap();
+        let store = InMemoryStore::new();
+        assert!(update.apply(&store));
+
+        assert!(store.is_initialized());
+        assert_eq!(2, store.flag("flag").unwrap().version);
+        assert_eq!(3, store.segment("segment").unwrap().version);
+    }
+
+    #[test]
+    fn put_event_path_is_optional() {
+        let data = format!(r#"{{"data": {{"flags": {{"flag": {}}}}}}}"#, FLAG_JSON);
+        match DataUpdate::from_put(&data).unwrap() {
+            DataUpdate::Init(data) => {
+                assert_eq!(1, data.flags.len());
+                assert!(data.segments.is_empty());
+            }
+            other => panic!("unexpected update {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parses_polling_response() {
+        let body = format!(
+            r#"{{"flags": {{"flag": {}}}, "segments": {{}}}}"#,
+            FLAG_JSON
+        );
+        let data = AllData::from_polling_response(&body).unwrap();
+        let store = InMemoryStore::new();
+        DataUpdate::from(data).apply(&store);
+        assert_eq!(Some(vec!["flag".to_string()]), store.flag_keys());
+    }
+
+    #[test]
+    fn patch_events_are_applied_with_version_checks() {
+        let store = InMemoryStore::new();
+        DataUpdate::from_put(&put_event()).unwrap().apply(&store);
+
+        let newer = FLAG_JSON.replace(r#""version": 2"#, r#""version": 5"#);
+        let patch = format!(r#"{{"path": "/flags/flag", "data": {}}}"#, newer);
+        let update = DataUpdate::from_stream_event("patch", &patch)
+            .unwrap()
+            .unwrap();
+        assert!(update.apply(&store));
+        assert_eq!(5, store.flag("flag").unwrap().version);
+
+        let older = format!(r#"{{"path": "/flags/flag", "data": {}}}"#, FLAG_JSON);
+        let update = DataUpdate::from_patch(&older).unwrap().unwrap();
+        assert!(!update.apply(&store));
+        assert_eq!(5, store.flag("flag").unwrap().version);
+
+        let patch = format!(r#"{{"path": "/segments/other", "data": {}}}"#, SEGMENT_JSON);
+        match DataUpdate::from_patch(&patch).unwrap().unwrap() {
+            DataUpdate::UpsertSegment(segment) => assert_eq!("other", segment.key),
+            other => panic!("unexpected update {:?}", other),
+        }
+    }
+
+    #[test]
+    fn delete_events_are_applied_with_version_checks() {
+        let store = InMemoryStore::new();
+        DataUpdate::from_put(&put_event()).unwrap().apply(&store);
+
+        let update =
+            DataUpdate::from_stream_event("delete", r#"{"path": "/flags/flag", "version": 1}"#)
+                .unwrap()
+                .unwrap();
+        assert!(!update.apply(&store));
+        assert!(store.flag("flag").is_some());
+
+        let update = DataUpdate::from_delete(r#"{"path": "/segments/segment", "version": 4}"#)
+            .unwrap()
+            .unwrap();
+        assert!(update.apply(&store));
+        assert!(store.segment("segment").is_none());
+    }
+
+    #[test_case("patch", r#"{"path": "/configurationOverrides/x", "data": {}}"#)]
+    #[test_case("delete", r#"{"path": "/metrics/x", "version": 1}"#)]
+    #[test_case("reconnect", "")]
+    fn unknown_events_and_paths_are_ignored(event_name: &str, data: &str) {
+        assert!(DataUpdate::from_stream_event(event_name, data)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test_case("put", "{}")]
+    #[test_case("put", r#"{"path": "/flags", "data": {}}"#)]
+    #[test_case("patch", r#"{"path": "/flags/flag", "data": {"key": "flag"}}"#)]
+    #[test_case("delete", r#"{"path": "/flags/flag"}"#)]
+    fn malformed_events_are_errors(event_name: &str, data: &str) {
+        assert!(DataUpdate::from_stream_event(event_name, data).is_err());
+    }
+}
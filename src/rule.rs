@@ -1,14 +1,24 @@
 use crate::attribute_value::AttributeValue;
 use crate::contexts::attribute_reference::AttributeName;
 use crate::contexts::context::Kind;
+use crate::eval::EvaluationError;
+use crate::operator::CustomOperator;
 use crate::store::Store;
+use crate::trace::TraceEvent;
+use crate::util::{parse_duration, IpNetwork, SemVerRange, TimeOfDayRange};
 use crate::variation::VariationOrRollout;
-use crate::{util, Context, EvaluationStack, Reference};
-use chrono::{self, Utc};
+use crate::{util, Context, EvaluationOptions, EvaluationStack, Reference};
+use caseless::Caseless;
+use chrono::{self, Datelike, Duration, Utc, Weekday};
 use log::{error, warn};
 use regex::Regex;
+use serde::de::value::{self, StrDeserializer};
+use serde::de::IntoDeserializer;
 use serde::{Deserialize, Serialize};
 use serde_with::skip_serializing_none;
+use std::collections::HashSet;
+use std::net::IpAddr;
+use unicode_normalization::UnicodeNormalization;
 use util::is_false;
 
 /// Clause describes an individual clause within a [crate::FlagRule] or `SegmentRule`.
@@ -32,9 +42,138 @@ pub struct Clause {
     #[serde(skip_serializing_if = "is_false")]
     negate: bool,
     // The test operation.
+    #[serde(skip)]
     op: Op,
+    // The name of the test operation, as given in the JSON model. This is kept so that an
+    // operation which is not recognized can be passed to an OperatorRegistry, and serialized again
+    // unchanged.
+    #[serde(rename = "op")]
+    op_name: String,
     // The values to test against.
     values: Vec<AttributeValue>,
+    // The values parsed according to op, built when the clause is constructed.
+    #[serde(skip)]
+    prepared: PreparedValues,
+}
+
+// Clause values which have been parsed ahead of time, so that regexes, dates and versions do not
+// need to be parsed again every time a clause is evaluated.
+#[derive(Clone, Debug, Default)]
+struct PreparedValues {
+    // One entry for each of the clause's values, in the same order.
+    values: Vec<PreparedValue>,
+    // For the in operators, the clause's string values, so that a string can be checked without
+    // comparing it to every value. These are case-folded for the case-insensitive operator.
+    strings: Option<HashSet<String>>,
+}
+
+// PreparedValues are derived entirely from the other fields of a clause, so they need not be
+// compared.
+impl PartialEq for PreparedValues {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+#[derive(Clone, Debug)]
+enum PreparedValue {
+    // The operator compares the value as it is.
+    Raw,
+    // The value cannot be parsed as the operator requires, so it can never match.
+    Invalid,
+    Regex(Regex),
+    DateTime(chrono::DateTime<Utc>),
+    SemVer(semver::Version),
+    SemVerRange(SemVerRange),
+    Duration(Duration),
+    TimeOfDay(TimeOfDayRange),
+    Weekday(Weekday),
+    Network(IpNetwork),
+    // A string with its case folded, for the case-insensitive operators.
+    Folded(String),
+}
+
+impl PreparedValues {
+    fn new(op: Op, values: &[AttributeValue]) -> Self {
+        let values_prepared = values
+            .iter()
+            .map(|value| PreparedValue::new(op, value))
+            .collect();
+        let strings = match op {
+            Op::In => Some(
+                values
+                    .iter()
+                    .filter_map(|value| value.as_str().map(String::from))
+                    .collect(),
+            ),
+            Op::InIgnoreCase => Some(
+                values
+                    .iter()
+                    .filter_map(|value| value.as_str().map(fold_case))
+                    .collect(),
+            ),
+            _ => None,
+        };
+
+        Self {
+            values: values_prepared,
+            strings,
+        }
+    }
+}
+
+impl PreparedValue {
+    fn new(op: Op, value: &AttributeValue) -> Self {
+        let prepared = match op {
+            Op::Matches => value
+                .as_str()
+                .and_then(|pattern| match Regex::new(pattern) {
+                    Ok(re) => Some(PreparedValue::Regex(re)),
+                    Err(e) => {
+                        warn!("Invalid regex for 'matches' operator ({}): {}", e, pattern);
+                        None
+                    }
+                }),
+            Op::Before | Op::After => value.to_datetime().map(PreparedValue::DateTime),
+            Op::OlderThan | Op::NewerThan => duration(value).map(PreparedValue::Duration),
+            Op::TimeOfDayBetween => value
+                .as_str()
+                .and_then(TimeOfDayRange::parse)
+                .map(PreparedValue::TimeOfDay),
+            Op::DayOfWeekIn => value
+                .as_str()
+                .and_then(|day| day.trim().parse().ok())
+                .map(PreparedValue::Weekday),
+            Op::SemVerEqual | Op::SemVerGreaterThan | Op::SemVerLessThan => {
+                value.as_semver().map(PreparedValue::SemVer)
+            }
+            Op::SemVerInRange => value
+                .as_str()
+                .and_then(SemVerRange::parse)
+                .map(PreparedValue::SemVerRange),
+            Op::IpInRange => value
+                .as_str()
+                .and_then(IpNetwork::parse)
+                .map(PreparedValue::Network),
+            Op::ArrayLengthEqual | Op::ArrayLengthLessThan | Op::ArrayLengthGreaterThan => {
+                match value {
+                    AttributeValue::Number(_) => return PreparedValue::Raw,
+                    _ => None,
+                }
+            }
+            // Values other than strings are compared as they are.
+            Op::InIgnoreCase
+            | Op::StartsWithIgnoreCase
+            | Op::EndsWithIgnoreCase
+            | Op::ContainsIgnoreCase => match value.as_str() {
+                Some(s) => Some(PreparedValue::Folded(fold_case(s))),
+                None => return PreparedValue::Raw,
+            },
+            _ => return PreparedValue::Raw,
+        };
+
+        prepared.unwrap_or(PreparedValue::Invalid)
+    }
 }
 
 #[derive(Debug, Deserialize, PartialEq)]
@@ -44,7 +183,7 @@ struct ClauseWithKind {
     attribute: Reference,
     #[serde(default)]
     negate: bool,
-    op: Op,
+    op: String,
     values: Vec<AttributeValue>,
 }
 
@@ -54,7 +193,7 @@ struct ClauseWithoutKind {
     attribute: AttributeName,
     #[serde(default)]
     negate: bool,
-    op: Op,
+    op: String,
     values: Vec<AttributeValue>,
 }
 
@@ -70,20 +209,20 @@ enum IntermediateClause {
 impl From<IntermediateClause> for Clause {
     fn from(ic: IntermediateClause) -> Self {
         match ic {
-            IntermediateClause::ContextAware(fields) => Self {
-                context_kind: fields.context_kind,
-                attribute: fields.attribute,
-                negate: fields.negate,
-                op: fields.op,
-                values: fields.values,
-            },
-            IntermediateClause::ContextOblivious(fields) => Self {
-                context_kind: Kind::default(),
-                attribute: Reference::from(fields.attribute),
-                negate: fields.negate,
-                op: fields.op,
-                values: fields.values,
-            },
+            IntermediateClause::ContextAware(fields) => Self::with_op_name(
+                fields.context_kind,
+                fields.attribute,
+                fields.negate,
+                fields.op,
+                fields.values,
+            ),
+            IntermediateClause::ContextOblivious(fields) => Self::with_op_name(
+                Kind::default(),
+                Reference::from(fields.attribute),
+                fields.negate,
+                fields.op,
+                fields.values,
+            ),
         }
     }
 }
@@ -109,13 +248,13 @@ pub(crate) mod proptest_generators {
             values in vec(any::<bool>(), 0..5),
             op in any::<Op>()
         ) -> Clause {
-            Clause {
-                context_kind: kind,
-                attribute: reference,
+            Clause::new(
+                kind,
+                reference,
                 negate,
                 op,
-                values: values.iter().map(|&b| AttributeValue::from(b)).collect()
-            }
+                values.iter().map(|&b| AttributeValue::from(b)).collect()
+            )
         }
     }
 }
@@ -132,7 +271,7 @@ pub struct FlagRule {
     /// This is used to populate the id property of [crate::Reason]
     #[serde(default)]
     pub id: String,
-    clauses: Vec<Clause>,
+    pub(crate) clauses: Vec<Clause>,
 
     /// Defines what variation to return if the context matches this rule.
     #[serde(flatten)]
@@ -149,40 +288,334 @@ pub struct FlagRule {
     pub track_events: bool,
 }
 
-#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+/// Op is the test operation performed by a [Clause].
+///
+/// Unless otherwise noted, a clause matches if the operation succeeds for any pair of a context
+/// value and a clause value.
+///
+/// New operations may be added in future versions of this crate, so matches against this enum
+/// must include a wildcard arm.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
 #[cfg_attr(test, derive(proptest_derive::Arbitrary))]
 #[serde(rename_all = "camelCase")]
-enum Op {
+#[non_exhaustive]
+pub enum Op {
+    /// The context value is equal to the clause value.
     In,
+    /// The context value is a string which starts with the clause value.
     StartsWith,
+    /// The context value is a string which ends with the clause value.
     EndsWith,
+    /// The context value is a string which contains the clause value.
     Contains,
+    /// The context value is a string which matches the regular expression in the clause value.
     Matches,
+    /// Like [Op::In], but strings are compared ignoring case. See [Op::StartsWithIgnoreCase] for
+    /// how case is ignored.
+    InIgnoreCase,
+    /// The context value is a string which starts with the clause value, ignoring case.
+    ///
+    /// Strings are compared using Unicode default case folding after canonical normalization, so
+    /// that for example `STRASSE` starts with `straße`, and a precomposed `é` is the same as an `e`
+    /// followed by a combining accent.
+    StartsWithIgnoreCase,
+    /// The context value is a string which ends with the clause value, ignoring case. See
+    /// [Op::StartsWithIgnoreCase] for how case is ignored.
+    EndsWithIgnoreCase,
+    /// The context value is a string which contains the clause value, ignoring case. See
+    /// [Op::StartsWithIgnoreCase] for how case is ignored.
+    ContainsIgnoreCase,
+    /// The context value is a number less than the clause value.
     LessThan,
+    /// The context value is a number less than or equal to the clause value.
     LessThanOrEqual,
+    /// The context value is a number greater than the clause value.
     GreaterThan,
+    /// The context value is a number greater than or equal to the clause value.
     GreaterThanOrEqual,
+    /// The context value is a date, as a Unix millisecond timestamp or RFC3339 string, before the
+    /// clause value.
     Before,
+    /// The context value is a date, as a Unix millisecond timestamp or RFC3339 string, after the
+    /// clause value.
     After,
+    /// The context value is a date, as for [Op::Before], which is more than the duration given by
+    /// the clause value before the time of evaluation. The duration is a number of milliseconds,
+    /// or a string such as `30d` or `1h30m` using the units `w`, `d`, `h`, `m`, `s` and `ms`.
+    ///
+    /// The time of evaluation is given by the [crate::Clock] in [crate::EvaluationOptions].
+    OlderThan,
+    /// The context value is a date, as for [Op::Before], which is less than the duration given by
+    /// the clause value before the time of evaluation. See [Op::OlderThan] for how durations are
+    /// written. A date after the time of evaluation is also newer than any duration.
+    NewerThan,
+    /// The context value is a date, as for [Op::Before], whose time of day in UTC is within the
+    /// range given by the clause value, such as `09:00-17:00`. The start of the range is included
+    /// and the end is not; seconds may also be given, as in `09:00:00-17:00:00`. A range whose end
+    /// is earlier than its start wraps around midnight, and one whose start and end are equal is
+    /// malformed.
+    TimeOfDayBetween,
+    /// The context value is a date, as for [Op::Before], which falls in UTC on the day of the week
+    /// named by the clause value, such as `Monday` or `mon`.
+    DayOfWeekIn,
+    /// The context is a member of any of the segments whose keys are the clause values. The
+    /// clause's attribute is ignored.
     SegmentMatch,
+    /// The context value is a semantic version equal to the clause value.
     SemVerEqual,
+    /// The context value is a semantic version greater than the clause value.
     SemVerGreaterThan,
+    /// The context value is a semantic version less than the clause value.
     SemVerLessThan,
+    /// The context value is a semantic version within the range given by the clause value, an npm
+    /// or Cargo style requirement such as `^2.3`, `>=1.2.0 <2.0.0` or `~1.2 || ^3`. As in npm, a
+    /// version without an operator such as `2.3` means any `2.3.x` version. Context values are
+    /// parsed as loosely as for the other semantic version operators, so `2.3` is `2.3.0`.
+    SemVerInRange,
+    /// The context value is a string containing an IPv4 or IPv6 address within the network given
+    /// by the clause value, in CIDR notation such as `10.0.0.0/8`. A clause value without a prefix
+    /// length matches only that address.
+    IpInRange,
+    /// The context value is an array which contains every one of the clause values.
+    ///
+    /// This and the other array operators apply to the context value as a whole, rather than to
+    /// each of its elements in turn. A context value which is not an array is treated as an array
+    /// containing only that value, and a missing or null value as an empty array.
+    ContainsAll,
+    /// The context value is an array which contains at least one of the clause values.
+    ContainsAny,
+    /// The context value is an array which contains none of the clause values.
+    ContainsNone,
+    /// The context value is an array whose length is equal to the clause value.
+    ArrayLengthEqual,
+    /// The context value is an array whose length is less than the clause value.
+    ArrayLengthLessThan,
+    /// The context value is an array whose length is greater than the clause value.
+    ArrayLengthGreaterThan,
+    /// An operation which is not recognized by this version of the evaluator. A clause with an
+    /// unknown operation never matches, unless the operation is implemented by an
+    /// [crate::OperatorRegistry] supplied in [crate::EvaluationOptions]. See [Clause::op_name].
     #[serde(other)]
     Unknown,
 }
 
 impl Clause {
+    pub(crate) fn new(
+        context_kind: Kind,
+        attribute: Reference,
+        negate: bool,
+        op: Op,
+        values: Vec<AttributeValue>,
+    ) -> Self {
+        let op_name = match serde_json::to_value(op) {
+            Ok(serde_json::Value::String(name)) => name,
+            _ => String::new(),
+        };
+        let prepared = PreparedValues::new(op, &values);
+        Self {
+            context_kind,
+            attribute,
+            negate,
+            op,
+            op_name,
+            values,
+            prepared,
+        }
+    }
+
+    // Creates a clause from the name of its operation, which may be one this crate does not
+    // recognize.
+    pub(crate) fn with_op_name(
+        context_kind: Kind,
+        attribute: Reference,
+        negate: bool,
+        op_name: String,
+        values: Vec<AttributeValue>,
+    ) -> Self {
+        let name: StrDeserializer<'_, value::Error> = op_name.as_str().into_deserializer();
+        let op = Op::deserialize(name).unwrap_or(Op::Unknown);
+        Self {
+            op_name,
+            ..Self::new(context_kind, attribute, negate, op, values)
+        }
+    }
+
+    /// The kind of context whose attribute is tested.
+    pub fn context_kind(&self) -> &Kind {
+        &self.context_kind
+    }
+
+    /// The context attribute which is tested. This is not used by [Op::SegmentMatch] clauses.
+    pub fn attribute(&self) -> &Reference {
+        &self.attribute
+    }
+
+    /// True if the result of the test is negated.
+    pub fn negate(&self) -> bool {
+        self.negate
+    }
+
+    /// The test operation.
+    pub fn op(&self) -> Op {
+        self.op
+    }
+
+    /// The name of the test operation, as it appears in the JSON model. For an [Op::Unknown]
+    /// operation, this is the name which an [crate::OperatorRegistry] is asked to implement.
+    pub fn op_name(&self) -> &str {
+        &self.op_name
+    }
+
+    /// The values to test against.
+    pub fn values(&self) -> &[AttributeValue] {
+        &self.values
+    }
+
+    // The keys of the segments referred to by a segment match clause.
+    pub(crate) fn segment_keys(&self) -> impl Iterator<Item = &str> {
+        let values = match self.op {
+            Op::SegmentMatch => self.values.as_slice(),
+            _ => &[],
+        };
+        values.iter().filter_map(AttributeValue::as_str)
+    }
+
+    // The values which cannot be parsed as the operator requires, and so can never match.
+    pub(crate) fn invalid_values(&self) -> impl Iterator<Item = &AttributeValue> {
+        self.values
+            .iter()
+            .zip(&self.prepared.values)
+            .filter(|(_, prepared)| matches!(prepared, PreparedValue::Invalid))
+            .map(|(value, _)| value)
+    }
+
     pub(crate) fn matches(
         &self,
         context: &Context,
         store: &dyn Store,
         evaluation_stack: &mut EvaluationStack,
-    ) -> Result<bool, String> {
+    ) -> Result<bool, EvaluationError> {
         if let Op::SegmentMatch = self.op {
             self.matches_segment(context, store, evaluation_stack)
         } else {
-            self.matches_non_segment(context)
+            self.matches_non_segment(context, &evaluation_stack.options)
+        }
+    }
+
+    // Matches the clause as the one at clause_index within its rule, recording it in the trace.
+    pub(crate) fn matches_traced(
+        &self,
+        clause_index: usize,
+        context: &Context,
+        store: &dyn Store,
+        evaluation_stack: &mut EvaluationStack,
+    ) -> Result<bool, EvaluationError> {
+        evaluation_stack.trace_begin(|| TraceEvent::Clause {
+            clause_index,
+            attribute: self.attribute.clone(),
+            op: self.op_name.clone(),
+            context_value: None,
+            matched_values: Vec::new(),
+            matched: false,
+        });
+
+        let result = self.matches(context, store, evaluation_stack);
+
+        let options = evaluation_stack.options;
+        evaluation_stack.trace_end(|event| {
+            if let TraceEvent::Clause {
+                context_value,
+                matched_values,
+                matched,
+                ..
+            } = event
+            {
+                *context_value = self.context_value(context);
+                *matched_values = self.matched_values(context_value.as_ref(), &options);
+                *matched = matches!(result, Ok(true));
+            }
+        });
+
+        result
+    }
+
+    // Resolves the value this clause's attribute has in the context. This is only used for tracing;
+    // matching resolves the value itself as it goes.
+    fn context_value(&self, context: &Context) -> Option<AttributeValue> {
+        if let Op::SegmentMatch = self.op {
+            return None;
+        }
+
+        if self.attribute.is_kind() {
+            return Some(
+                context
+                    .kinds()
+                    .into_iter()
+                    .map(|kind| kind.to_string())
+                    .collect(),
+            );
+        }
+
+        context
+            .as_kind(&self.context_kind)
+            .and_then(|context| context.get_value(&self.attribute))
+    }
+
+    // The clause values which match the context value, before applying negation. This is only used
+    // for tracing. It is empty for segment match clauses, whose segments are traced as steps of
+    // their own, and for the operators which apply to an array as a whole.
+    fn matched_values(
+        &self,
+        context_value: Option<&AttributeValue>,
+        options: &EvaluationOptions,
+    ) -> Vec<AttributeValue> {
+        if let Op::SegmentMatch = self.op {
+            return Vec::new();
+        }
+        if self.op.matches_whole_array() {
+            return Vec::new();
+        }
+
+        let context_values = match context_value {
+            Some(AttributeValue::Array(values)) => values.as_slice(),
+            Some(value) => std::slice::from_ref(value),
+            None => return Vec::new(),
+        };
+        let folded: Vec<_> = context_values
+            .iter()
+            .map(|value| match value {
+                AttributeValue::String(s) if self.op.ignores_case() => {
+                    AttributeValue::String(fold_case(s))
+                }
+                _ => value.clone(),
+            })
+            .collect();
+        let custom = self.custom_operator(options);
+
+        self.values
+            .iter()
+            .zip(&self.prepared.values)
+            .filter(|(clause_value, prepared)| {
+                folded.iter().any(|context_value| match custom {
+                    Some(custom) => custom.matches(context_value, clause_value),
+                    None => {
+                        self.op
+                            .matches_prepared(context_value, clause_value, prepared, options)
+                    }
+                })
+            })
+            .map(|(clause_value, _)| clause_value.clone())
+            .collect()
+    }
+
+    // The operator registered for a clause whose operator is not built in, if there is one.
+    fn custom_operator<'a>(
+        &self,
+        options: &EvaluationOptions<'a>,
+    ) -> Option<&'a dyn CustomOperator> {
+        match (self.op, options.operators) {
+            (Op::Unknown, Some(operators)) => operators.operator(&self.op_name),
+            _ => None,
         }
     }
 
@@ -199,10 +632,10 @@ impl Clause {
         context: &Context,
         store: &dyn Store,
         evaluation_stack: &mut EvaluationStack,
-    ) -> Result<bool, String> {
+    ) -> Result<bool, EvaluationError> {
         for value in self.values.iter() {
             if let Some(segment_key) = value.as_str() {
-                if let Some(segment) = store.segment(segment_key) {
+                if let Some(segment) = store.shared_segment(segment_key) {
                     let matches = segment.contains(context, store, evaluation_stack)?;
                     if matches {
                         return Ok(self.maybe_negate(true));
@@ -214,81 +647,133 @@ impl Clause {
         Ok(self.maybe_negate(false))
     }
 
-    pub(crate) fn matches_non_segment(&self, context: &Context) -> Result<bool, String> {
+    pub(crate) fn matches_non_segment(
+        &self,
+        context: &Context,
+        options: &EvaluationOptions,
+    ) -> Result<bool, EvaluationError> {
         if !self.attribute.is_valid() {
-            return Err(self.attribute.error());
+            return Err(EvaluationError::invalid_reference(&self.attribute));
         }
 
+        let custom = self.custom_operator(options);
+        let matches_value = |context_value: &AttributeValue| match custom {
+            Some(custom) => self
+                .values
+                .iter()
+                .any(|clause_value| custom.matches(context_value, clause_value)),
+            None => self.matches_value(context_value, options),
+        };
+
         if self.attribute.is_kind() {
-            for clause_value in &self.values {
-                for kind in context.kinds().iter() {
-                    if self
-                        .op
-                        .matches(&AttributeValue::String(kind.to_string()), clause_value)
-                    {
-                        return Ok(self.maybe_negate(true));
-                    }
-                }
-            }
-            return Ok(self.maybe_negate(false));
+            let kinds: Vec<_> = context
+                .kinds()
+                .iter()
+                .map(|kind| AttributeValue::String(kind.to_string()))
+                .collect();
+            let matched = if self.op.matches_whole_array() {
+                self.matches_array(&kinds)
+            } else {
+                kinds.iter().any(&matches_value)
+            };
+            return Ok(self.maybe_negate(matched));
         }
 
         if let Some(actual_context) = context.as_kind(&self.context_kind) {
             return match actual_context.get_value(&self.attribute) {
+                None | Some(AttributeValue::Null) if self.op.matches_whole_array() => {
+                    Ok(self.maybe_negate(self.matches_array(&[])))
+                }
                 None | Some(AttributeValue::Null) => Ok(false),
-                Some(AttributeValue::Array(context_values)) => {
-                    for clause_value in &self.values {
-                        for context_value in context_values.iter() {
-                            if self.op.matches(context_value, clause_value) {
-                                return Ok(self.maybe_negate(true));
-                            }
-                        }
-                    }
-
-                    Ok(self.maybe_negate(false))
+                Some(AttributeValue::Array(context_values)) if self.op.matches_whole_array() => {
+                    Ok(self.maybe_negate(self.matches_array(&context_values)))
                 }
-                Some(context_value) => {
-                    if self
-                        .values
-                        .iter()
-                        .any(|clause_value| self.op.matches(&context_value, clause_value))
-                    {
-                        return Ok(self.maybe_negate(true));
-                    }
-                    Ok(self.maybe_negate(false))
+                Some(context_value) if self.op.matches_whole_array() => {
+                    Ok(self.maybe_negate(self.matches_array(std::slice::from_ref(&context_value))))
+                }
+                Some(AttributeValue::Array(context_values)) => {
+                    let matched = context_values.iter().any(&matches_value);
+                    Ok(self.maybe_negate(matched))
                 }
+                Some(context_value) => Ok(self.maybe_negate(matches_value(&context_value))),
             };
         }
 
         Ok(false)
     }
 
+    // Determines if an array of values from the context matches the clause's values, for the
+    // operators which apply to an array as a whole, before applying negation.
+    fn matches_array(&self, context_values: &[AttributeValue]) -> bool {
+        let contains = |clause_value| context_values.contains(clause_value);
+        let length = AttributeValue::Number(context_values.len() as f64);
+        match self.op {
+            Op::ContainsAll => self.values.iter().all(contains),
+            Op::ContainsAny => self.values.iter().any(contains),
+            Op::ContainsNone => !self.values.iter().any(contains),
+            Op::ArrayLengthEqual => self
+                .values
+                .iter()
+                .any(|r| numeric_op(&length, r, |l, r| l == r)),
+            Op::ArrayLengthLessThan => self
+                .values
+                .iter()
+                .any(|r| numeric_op(&length, r, |l, r| l < r)),
+            Op::ArrayLengthGreaterThan => self
+                .values
+                .iter()
+                .any(|r| numeric_op(&length, r, |l, r| l > r)),
+            _ => false,
+        }
+    }
+
+    // Determines if a single value from the context matches any of the clause's values, before
+    // applying negation.
+    fn matches_value(&self, context_value: &AttributeValue, options: &EvaluationOptions) -> bool {
+        // The clause values of the case-insensitive operators are folded ahead of time, so the
+        // context value is folded once here rather than for every clause value.
+        let folded;
+        let context_value = match context_value {
+            AttributeValue::String(s) if self.op.ignores_case() => {
+                folded = AttributeValue::String(fold_case(s));
+                &folded
+            }
+            _ => context_value,
+        };
+
+        if let (Some(strings), AttributeValue::String(s)) = (&self.prepared.strings, context_value)
+        {
+            return strings.contains(s);
+        }
+
+        self.values
+            .iter()
+            .zip(&self.prepared.values)
+            .any(|(clause_value, prepared)| {
+                self.op
+                    .matches_prepared(context_value, clause_value, prepared, options)
+            })
+    }
+
     #[cfg(test)]
     // Use when matching a clause that has an associated context kind.
     pub(crate) fn new_match(reference: Reference, value: AttributeValue, kind: Kind) -> Self {
-        Self {
-            attribute: reference,
-            negate: false,
-            op: Op::Matches,
-            values: vec![value],
-            context_kind: kind,
-        }
+        Self::new(kind, reference, false, Op::Matches, vec![value])
     }
 
     #[cfg(test)]
     // Use when matching a clause that isn't context-aware.
     pub(crate) fn new_context_oblivious_match(reference: Reference, value: AttributeValue) -> Self {
-        Self {
-            attribute: reference,
-            negate: false,
-            op: Op::Matches,
-            values: vec![value],
-            context_kind: Kind::default(),
-        }
+        Self::new(Kind::default(), reference, false, Op::Matches, vec![value])
     }
 }
 
 impl FlagRule {
+    /// The rule's clauses. A context matches the rule only if it matches every clause.
+    pub fn clauses(&self) -> &[Clause] {
+        &self.clauses
+    }
+
     /// Determines if a context matches the provided flag rule.
     ///
     /// A context will match if all flag clauses match; otherwise, this method returns false.
@@ -297,10 +782,10 @@ impl FlagRule {
         context: &Context,
         store: &dyn Store,
         evaluation_stack: &mut EvaluationStack,
-    ) -> Result<bool, String> {
+    ) -> Result<bool, EvaluationError> {
         // rules match if _all_ of their clauses do
-        for clause in &self.clauses {
-            let result = clause.matches(context, store, evaluation_stack)?;
+        for (clause_index, clause) in self.clauses.iter().enumerate() {
+            let result = clause.matches_traced(clause_index, context, store, evaluation_stack)?;
             if !result {
                 return Ok(false);
             }
@@ -313,16 +798,16 @@ impl FlagRule {
     pub(crate) fn new_segment_match(segment_keys: Vec<&str>, kind: Kind) -> Self {
         Self {
             id: "rule".to_string(),
-            clauses: vec![Clause {
-                attribute: Reference::new("key"),
-                negate: false,
-                op: Op::SegmentMatch,
-                values: segment_keys
+            clauses: vec![Clause::new(
+                kind,
+                Reference::new("key"),
+                false,
+                Op::SegmentMatch,
+                segment_keys
                     .iter()
                     .map(|key| AttributeValue::String(key.to_string()))
                     .collect(),
-                context_kind: kind,
-            }],
+            )],
             variation_or_rollout: VariationOrRollout::Variation { variation: 1 },
             track_events: false,
         }
@@ -330,6 +815,93 @@ impl FlagRule {
 }
 
 impl Op {
+    // Like matches, but uses the prepared form of rhs where the operator has one.
+    fn matches_prepared(
+        &self,
+        lhs: &AttributeValue,
+        rhs: &AttributeValue,
+        prepared: &PreparedValue,
+        options: &EvaluationOptions,
+    ) -> bool {
+        match (self, prepared) {
+            (_, PreparedValue::Invalid) => false,
+            (Op::Matches, PreparedValue::Regex(re)) => {
+                lhs.as_str().map_or(false, |l| re.is_match(l))
+            }
+            (Op::Before, PreparedValue::DateTime(r)) => lhs.to_datetime().map_or(false, |l| l < *r),
+            (Op::After, PreparedValue::DateTime(r)) => lhs.to_datetime().map_or(false, |l| l > *r),
+            (Op::OlderThan, PreparedValue::Duration(r)) => {
+                match (lhs.to_datetime(), options.now().checked_sub_signed(*r)) {
+                    (Some(l), Some(cutoff)) => l < cutoff,
+                    _ => false,
+                }
+            }
+            (Op::NewerThan, PreparedValue::Duration(r)) => {
+                match (lhs.to_datetime(), options.now().checked_sub_signed(*r)) {
+                    (Some(l), Some(cutoff)) => l > cutoff,
+                    // The duration reaches back beyond the earliest representable date.
+                    (Some(_), None) => true,
+                    _ => false,
+                }
+            }
+            (Op::TimeOfDayBetween, PreparedValue::TimeOfDay(r)) => {
+                lhs.to_datetime().map_or(false, |l| r.contains(l.time()))
+            }
+            (Op::DayOfWeekIn, PreparedValue::Weekday(r)) => {
+                lhs.to_datetime().map_or(false, |l| l.weekday() == *r)
+            }
+            (Op::SemVerEqual, PreparedValue::SemVer(r)) => {
+                lhs.as_semver().map_or(false, |l| l == *r)
+            }
+            (Op::SemVerLessThan, PreparedValue::SemVer(r)) => {
+                lhs.as_semver().map_or(false, |l| l < *r)
+            }
+            (Op::SemVerGreaterThan, PreparedValue::SemVer(r)) => {
+                lhs.as_semver().map_or(false, |l| l > *r)
+            }
+            (Op::SemVerInRange, PreparedValue::SemVerRange(r)) => {
+                lhs.as_semver().map_or(false, |l| r.contains(&l))
+            }
+            (Op::IpInRange, PreparedValue::Network(r)) => {
+                ip_address(lhs).map_or(false, |l| r.contains(l))
+            }
+            // lhs has already been folded by Clause::matches_value.
+            (Op::InIgnoreCase, PreparedValue::Folded(r)) => lhs.as_str() == Some(r.as_str()),
+            (Op::StartsWithIgnoreCase, PreparedValue::Folded(r)) => {
+                lhs.as_str().map_or(false, |l| l.starts_with(r.as_str()))
+            }
+            (Op::EndsWithIgnoreCase, PreparedValue::Folded(r)) => {
+                lhs.as_str().map_or(false, |l| l.ends_with(r.as_str()))
+            }
+            (Op::ContainsIgnoreCase, PreparedValue::Folded(r)) => {
+                lhs.as_str().map_or(false, |l| l.contains(r.as_str()))
+            }
+            _ => self.matches(lhs, rhs),
+        }
+    }
+
+    fn matches_whole_array(&self) -> bool {
+        matches!(
+            self,
+            Op::ContainsAll
+                | Op::ContainsAny
+                | Op::ContainsNone
+                | Op::ArrayLengthEqual
+                | Op::ArrayLengthLessThan
+                | Op::ArrayLengthGreaterThan
+        )
+    }
+
+    fn ignores_case(&self) -> bool {
+        matches!(
+            self,
+            Op::InIgnoreCase
+                | Op::StartsWithIgnoreCase
+                | Op::EndsWithIgnoreCase
+                | Op::ContainsIgnoreCase
+        )
+    }
+
     fn matches(&self, lhs: &AttributeValue, rhs: &AttributeValue) -> bool {
         match self {
             Op::In => lhs == rhs,
@@ -345,6 +917,19 @@ impl Op {
                     false
                 }
             }),
+            Op::InIgnoreCase => match (lhs.as_str(), rhs.as_str()) {
+                (Some(l), Some(r)) => fold_case(l) == fold_case(r),
+                _ => lhs == rhs,
+            },
+            Op::StartsWithIgnoreCase => {
+                string_op(lhs, rhs, |l, r| fold_case(l).starts_with(&fold_case(r)))
+            }
+            Op::EndsWithIgnoreCase => {
+                string_op(lhs, rhs, |l, r| fold_case(l).ends_with(&fold_case(r)))
+            }
+            Op::ContainsIgnoreCase => {
+                string_op(lhs, rhs, |l, r| fold_case(l).contains(&fold_case(r)))
+            }
 
             // numeric ops
             Op::LessThan => numeric_op(lhs, rhs, |l, r| l < r),
@@ -355,6 +940,25 @@ impl Op {
             Op::Before => time_op(lhs, rhs, |l, r| l < r),
             Op::After => time_op(lhs, rhs, |l, r| l > r),
 
+            Op::OlderThan | Op::NewerThan => {
+                error!("relative time operators need the time of evaluation, shouldn't get here");
+                false
+            }
+            Op::TimeOfDayBetween | Op::DayOfWeekIn => {
+                error!("time window operators should use prepared values, shouldn't get here");
+                false
+            }
+
+            Op::ContainsAll
+            | Op::ContainsAny
+            | Op::ContainsNone
+            | Op::ArrayLengthEqual
+            | Op::ArrayLengthLessThan
+            | Op::ArrayLengthGreaterThan => {
+                error!("array operators should be applied to the whole array, shouldn't get here");
+                false
+            }
+
             Op::SegmentMatch => {
                 error!("segmentMatch operator should be special-cased, shouldn't get here");
                 false
@@ -363,6 +967,15 @@ impl Op {
             Op::SemVerEqual => semver_op(lhs, rhs, |l, r| l == r),
             Op::SemVerLessThan => semver_op(lhs, rhs, |l, r| l < r),
             Op::SemVerGreaterThan => semver_op(lhs, rhs, |l, r| l > r),
+            Op::SemVerInRange => {
+                error!("semVerInRange operator should use prepared values, shouldn't get here");
+                false
+            }
+
+            Op::IpInRange => {
+                error!("ipInRange operator should use prepared values, shouldn't get here");
+                false
+            }
             Op::Unknown => false,
         }
     }
@@ -375,6 +988,28 @@ fn string_op<F: Fn(&str, &str) -> bool>(lhs: &AttributeValue, rhs: &AttributeVal
     }
 }
 
+// Folds the case of a string for the case-insensitive operators. The string is decomposed before
+// folding and recomposed afterwards, so that canonically equivalent strings fold to the same thing.
+fn fold_case(s: &str) -> String {
+    s.nfd().default_case_fold().nfc().collect()
+}
+
+// A clause value for the relative time operators: a number of milliseconds, or a string such as
+// "30d".
+fn duration(value: &AttributeValue) -> Option<Duration> {
+    match value {
+        AttributeValue::Number(millis) => util::f64_to_i64_safe(*millis)
+            .filter(|millis| *millis >= 0)
+            .map(Duration::milliseconds),
+        AttributeValue::String(s) => parse_duration(s),
+        _ => None,
+    }
+}
+
+fn ip_address(value: &AttributeValue) -> Option<IpAddr> {
+    value.as_str().and_then(|s| s.trim().parse().ok())
+}
+
 fn numeric_op<F: Fn(f64, f64) -> bool>(lhs: &AttributeValue, rhs: &AttributeValue, f: F) -> bool {
     match (lhs.to_f64(), rhs.to_f64()) {
         (Some(l), Some(r)) => f(l, r),
@@ -673,41 +1308,41 @@ mod tests {
 
     #[test]
     fn test_clause_matches() {
-        let one_val_clause = Clause {
-            attribute: Reference::new("a"),
-            negate: false,
-            op: Op::In,
-            values: vec!["foo".into()],
-            context_kind: Kind::default(),
-        };
-        let many_val_clause = Clause {
-            attribute: Reference::new("a"),
-            negate: false,
-            op: Op::In,
-            values: vec!["foo".into(), "bar".into()],
-            context_kind: Kind::default(),
-        };
-        let negated_clause = Clause {
-            attribute: Reference::new("a"),
-            negate: true,
-            op: Op::In,
-            values: vec!["foo".into()],
-            context_kind: Kind::default(),
-        };
-        let negated_many_val_clause = Clause {
-            attribute: Reference::new("a"),
-            negate: true,
-            op: Op::In,
-            values: vec!["foo".into(), "bar".into()],
-            context_kind: Kind::default(),
-        };
-        let key_clause = Clause {
-            attribute: Reference::new("key"),
-            negate: false,
-            op: Op::In,
-            values: vec!["matching".into()],
-            context_kind: Kind::default(),
-        };
+        let one_val_clause = Clause::new(
+            Kind::default(),
+            Reference::new("a"),
+            false,
+            Op::In,
+            vec!["foo".into()],
+        );
+        let many_val_clause = Clause::new(
+            Kind::default(),
+            Reference::new("a"),
+            false,
+            Op::In,
+            vec!["foo".into(), "bar".into()],
+        );
+        let negated_clause = Clause::new(
+            Kind::default(),
+            Reference::new("a"),
+            true,
+            Op::In,
+            vec!["foo".into()],
+        );
+        let negated_many_val_clause = Clause::new(
+            Kind::default(),
+            Reference::new("a"),
+            true,
+            Op::In,
+            vec!["foo".into(), "bar".into()],
+        );
+        let key_clause = Clause::new(
+            Kind::default(),
+            Reference::new("key"),
+            false,
+            Op::In,
+            vec!["matching".into()],
+        );
 
         let mut context_builder = ContextBuilder::new("without");
         let context_without_attribute = context_builder.build().expect("Failed to build context");
@@ -856,13 +1491,13 @@ mod tests {
         let mut evaluation_stack = EvaluationStack::default();
 
         for (attr, test_case) in tests {
-            let clause = Clause {
-                attribute: Reference::new(attr),
-                negate: false,
-                op: Op::In,
-                values: vec!["match".into()],
-                context_kind: Kind::default(),
-            };
+            let clause = Clause::new(
+                Kind::default(),
+                Reference::new(attr),
+                false,
+                Op::In,
+                vec!["match".into()],
+            );
 
             assert!(
                 clause
@@ -904,13 +1539,13 @@ mod tests {
 
     #[test]
     fn test_clause_matches_anonymous_attribute() {
-        let clause = Clause {
-            attribute: Reference::new("anonymous"),
-            negate: false,
-            op: Op::In,
-            values: vec![true.into()],
-            context_kind: Kind::default(),
-        };
+        let clause = Clause::new(
+            Kind::default(),
+            Reference::new("anonymous"),
+            false,
+            Op::In,
+            vec![true.into()],
+        );
 
         let anon_context = ContextBuilder::new("anon").anonymous(true).build().unwrap();
         let non_anon_context = ContextBuilder::new("nonanon")
@@ -939,13 +1574,13 @@ mod tests {
     fn test_clause_matches_custom_attributes() {
         // check we can have an attribute called "custom"
         for attr in &["custom", "custom1"] {
-            let clause = Clause {
-                attribute: Reference::new(attr),
-                negate: false,
-                op: Op::In,
-                values: vec!["match".into()],
-                context_kind: Kind::default(),
-            };
+            let clause = Clause::new(
+                Kind::default(),
+                Reference::new(attr),
+                false,
+                Op::In,
+                vec!["match".into()],
+            );
 
             let matching_context = ContextBuilder::new("matching")
                 .set_value(attr, AttributeValue::String("match".into()))
@@ -1028,13 +1663,13 @@ mod tests {
             Op::SemVerLessThan,
         ] {
             for neg in &[true, false] {
-                let clause = Clause {
-                    attribute: Reference::new("attr"),
-                    negate: *neg,
-                    op: *op,
-                    values: clause_values.clone(),
-                    context_kind: Kind::default(),
-                };
+                let clause = Clause::new(
+                    Kind::default(),
+                    Reference::new("attr"),
+                    *neg,
+                    *op,
+                    clause_values.clone(),
+                );
                 let mut evaluation_stack = EvaluationStack::default();
                 assert!(
                     !clause
@@ -1065,16 +1700,16 @@ mod tests {
         S: Clone,
         T: Clone,
     {
-        let clause = Clause {
-            attribute: Reference::new("attr"),
-            negate: false,
+        let clause = Clause::new(
+            Kind::default(),
+            Reference::new("attr"),
+            false,
             op,
-            values: match clause_value.into() {
+            match clause_value.into() {
                 AttributeValue::Array(vec) => vec,
                 other => vec![other],
             },
-            context_kind: Kind::default(),
-        };
+        );
 
         let context = ContextBuilder::new("key")
             .set_value("attr", context_value.into())
@@ -1097,13 +1732,7 @@ mod tests {
 
     #[test]
     fn match_is_false_on_invalid_reference() {
-        let clause = Clause {
-            attribute: Reference::new("/"),
-            negate: false,
-            op: Op::In,
-            values: vec![],
-            context_kind: Kind::default(),
-        };
+        let clause = Clause::new(Kind::default(), Reference::new("/"), false, Op::In, vec![]);
 
         let context = ContextBuilder::new("key")
             .set_value("attr", true.into())
@@ -1117,13 +1746,13 @@ mod tests {
 
     #[test]
     fn match_is_false_no_context_matches() {
-        let clause = Clause {
-            attribute: Reference::new("attr"),
-            negate: false,
-            op: Op::In,
-            values: vec![true.into()],
-            context_kind: Kind::default(),
-        };
+        let clause = Clause::new(
+            Kind::default(),
+            Reference::new("attr"),
+            false,
+            Op::In,
+            vec![true.into()],
+        );
 
         let context = ContextBuilder::new("key")
             .kind("org")
@@ -1228,6 +1857,498 @@ mod tests {
         clause_test_case(Op::Matches, "hello world", "***bad regex", false);
     }
 
+    #[test]
+    fn clause_values_are_prepared_when_deserialized() {
+        let clause: Clause = serde_json::from_value(json!({
+            "attribute": "name",
+            "op": "matches",
+            "values": ["^a", "***bad regex", 1]
+        }))
+        .unwrap();
+        assert!(matches!(
+            clause.prepared.values.as_slice(),
+            [
+                PreparedValue::Regex(_),
+                PreparedValue::Invalid,
+                PreparedValue::Invalid
+            ]
+        ));
+
+        let clause: Clause = serde_json::from_value(json!({
+            "attribute": "name",
+            "op": "in",
+            "values": ["a", "b", 1]
+        }))
+        .unwrap();
+        assert_eq!(
+            Some(HashSet::from(["a".to_string(), "b".to_string()])),
+            clause.prepared.strings
+        );
+        assert!(clause.matches_value(&"b".into(), &EvaluationOptions::default()));
+        assert!(clause.matches_value(&1.into(), &EvaluationOptions::default()));
+        assert!(!clause.matches_value(&"c".into(), &EvaluationOptions::default()));
+    }
+
+    #[test]
+    fn unknown_op_name_is_preserved() {
+        let json = json!({
+            "contextKind": "user",
+            "attribute": "ip",
+            "op": "cidrMatch",
+            "values": ["10.0.0.0/8"]
+        });
+        let clause: Clause = serde_json::from_value(json.clone()).unwrap();
+        assert_eq!(Op::Unknown, clause.op());
+        assert_eq!("cidrMatch", clause.op_name());
+        assert_json_eq!(json, json!(clause));
+
+        let clause: Clause = serde_json::from_value(json!({
+            "attribute": "name",
+            "op": "startsWith",
+            "values": ["a"]
+        }))
+        .unwrap();
+        assert_eq!(Op::StartsWith, clause.op());
+        assert_eq!("startsWith", clause.op_name());
+    }
+
+    #[test]
+    fn unknown_op_is_dispatched_to_operator_registry() {
+        use crate::{evaluate_with_options, CustomOperator, FlagBuilder};
+        use crate::{ClauseBuilder, FlagRuleBuilder, FlagValue};
+
+        let clause = ClauseBuilder::custom("name", "equalsIgnoringCase")
+            .add_value("ALICE")
+            .build()
+            .unwrap();
+        assert_eq!(Op::Unknown, clause.op());
+        let flag = FlagBuilder::new("flag")
+            .on(true)
+            .boolean_variations()
+            .off_variation(0)
+            .fallthrough_variation(0)
+            .add_rule(
+                FlagRuleBuilder::new("rule")
+                    .add_clause(clause)
+                    .variation(1)
+                    .build()
+                    .unwrap(),
+            )
+            .build()
+            .unwrap();
+        let store = TestStore;
+        let context = ContextBuilder::new("key").name("alice").build().unwrap();
+
+        let result = |options: &EvaluationOptions| {
+            evaluate_with_options(&store, &flag, &context, None, options)
+                .detail
+                .value
+                .cloned()
+        };
+        assert_eq!(
+            Some(FlagValue::Bool(false)),
+            result(&EvaluationOptions::default())
+        );
+
+        let mut operators: HashMap<String, Box<dyn CustomOperator>> = HashMap::new();
+        operators.insert(
+            "equalsIgnoringCase".to_string(),
+            Box::new(|lhs: &AttributeValue, rhs: &AttributeValue| {
+                match (lhs.as_str(), rhs.as_str()) {
+                    (Some(l), Some(r)) => l.eq_ignore_ascii_case(r),
+                    _ => false,
+                }
+            }),
+        );
+        let options = EvaluationOptions {
+            operators: Some(&operators),
+            ..Default::default()
+        };
+        assert_eq!(Some(FlagValue::Bool(true)), result(&options));
+
+        operators.clear();
+        let options = EvaluationOptions {
+            operators: Some(&operators),
+            ..Default::default()
+        };
+        assert_eq!(Some(FlagValue::Bool(false)), result(&options));
+    }
+
+    #[test]
+    fn test_ip_clauses() {
+        clause_test_case(Op::IpInRange, "10.1.2.3", "10.0.0.0/8", true);
+        clause_test_case(Op::IpInRange, " 10.1.2.3 ", "10.0.0.0/8", true);
+        clause_test_case(Op::IpInRange, "11.1.2.3", "10.0.0.0/8", false);
+        clause_test_case(
+            Op::IpInRange,
+            "192.168.0.1",
+            vec!["10.0.0.0/8", "192.168.0.0/16"],
+            true,
+        );
+        clause_test_case(Op::IpInRange, "2001:db8::1", "2001:db8::/32", true);
+        clause_test_case(Op::IpInRange, "2001:db8::1", "10.0.0.0/8", false);
+        clause_test_case(Op::IpInRange, "10.1.2.3", "10.1.2.3", true);
+        clause_test_case(Op::IpInRange, "10.1.2.3", "not a network", false);
+        clause_test_case(Op::IpInRange, "not an address", "10.0.0.0/8", false);
+        clause_test_case(Op::IpInRange, 10, "10.0.0.0/8", false);
+
+        let clause: Clause = serde_json::from_value(json!({
+            "attribute": "ip",
+            "op": "ipInRange",
+            "values": ["10.0.0.0/8", "10.0.0.0/99"]
+        }))
+        .unwrap();
+        assert!(matches!(
+            clause.prepared.values.as_slice(),
+            [PreparedValue::Network(_), PreparedValue::Invalid]
+        ));
+    }
+
+    #[test]
+    fn test_case_insensitive_string_clauses() {
+        clause_test_case(
+            Op::InIgnoreCase,
+            "Alice@Example.com",
+            "alice@example.COM",
+            true,
+        );
+        clause_test_case(Op::InIgnoreCase, "x", vec!["a", "X"], true);
+        clause_test_case(Op::InIgnoreCase, "x", "xyz", false);
+        clause_test_case(Op::InIgnoreCase, 99, 99, true);
+        clause_test_case(Op::InIgnoreCase, "99", 99, false);
+        clause_test_case(Op::StartsWithIgnoreCase, "XYZ", "xy", true);
+        clause_test_case(Op::StartsWithIgnoreCase, "x", "XYZ", false);
+        clause_test_case(Op::EndsWithIgnoreCase, "xyZ", "YZ", true);
+        clause_test_case(Op::EndsWithIgnoreCase, "z", "xyz", false);
+        clause_test_case(Op::ContainsIgnoreCase, "xYz", "y", true);
+        clause_test_case(Op::ContainsIgnoreCase, "Y", "xyz", false);
+        clause_test_case(Op::ContainsIgnoreCase, "99", 99, false);
+
+        // full case folding and canonical equivalence
+        clause_test_case(Op::InIgnoreCase, "STRASSE", "straße", true);
+        clause_test_case(Op::StartsWithIgnoreCase, "STRASSE 1", "Straße", true);
+        clause_test_case(Op::InIgnoreCase, "ΣΊΣΥΦΟΣ", "σίσυφος", true);
+        clause_test_case(Op::EndsWithIgnoreCase, "Caf\u{e9}", "CAFE\u{301}", true);
+        clause_test_case(Op::ContainsIgnoreCase, "CAFE\u{301}", "caf\u{e9}", true);
+        clause_test_case(Op::InIgnoreCase, "cafe", "caf\u{e9}", false);
+        clause_test_case(Op::StartsWithIgnoreCase, "caf\u{e9}", "cafe", false);
+    }
+
+    #[test]
+    fn case_insensitive_clauses_match_arrays_and_negate() {
+        let context = ContextBuilder::new("key")
+            .set_value("tags", vec!["Beta", "Internal"].into())
+            .build()
+            .unwrap();
+        let clause = |op, negate, value: &str| {
+            Clause::new(
+                Kind::default(),
+                Reference::new("tags"),
+                negate,
+                op,
+                vec![value.into()],
+            )
+        };
+        let matches = |clause: Clause| {
+            clause
+                .matches(&context, &TestStore, &mut EvaluationStack::default())
+                .unwrap()
+        };
+
+        assert!(matches(clause(Op::InIgnoreCase, false, "internal")));
+        assert!(!matches(clause(Op::InIgnoreCase, true, "internal")));
+        assert!(matches(clause(Op::StartsWithIgnoreCase, false, "BE")));
+        assert!(!matches(clause(Op::StartsWithIgnoreCase, false, "al")));
+        assert!(matches(clause(Op::ContainsIgnoreCase, true, "alpha")));
+    }
+
+    #[test]
+    fn case_insensitive_clause_values_are_folded_when_prepared() {
+        let clause: Clause = serde_json::from_value(json!({
+            "attribute": "name",
+            "op": "containsIgnoreCase",
+            "values": ["Straße", 3]
+        }))
+        .unwrap();
+        assert_eq!(Op::ContainsIgnoreCase, clause.op());
+        assert!(matches!(
+            clause.prepared.values.as_slice(),
+            [PreparedValue::Folded(folded), PreparedValue::Raw] if folded == "strasse"
+        ));
+        assert!(clause.invalid_values().next().is_none());
+    }
+
+    #[test]
+    fn test_relative_time_clauses() {
+        use chrono::TimeZone;
+
+        let now = Utc.with_ymd_and_hms(2022, 6, 15, 12, 0, 0).unwrap();
+        let clock = move || now;
+        let matches = |op, context_value: AttributeValue, clause_value: AttributeValue| {
+            let clause = Clause::new(
+                Kind::default(),
+                Reference::new("attr"),
+                false,
+                op,
+                vec![clause_value],
+            );
+            let context = ContextBuilder::new("key")
+                .set_value("attr", context_value)
+                .build()
+                .unwrap();
+            let mut evaluation_stack = EvaluationStack::default();
+            evaluation_stack.options.clock = Some(&clock);
+            clause
+                .matches(&context, &TestStore, &mut evaluation_stack)
+                .unwrap()
+        };
+        let millis_ago = |millis: i64| (now.timestamp_millis() - millis).into();
+
+        assert!(matches(
+            Op::NewerThan,
+            "2022-06-01T00:00:00Z".into(),
+            "30d".into()
+        ));
+        assert!(!matches(
+            Op::OlderThan,
+            "2022-06-01T00:00:00Z".into(),
+            "30d".into()
+        ));
+        assert!(matches(
+            Op::OlderThan,
+            "2022-05-01T00:00:00Z".into(),
+            "30d".into()
+        ));
+        assert!(!matches(
+            Op::NewerThan,
+            "2022-05-01T00:00:00Z".into(),
+            "30d".into()
+        ));
+        assert!(matches(Op::NewerThan, millis_ago(500), 1000.into()));
+        assert!(!matches(Op::NewerThan, millis_ago(1500), 1000.into()));
+        assert!(matches(
+            Op::OlderThan,
+            millis_ago(90 * 60 * 1000 + 1),
+            "1h30m".into()
+        ));
+        assert!(matches(
+            Op::NewerThan,
+            "2022-06-16T00:00:00Z".into(),
+            "1d".into()
+        ));
+        assert!(matches(
+            Op::NewerThan,
+            "2022-06-01T00:00:00Z".into(),
+            "99999999w".into()
+        ));
+        assert!(!matches(
+            Op::OlderThan,
+            "2022-06-01T00:00:00Z".into(),
+            "99999999w".into()
+        ));
+        assert!(!matches(
+            Op::OlderThan,
+            "2022-05-01T00:00:00Z".into(),
+            "soon".into()
+        ));
+        assert!(!matches(
+            Op::OlderThan,
+            "2022-05-01T00:00:00Z".into(),
+            (-1).into()
+        ));
+        assert!(!matches(Op::NewerThan, "yesterday".into(), "30d".into()));
+
+        let clause: Clause = serde_json::from_value(json!({
+            "attribute": "createdAt",
+            "op": "olderThan",
+            "values": ["30d", 1000, "soon", -1]
+        }))
+        .unwrap();
+        assert!(matches!(
+            clause.prepared.values.as_slice(),
+            [
+                PreparedValue::Duration(_),
+                PreparedValue::Duration(_),
+                PreparedValue::Invalid,
+                PreparedValue::Invalid
+            ]
+        ));
+    }
+
+    #[test]
+    fn test_time_window_clauses() {
+        clause_test_case(
+            Op::TimeOfDayBetween,
+            "2022-06-15T10:30:00Z",
+            "09:00-17:00",
+            true,
+        );
+        clause_test_case(
+            Op::TimeOfDayBetween,
+            "2022-06-15T18:30:00+02:00",
+            "09:00-17:00",
+            true,
+        );
+        clause_test_case(
+            Op::TimeOfDayBetween,
+            "2022-06-15T17:00:00Z",
+            "09:00-17:00",
+            false,
+        );
+        clause_test_case(
+            Op::TimeOfDayBetween,
+            "2022-06-15T02:00:00Z",
+            vec!["09:00-17:00", "22:00-06:00"],
+            true,
+        );
+        clause_test_case(Op::TimeOfDayBetween, 1655287200000.0, "09:00-17:00", true);
+        clause_test_case(Op::TimeOfDayBetween, "10:30", "09:00-17:00", false);
+        clause_test_case(Op::TimeOfDayBetween, "2022-06-15T10:30:00Z", "9-5", false);
+        clause_test_case(
+            Op::TimeOfDayBetween,
+            "2022-06-15T09:00:00Z",
+            "09:00-09:00",
+            false,
+        );
+
+        let clause: Clause = serde_json::from_value(json!({
+            "attribute": "createdAt",
+            "op": "timeOfDayBetween",
+            "values": ["09:00-17:00", "09:00-09:00"]
+        }))
+        .unwrap();
+        assert_eq!(
+            vec![&AttributeValue::from("09:00-09:00")],
+            clause.invalid_values().collect::<Vec<_>>()
+        );
+
+        clause_test_case(Op::DayOfWeekIn, "2022-06-15T10:00:00Z", "wed", true);
+        clause_test_case(Op::DayOfWeekIn, "2022-06-15T10:00:00Z", "Wednesday", true);
+        clause_test_case(
+            Op::DayOfWeekIn,
+            "2022-06-15T10:00:00Z",
+            vec!["Saturday", "Sunday"],
+            false,
+        );
+        clause_test_case(
+            Op::DayOfWeekIn,
+            "2022-06-18T23:30:00-02:00",
+            vec!["Saturday", "Sunday"],
+            true,
+        );
+        clause_test_case(Op::DayOfWeekIn, "2022-06-15T10:00:00Z", "someday", false);
+    }
+
+    #[test]
+    fn test_array_clauses() {
+        let roles = || vec!["admin", "billing", "support"];
+        clause_test_case(Op::ContainsAll, roles(), vec!["admin", "support"], true);
+        clause_test_case(Op::ContainsAll, roles(), vec!["admin", "owner"], false);
+        clause_test_case(Op::ContainsAll, "admin", "admin", true);
+        clause_test_case(Op::ContainsAll, "admin", vec!["admin", "support"], false);
+        clause_test_case(Op::ContainsAny, roles(), vec!["owner", "billing"], true);
+        clause_test_case(Op::ContainsAny, roles(), vec!["owner", "guest"], false);
+        clause_test_case(Op::ContainsNone, roles(), vec!["owner", "guest"], true);
+        clause_test_case(Op::ContainsNone, roles(), vec!["owner", "admin"], false);
+        clause_test_case(Op::ContainsNone, "admin", "owner", true);
+        clause_test_case(Op::ContainsAll, vec![1, 2, 3], vec![3.0, 1.0], true);
+        clause_test_case(Op::ContainsAny, vec![1, 2, 3], "1", false);
+
+        clause_test_case(Op::ArrayLengthEqual, roles(), 3, true);
+        clause_test_case(Op::ArrayLengthEqual, roles(), vec![1, 2], false);
+        clause_test_case(Op::ArrayLengthLessThan, roles(), 4, true);
+        clause_test_case(Op::ArrayLengthLessThan, roles(), 3, false);
+        clause_test_case(Op::ArrayLengthGreaterThan, roles(), 2, true);
+        clause_test_case(Op::ArrayLengthGreaterThan, "admin", 1, false);
+        clause_test_case(Op::ArrayLengthEqual, roles(), "3", false);
+
+        let clause: Clause = serde_json::from_value(json!({
+            "attribute": "roles",
+            "op": "arrayLengthGreaterThan",
+            "values": [2, "2"]
+        }))
+        .unwrap();
+        assert_eq!(
+            vec![&AttributeValue::from("2")],
+            clause.invalid_values().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn array_clauses_treat_missing_values_as_empty_arrays() {
+        let context = ContextBuilder::new("key")
+            .set_value("nothing", AttributeValue::Null)
+            .build()
+            .unwrap();
+        let matches = |kind: &str, attribute: &str, op, negate, value: AttributeValue| {
+            Clause::new(
+                Kind::from(kind),
+                Reference::new(attribute),
+                negate,
+                op,
+                vec![value],
+            )
+            .matches(&context, &TestStore, &mut EvaluationStack::default())
+            .unwrap()
+        };
+
+        for attribute in ["groups", "nothing"] {
+            let matches = |op, negate, value| matches("user", attribute, op, negate, value);
+            assert!(matches(Op::ContainsNone, false, "admin".into()));
+            assert!(!matches(Op::ContainsNone, true, "admin".into()));
+            assert!(!matches(Op::ContainsAny, false, "admin".into()));
+            assert!(!matches(Op::ContainsAll, false, "admin".into()));
+            assert!(matches(Op::ArrayLengthEqual, false, 0.into()));
+            assert!(matches(Op::ArrayLengthLessThan, false, 1.into()));
+            assert!(!matches(Op::ArrayLengthGreaterThan, false, 0.into()));
+        }
+
+        // A context without the clause's kind still does not match.
+        assert!(!matches(
+            "org",
+            "groups",
+            Op::ContainsNone,
+            false,
+            "admin".into()
+        ));
+    }
+
+    #[test]
+    fn array_clauses_apply_negation_to_the_whole_array() {
+        let context = ContextBuilder::new("key")
+            .set_value("groups", vec!["beta", "staff"].into())
+            .build()
+            .unwrap();
+        let clause = |attribute: &str, op, negate, values: Vec<&str>| {
+            Clause::new(
+                Kind::default(),
+                Reference::new(attribute),
+                negate,
+                op,
+                values.into_iter().map(AttributeValue::from).collect(),
+            )
+        };
+        let matches = |op, negate, values| {
+            clause("groups", op, negate, values)
+                .matches(&context, &TestStore, &mut EvaluationStack::default())
+                .unwrap()
+        };
+
+        assert!(matches(Op::ContainsAll, false, vec!["beta", "staff"]));
+        assert!(matches(Op::ContainsAll, true, vec!["beta", "admin"]));
+        assert!(!matches(Op::ContainsNone, true, vec!["admin"]));
+        assert!(matches(Op::ContainsNone, true, vec!["staff"]));
+
+        let multi = crate::MultiContextBuilder::new()
+            .add_context(ContextBuilder::new("a").build().unwrap())
+            .add_context(ContextBuilder::new("b").kind("org").build().unwrap())
+            .build()
+            .unwrap();
+        assert!(clause("kind", Op::ContainsAll, false, vec!["user", "org"])
+            .matches(&multi, &TestStore, &mut EvaluationStack::default())
+            .unwrap());
+    }
+
     #[test]
     fn test_date_clauses() {
         const DATE_STR1: &str = "2017-12-06T00:00:00.000-07:00";
@@ -1275,6 +2396,29 @@ mod tests {
         clause_test_case(Op::SemVerGreaterThan, "2.0.0-rc.1", "2.0.0-rc.0", true);
     }
 
+    #[test]
+    fn test_semver_range_clauses() {
+        clause_test_case(Op::SemVerInRange, "2.3.1", "^2.3", true);
+        clause_test_case(Op::SemVerInRange, "2.5", "^2.3", true);
+        clause_test_case(Op::SemVerInRange, "2", "^2.3", false);
+        clause_test_case(Op::SemVerInRange, "2.4.0-beta", "^2.3", false);
+        clause_test_case(Op::SemVerInRange, "2.4.0+build7", ">=2.4.0 <2.5.0", true);
+        clause_test_case(Op::SemVerInRange, "1.0.0", vec!["^2.3", "1.x"], true);
+        clause_test_case(Op::SemVerInRange, "xbad%ver", "*", false);
+        clause_test_case(Op::SemVerInRange, 2, "*", false);
+
+        let clause: Clause = serde_json::from_value(json!({
+            "attribute": "version",
+            "op": "semVerInRange",
+            "values": ["~1.2 || ^3", "not a range"]
+        }))
+        .unwrap();
+        assert!(matches!(
+            clause.prepared.values.as_slice(),
+            [PreparedValue::SemVerRange(_), PreparedValue::Invalid]
+        ));
+    }
+
     #[test]
     fn clause_deserialize_with_attribute_missing_causes_error() {
         let attribute_missing = json!({
@@ -1315,7 +2459,7 @@ mod tests {
             IntermediateClause::ContextOblivious(ClauseWithoutKind {
                 attribute: AttributeName::default(),
                 negate: false,
-                op: Op::In,
+                op: "in".to_string(),
                 values: vec![]
             })
         );
@@ -0,0 +1,53 @@
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::Context;
+
+/// Compute the secure mode hash of a context, which a server passes to a client-side SDK along
+/// with the context so that LaunchDarkly can verify the context came from the server.
+///
+/// This is the hex encoding of the HMAC-SHA256 of the context's [Context::canonical_key], using
+/// the SDK key as the HMAC key. For a multi-context, the canonical key combines the keys of every
+/// context it contains, so the hash is not the same as that of any one of them.
+pub fn secure_mode_hash(sdk_key: &str, context: &Context) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(sdk_key.as_bytes())
+        .expect("HMAC can take a key of any size");
+    mac.update(context.canonical_key().as_bytes());
+    base16ct::lower::encode_string(&mac.finalize().into_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ContextBuilder, MultiContextBuilder};
+    use test_case::test_case;
+
+    #[test]
+    fn hash_is_hex_hmac_sha256_of_key() {
+        let context = ContextBuilder::new("Message").build().unwrap();
+        assert_eq!(
+            "aa747c502a898200f9e4fa21bac68136f886a0e27aec70ba06daf2e2a5cb5597",
+            secure_mode_hash("secret", &context)
+        );
+    }
+
+    #[test_case(
+        ContextBuilder::new("alice").build().unwrap(),
+        "ef2c980c176824c3ecca7ff712d76249db0ee5dd28e6ea30e426da3a098ac96a"
+    )]
+    #[test_case(
+        ContextBuilder::new("acme").kind("org").build().unwrap(),
+        "323a175d9e8dc36228eac98c5167d3f039a55726d212d5c7369e5d488dc4a7a7"
+    )]
+    #[test_case(
+        MultiContextBuilder::new()
+            .add_context(ContextBuilder::new("alice").build().unwrap())
+            .add_context(ContextBuilder::new("acme").kind("org").build().unwrap())
+            .build()
+            .unwrap(),
+        "d856690d026135cc90f38e44e43ff065ac8bf9982e149f599186ac60f2c3a68e"
+    )]
+    fn hash_uses_canonical_key(context: Context, expected: &str) {
+        assert_eq!(expected, secure_mode_hash("sdk-key", &context));
+    }
+}
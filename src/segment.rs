@@ -1,8 +1,13 @@
 use serde::{Deserialize, Serialize};
 
+use crate::big_segment::BigSegmentsStatus;
 use crate::contexts::attribute_reference::AttributeName;
 use crate::contexts::context::{BucketPrefix, Kind};
+use crate::eval::EvaluationError;
 use crate::rule::Clause;
+use crate::trace::{SegmentDecision, TraceEvent};
+use crate::util::KeySet;
+use crate::validation::{Diagnostic, Diagnostics, Problem};
 use crate::variation::VariationWeight;
 use crate::{Context, EvaluationStack, Reference, Store, Versioned};
 use serde_with::skip_serializing_none;
@@ -13,30 +18,31 @@ use serde_with::skip_serializing_none;
 pub struct Segment {
     /// The unique key of the segment.
     pub key: String,
-    /// A list of context keys that are always matched by this segment.
-    pub included: Vec<String>,
-    /// A list of context keys that are never matched by this segment, unless the key is also in
-    /// included.
-    pub excluded: Vec<String>,
+    pub(crate) included: KeySet,
+    pub(crate) excluded: KeySet,
 
     #[serde(default)]
-    included_contexts: Vec<SegmentTarget>,
+    pub(crate) included_contexts: Vec<SegmentTarget>,
     #[serde(default)]
-    excluded_contexts: Vec<SegmentTarget>,
+    pub(crate) excluded_contexts: Vec<SegmentTarget>,
 
-    rules: Vec<SegmentRule>,
-    salt: String,
+    pub(crate) rules: Vec<SegmentRule>,
+    pub(crate) salt: String,
 
     /// Unbounded is true if this is a segment whose included list is stored separately and is not limited in size.
-    /// Currently, the server-side Rust SDK cannot access the context list for this kind of segment; it only works when
-    /// flags are being evaluated within the LaunchDarkly service.
+    /// Membership of this kind of segment is looked up through the [crate::BigSegmentStore] provided by
+    /// [Store::big_segment_store]; the included and excluded lists of the segment itself are ignored.
     ///
     /// The name is historical: "unbounded segments" was an earlier name for the product feature that is currently
     /// known as "big segments". If unbounded is true, this is a big segment.
     #[serde(default)]
     pub unbounded: bool,
     #[serde(default)]
-    generation: Option<i64>,
+    pub(crate) generation: Option<i64>,
+    /// The kind of context whose key is used to look up membership of a big segment. If this is
+    /// None, the context kind is assumed to be user.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unbounded_context_kind: Option<Kind>,
 
     /// An integer that is incremented by LaunchDarkly every time the configuration of the segment
     /// is changed.
@@ -49,7 +55,7 @@ impl Versioned for Segment {
     }
 }
 
-// SegmentRule describes a rule that determines if a context is part of a segment.
+/// SegmentRule describes a rule that determines if a context is part of a segment.
 // SegmentRule is deserialized via a helper, IntermediateSegmentRule, because of semantic ambiguity
 // of the bucketBy Reference field.
 //
@@ -58,18 +64,18 @@ impl Versioned for Segment {
 #[skip_serializing_none]
 #[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
 #[serde(rename_all = "camelCase", from = "IntermediateSegmentRule")]
-struct SegmentRule {
+pub struct SegmentRule {
     // Unique identifier provided by the LaunchDarkly backend for this rule.
-    id: Option<String>,
+    pub(crate) id: Option<String>,
     // The clauses that comprise this rule.
-    clauses: Vec<Clause>,
+    pub(crate) clauses: Vec<Clause>,
     // A percentage rollout allowing only a subset of contexts to be included in this segment.
-    weight: Option<VariationWeight>,
+    pub(crate) weight: Option<VariationWeight>,
     // Which attribute should be used to distinguish between contexts in a rollout.
     // Can be omitted; evaluation should treat absence as 'key'.
-    bucket_by: Option<Reference>,
+    pub(crate) bucket_by: Option<Reference>,
     // Only present when this segment rule is a rollout, i.e., only present when weight is present.
-    rollout_context_kind: Option<Kind>,
+    pub(crate) rollout_context_kind: Option<Kind>,
 }
 
 // SegmentRule is deserialized via IntermediateSegmentRule, taking advantage of
@@ -144,52 +150,139 @@ impl Segment {
         context: &Context,
         store: &dyn Store,
         evaluation_stack: &mut EvaluationStack,
-    ) -> Result<bool, String> {
+    ) -> Result<bool, EvaluationError> {
         if evaluation_stack.segment_chain.contains(&self.key) {
-            return Err(format!("segment rule referencing segment {} caused a circular reference; this is probably a temporary condition due to an incomplete update", self.key));
+            return Err(EvaluationError::CircularSegmentReference {
+                segment_key: self.key.clone(),
+            });
         }
 
         evaluation_stack.segment_chain.insert(self.key.clone());
+        evaluation_stack.trace_begin(|| TraceEvent::Segment {
+            key: self.key.clone(),
+            decision: None,
+        });
+
+        let result = self.decide(context, store, evaluation_stack);
+
+        evaluation_stack.trace_end(|event| {
+            if let TraceEvent::Segment { decision, .. } = event {
+                *decision = result.as_ref().ok().copied();
+            }
+        });
+
+        let decision = result?;
+        evaluation_stack.segment_chain.remove(&self.key);
+
+        Ok(matches!(
+            decision,
+            SegmentDecision::Included | SegmentDecision::RuleMatch
+        ))
+    }
+
+    fn decide(
+        &self,
+        context: &Context,
+        store: &dyn Store,
+        evaluation_stack: &mut EvaluationStack,
+    ) -> Result<SegmentDecision, EvaluationError> {
+        if self.unbounded {
+            return self.decide_unbounded(context, store, evaluation_stack);
+        }
 
-        let mut does_contain = false;
         if self.is_contained_in(context, &self.included, &self.included_contexts) {
-            does_contain = true;
-        } else if self.is_contained_in(context, &self.excluded, &self.excluded_contexts) {
-            does_contain = false;
-        } else {
-            for rule in &self.rules {
-                let matches =
-                    rule.matches(context, store, &self.key, &self.salt, evaluation_stack)?;
-                if matches {
-                    does_contain = true;
-                    break;
+            return Ok(SegmentDecision::Included);
+        }
+
+        if self.is_contained_in(context, &self.excluded, &self.excluded_contexts) {
+            return Ok(SegmentDecision::Excluded);
+        }
+
+        self.decide_by_rules(context, store, evaluation_stack)
+    }
+
+    fn decide_by_rules(
+        &self,
+        context: &Context,
+        store: &dyn Store,
+        evaluation_stack: &mut EvaluationStack,
+    ) -> Result<SegmentDecision, EvaluationError> {
+        for (rule_index, rule) in self.rules.iter().enumerate() {
+            evaluation_stack.trace_begin(|| TraceEvent::SegmentRule {
+                rule_index,
+                rule_id: rule.id.clone(),
+                matched: false,
+            });
+            let result =
+                rule.matches_with_stack(context, store, &self.key, &self.salt, evaluation_stack);
+            evaluation_stack.trace_end(|event| {
+                if let TraceEvent::SegmentRule { matched, .. } = event {
+                    *matched = matches!(result, Ok(true));
                 }
+            });
+
+            if result? {
+                return Ok(SegmentDecision::RuleMatch);
             }
         }
 
-        evaluation_stack.segment_chain.remove(&self.key);
-
-        Ok(does_contain)
+        Ok(SegmentDecision::NoMatch)
     }
 
-    fn is_contained_in(
+    // The included and excluded lists of a big segment are held in a BigSegmentStore rather than
+    // in the segment itself. If the store has no opinion about the context, or could not be
+    // queried, the segment's rules are still considered.
+    fn decide_unbounded(
         &self,
         context: &Context,
-        keys: &[String],
-        targets: &[SegmentTarget],
-    ) -> bool {
+        store: &dyn Store,
+        evaluation_stack: &mut EvaluationStack,
+    ) -> Result<SegmentDecision, EvaluationError> {
+        if self.generation.is_none() {
+            evaluation_stack.merge_big_segments_status(BigSegmentsStatus::NotConfigured);
+            return Ok(SegmentDecision::NoMatch);
+        }
+
+        let kind = self.unbounded_context_kind.clone().unwrap_or_default();
+        let key = match context.as_kind(&kind) {
+            Some(context) => context.key(),
+            None => return Ok(SegmentDecision::NoMatch),
+        };
+
+        let big_segment_store = match store.big_segment_store() {
+            Some(big_segment_store) => big_segment_store,
+            None => {
+                evaluation_stack.merge_big_segments_status(BigSegmentsStatus::NotConfigured);
+                return Ok(SegmentDecision::NoMatch);
+            }
+        };
+
+        let membership = evaluation_stack.big_segment_membership(
+            big_segment_store,
+            key,
+            &self.unbounded_segment_id(),
+        );
+
+        match membership {
+            Some(true) => Ok(SegmentDecision::Included),
+            Some(false) => Ok(SegmentDecision::Excluded),
+            None => self.decide_by_rules(context, store, evaluation_stack),
+        }
+    }
+
+    fn is_contained_in(&self, context: &Context, keys: &KeySet, targets: &[SegmentTarget]) -> bool {
         if context.kind().is_user() && targets.is_empty() {
-            return keys.iter().any(|k| k == context.key());
+            return keys.contains(context.key());
         }
 
         for target in targets {
             if let Some(context) = context.as_kind(&target.context_kind) {
                 let key = context.key();
-                if target.values.iter().any(|v| v == key) {
+                if target.values.contains(key) {
                     return true;
                 }
 
-                if context.kind().is_user() && keys.iter().any(|k| k == key) {
+                if context.kind().is_user() && keys.contains(key) {
                     return true;
                 }
             }
@@ -198,6 +291,68 @@ impl Segment {
         false
     }
 
+    /// A list of user keys that are always matched by this segment.
+    pub fn included(&self) -> &[String] {
+        self.included.as_slice()
+    }
+
+    /// A list of user keys that are never matched by this segment, unless the key is also in
+    /// included.
+    pub fn excluded(&self) -> &[String] {
+        self.excluded.as_slice()
+    }
+
+    /// Lists of context keys, of any kind, which are always matched by this segment.
+    pub fn included_contexts(&self) -> &[SegmentTarget] {
+        &self.included_contexts
+    }
+
+    /// Lists of context keys, of any kind, which are never matched by this segment, unless the key
+    /// is also included.
+    pub fn excluded_contexts(&self) -> &[SegmentTarget] {
+        &self.excluded_contexts
+    }
+
+    /// The segment's rules, in the order in which they are checked.
+    pub fn rules(&self) -> &[SegmentRule] {
+        &self.rules
+    }
+
+    /// The salt used when computing rollout buckets for the segment's rules.
+    pub fn salt(&self) -> &str {
+        &self.salt
+    }
+
+    /// The generation of a big segment's membership list. This is None if the segment is not a
+    /// big segment, or if its membership list has not yet been created.
+    pub fn generation(&self) -> Option<i64> {
+        self.generation
+    }
+
+    /// Checks the segment's rules for malformed data, such as weights which are out of range,
+    /// invalid attribute references, and clause values which can never match.
+    ///
+    /// Returns an empty list if no problems were found.
+    pub fn validate(&self) -> Vec<Diagnostic> {
+        let mut diagnostics = Diagnostics::default();
+        for (index, rule) in self.rules.iter().enumerate() {
+            let location = format!("rules[{}]", index);
+            if let Some(weight) = rule.weight {
+                if !(0.0..=100_000.0).contains(&weight) {
+                    diagnostics.push(
+                        format!("{}.weight", location),
+                        Problem::WeightOutOfRange { weight },
+                    );
+                }
+            }
+            if let Some(bucket_by) = &rule.bucket_by {
+                diagnostics.check_reference(format!("{}.bucketBy", location), bucket_by);
+            }
+            diagnostics.check_clauses(&location, &rule.clauses);
+        }
+        diagnostics.into_vec()
+    }
+
     /// Retrieve the id representing this big segment.
     ///
     /// This id will either be the segment key if the segment isn't a big segment, or it will be a
@@ -211,20 +366,60 @@ impl Segment {
 }
 
 impl SegmentRule {
+    /// Unique identifier provided by the LaunchDarkly backend for this rule, if any.
+    pub fn id(&self) -> Option<&str> {
+        self.id.as_deref()
+    }
+
+    /// The rule's clauses. A context matches the rule only if it matches every clause.
+    pub fn clauses(&self) -> &[Clause] {
+        &self.clauses
+    }
+
+    /// If present, only this proportion of the contexts which match the clauses are included in
+    /// the segment, as an integer from 0 to 100000.
+    pub fn weight(&self) -> Option<VariationWeight> {
+        self.weight
+    }
+
+    /// The attribute which contexts are bucketed by when the rule has a weight. If this is None,
+    /// contexts are bucketed by their key.
+    pub fn bucket_by(&self) -> Option<&Reference> {
+        self.bucket_by.as_ref()
+    }
+
+    /// The kind of context which is bucketed when the rule has a weight. If this is None, the
+    /// context kind is assumed to be user.
+    pub fn rollout_context_kind(&self) -> Option<&Kind> {
+        self.rollout_context_kind.as_ref()
+    }
+
     /// Determines if a context matches the provided segment rule.
     ///
     /// A context will match if all segment clauses match; otherwise, this method returns false.
+    /// The `key` and `salt` of the segment holding the rule are used to bucket the context if the
+    /// rule has a weight.
     pub fn matches(
         &self,
         context: &Context,
         store: &dyn Store,
         key: &str,
         salt: &str,
+    ) -> Result<bool, EvaluationError> {
+        self.matches_with_stack(context, store, key, salt, &mut EvaluationStack::default())
+    }
+
+    pub(crate) fn matches_with_stack(
+        &self,
+        context: &Context,
+        store: &dyn Store,
+        key: &str,
+        salt: &str,
         evaluation_stack: &mut EvaluationStack,
-    ) -> Result<bool, String> {
+    ) -> Result<bool, EvaluationError> {
         // rules match if _all_ of their clauses do
-        for clause in &self.clauses {
-            let matches = clause.matches(context, store, evaluation_stack)?;
+        for (clause_index, clause) in self.clauses.iter().enumerate() {
+            let matches = clause.matches_traced(clause_index, context, store, evaluation_stack)?;
             if !matches {
                 return Ok(false);
             }
@@ -233,14 +428,16 @@ impl SegmentRule {
         match self.weight {
             Some(weight) if weight >= 0.0 => {
                 let prefix = BucketPrefix::KeyAndSalt(key, salt);
-                let (bucket, _) = context.bucket(
-                    &self.bucket_by,
-                    prefix,
-                    false,
-                    self.rollout_context_kind
-                        .as_ref()
-                        .unwrap_or(&Kind::default()),
-                )?;
+                let default_kind = Kind::default();
+                let context_kind = self.rollout_context_kind.as_ref().unwrap_or(&default_kind);
+                let (bucket, _) = context.bucket(&self.bucket_by, prefix, false, context_kind)?;
+
+                evaluation_stack.trace_record(|| TraceEvent::Bucket {
+                    context_kind: context_kind.clone(),
+                    bucket_by: self.bucket_by.clone(),
+                    bucket,
+                });
+
                 Ok(bucket < weight / 100_000.0)
             }
             _ => Ok(true),
@@ -248,22 +445,40 @@ impl SegmentRule {
     }
 }
 
+/// SegmentTarget describes a set of contexts, of a single kind, which are included in or excluded
+/// from a segment based on their keys.
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
-pub(crate) struct SegmentTarget {
-    values: Vec<String>,
-    context_kind: Kind,
+pub struct SegmentTarget {
+    pub(crate) values: KeySet,
+    pub(crate) context_kind: Kind,
+}
+
+impl SegmentTarget {
+    /// The kind of context which this target applies to.
+    pub fn context_kind(&self) -> &Kind {
+        &self.context_kind
+    }
+
+    /// The keys of the targeted contexts.
+    pub fn values(&self) -> &[String] {
+        self.values.as_slice()
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::big_segment::big_segment_context_hash;
     use crate::contexts::attribute_reference::Reference;
-    use crate::eval::evaluate;
+    use crate::eval::{evaluate, evaluate_with_options, EvaluationOptions, EvaluationResult};
+    use crate::BigSegmentStore;
     use crate::{proptest_generators::*, AttributeValue, ContextBuilder, Flag, FlagValue, Store};
     use assert_json_diff::assert_json_eq;
     use proptest::{collection::vec, option::of, prelude::*};
     use serde_json::json;
+    use std::cell::Cell;
+    use std::collections::HashMap;
 
     prop_compose! {
         // Generate an arbitrary SegmentRule with 0-3 clauses
@@ -299,8 +514,8 @@ mod tests {
         .to_string();
 
         let segment: Segment = serde_json::from_str(json).expect("Failed to parse segment");
-        assert_eq!(1, segment.included.len());
-        assert_eq!(1, segment.excluded.len());
+        assert_eq!(1, segment.included().len());
+        assert_eq!(1, segment.excluded().len());
 
         assert!(segment.included_contexts.is_empty());
         assert!(segment.excluded_contexts.is_empty());
@@ -327,8 +542,8 @@ mod tests {
         .to_string();
 
         let segment: Segment = serde_json::from_str(json).expect("Failed to parse segment");
-        assert!(segment.included.is_empty());
-        assert!(segment.excluded.is_empty());
+        assert!(segment.included().is_empty());
+        assert!(segment.excluded().is_empty());
 
         assert_eq!(1, segment.included_contexts.len());
         assert_eq!(1, segment.excluded_contexts.len());
@@ -359,14 +574,15 @@ mod tests {
     fn new_segment() -> Segment {
         Segment {
             key: "segkey".to_string(),
-            included: vec![],
-            excluded: vec![],
+            included: Default::default(),
+            excluded: Default::default(),
             included_contexts: vec![],
             excluded_contexts: vec![],
             rules: vec![],
             salt: "salty".to_string(),
             unbounded: false,
             generation: Some(1),
+            unbounded_context_kind: None,
             version: 1,
         }
     }
@@ -532,9 +748,9 @@ mod tests {
     #[test]
     fn segment_match_clause_falls_through_if_segment_not_found() {
         let mut segment = new_segment();
-        segment.included.push("foo".to_string());
+        segment.included = vec!["foo".to_string()].into();
         segment.included_contexts.push(SegmentTarget {
-            values: vec![],
+            values: vec![].into(),
             context_kind: Kind::user(),
         });
         segment.key = "different-key".to_string();
@@ -542,12 +758,75 @@ mod tests {
         assert_segment_match(&segment, context, true);
     }
 
+    #[test]
+    fn segment_model_can_be_inspected() {
+        let segment: Segment = serde_json::from_value(json!({
+            "key": "segment",
+            "included": [],
+            "excluded": [],
+            "includedContexts": [{"contextKind": "org", "values": ["acme"]}],
+            "excludedContexts": [{"contextKind": "org", "values": ["globex"]}],
+            "rules": [{
+                "id": "rule",
+                "clauses": [{"attribute": "name", "op": "in", "values": ["Jane"]}],
+                "weight": 30000,
+                "bucketBy": "email",
+                "rolloutContextKind": "user"
+            }],
+            "salt": "salty",
+            "unbounded": true,
+            "generation": 2,
+            "version": 1
+        }))
+        .unwrap();
+
+        assert_eq!(["acme"], segment.included_contexts()[0].values());
+        assert_eq!(
+            "org",
+            segment.excluded_contexts()[0].context_kind().as_ref()
+        );
+        assert_eq!("salty", segment.salt());
+        assert_eq!(Some(2), segment.generation());
+
+        let rule = &segment.rules()[0];
+        assert_eq!(Some("rule"), rule.id());
+        assert_eq!(1, rule.clauses().len());
+        assert_eq!(Some(30000.0), rule.weight());
+        assert_eq!(Some(&Reference::new("email")), rule.bucket_by());
+        assert_eq!(Some(&Kind::user()), rule.rollout_context_kind());
+    }
+
+    #[test]
+    fn segment_matches_changed_key_lists() {
+        let mut segment: Segment = serde_json::from_value(json!({
+            "key": "segkey",
+            "included": ["alice"],
+            "excluded": [],
+            "rules": [],
+            "salt": "salty",
+            "version": 1
+        }))
+        .unwrap();
+        let alice = ContextBuilder::new("alice").build().unwrap();
+        let bob = ContextBuilder::new("bob").build().unwrap();
+        assert_segment_match(&segment, alice.clone(), true);
+        assert_segment_match(&segment, bob.clone(), false);
+
+        segment.included = vec!["bob".to_string()].into();
+        assert_segment_match(&segment, alice.clone(), false);
+        assert_segment_match(&segment, bob.clone(), true);
+
+        segment.included = vec!["alice".to_string()].into();
+        assert_segment_match(&segment, alice, true);
+        assert_segment_match(&segment, bob, false);
+    }
+
     #[test]
     fn can_match_just_one_segment_from_list() {
         let mut segment = new_segment();
-        segment.included.push("foo".to_string());
+        segment.included = vec!["foo".to_string()].into();
         segment.included_contexts.push(SegmentTarget {
-            values: vec![],
+            values: vec![].into(),
             context_kind: Kind::user(),
         });
         let context = ContextBuilder::new("foo").build().unwrap();
@@ -562,16 +841,30 @@ mod tests {
     #[test]
     fn user_is_explicitly_included_in_segment() {
         let mut segment = new_segment();
-        segment.included.push("foo".to_string());
-        segment.included.push("bar".to_string());
+        segment.included = vec!["foo".to_string(), "bar".to_string()].into();
         segment.included_contexts.push(SegmentTarget {
-            values: vec![],
+            values: vec![].into(),
             context_kind: Kind::user(),
         });
         let context = ContextBuilder::new("bar").build().unwrap();
         assert_segment_match(&segment, context, true);
     }
 
+    #[test]
+    fn segment_rule_can_be_matched_directly() {
+        let rule = jane_rule(None, None, None);
+        let jane = ContextBuilder::new("foo").name("Jane").build().unwrap();
+        let joan = ContextBuilder::new("foo").name("Joan").build().unwrap();
+        assert_eq!(
+            Ok(true),
+            rule.matches(&jane, &new_segment(), "segkey", "salty")
+        );
+        assert_eq!(
+            Ok(false),
+            rule.matches(&joan, &new_segment(), "segkey", "salty")
+        );
+    }
+
     proptest! {
         #[test]
         fn user_is_matched_by_segment_rule(kind in of(Just(Kind::user()))) {
@@ -589,10 +882,9 @@ mod tests {
         fn user_is_explicitly_excluded_from_segment(kind in of(Just(Kind::user()))) {
             let mut segment = new_segment();
             segment.rules.push(jane_rule(None, None, kind));
-            segment.excluded.push("foo".to_string());
-            segment.excluded.push("bar".to_string());
+            segment.excluded = vec!["foo".to_string(), "bar".to_string()].into();
             segment.excluded_contexts.push(SegmentTarget {
-                values: vec![],
+                values: vec![].into(),
                 context_kind: Kind::user(),
             });
             let jane = ContextBuilder::new("foo").name("Jane").build().unwrap();
@@ -603,15 +895,14 @@ mod tests {
     #[test]
     fn segment_includes_override_excludes() {
         let mut segment = new_segment();
-        segment.included.push("bar".to_string());
+        segment.included = vec!["bar".to_string()].into();
         segment.included_contexts.push(SegmentTarget {
-            values: vec![],
+            values: vec![].into(),
             context_kind: Kind::user(),
         });
-        segment.excluded.push("foo".to_string());
-        segment.excluded.push("bar".to_string());
+        segment.excluded = vec!["foo".to_string(), "bar".to_string()].into();
         segment.excluded_contexts.push(SegmentTarget {
-            values: vec![],
+            values: vec![].into(),
             context_kind: Kind::user(),
         });
         let context = ContextBuilder::new("bar").build().unwrap();
@@ -622,11 +913,11 @@ mod tests {
     fn user_is_explicitly_included_in_context_match() {
         let mut segment = new_segment();
         segment.included_contexts.push(SegmentTarget {
-            values: vec!["foo".to_string()],
+            values: vec!["foo".to_string()].into(),
             context_kind: Kind::user(),
         });
         segment.included_contexts.push(SegmentTarget {
-            values: vec!["bar".to_string()],
+            values: vec!["bar".to_string()].into(),
             context_kind: Kind::user(),
         });
         let context = ContextBuilder::new("bar").build().unwrap();
@@ -637,7 +928,7 @@ mod tests {
     fn segment_include_target_does_not_match_with_mismatched_context() {
         let mut segment = new_segment();
         segment.included_contexts.push(SegmentTarget {
-            values: vec!["bar".to_string()],
+            values: vec!["bar".to_string()].into(),
             context_kind: Kind::from("org"),
         });
         let context = ContextBuilder::new("bar").build().unwrap();
@@ -650,11 +941,11 @@ mod tests {
             let mut segment = new_segment();
             segment.rules.push(jane_rule(None, None, kind));
             segment.excluded_contexts.push(SegmentTarget {
-                values: vec!["foo".to_string()],
+                values: vec!["foo".to_string()].into(),
                 context_kind: Kind::user(),
             });
             segment.excluded_contexts.push(SegmentTarget {
-                values: vec!["bar".to_string()],
+                values: vec!["bar".to_string()].into(),
                 context_kind: Kind::user(),
             });
             let jane = ContextBuilder::new("foo").name("Jane").build().unwrap();
@@ -665,7 +956,7 @@ mod tests {
         fn segment_does_not_match_if_no_includes_or_rules_match(kind in of(Just(Kind::user()))) {
             let mut segment = new_segment();
             segment.rules.push(jane_rule(None, None, kind));
-            segment.included.push("key".to_string());
+            segment.included = vec!["key".to_string()].into();
             let context = ContextBuilder::new("other-key")
                 .name("Bob")
                 .build()
@@ -712,4 +1003,241 @@ mod tests {
             assert_segment_match(&segment, context_z, false);
         }
     }
+
+    // A store holding a single big segment, along with the membership that the big segment store
+    // should report for it.
+    struct BigSegmentTestStore {
+        segment: Segment,
+        membership: Result<Option<bool>, String>,
+        status: BigSegmentsStatus,
+        configured: bool,
+        queries: Cell<usize>,
+    }
+
+    impl BigSegmentTestStore {
+        fn new(membership: Result<Option<bool>, String>) -> Self {
+            let mut segment = new_segment();
+            segment.unbounded = true;
+            BigSegmentTestStore {
+                segment,
+                membership,
+                status: BigSegmentsStatus::Healthy,
+                configured: true,
+                queries: Cell::new(0),
+            }
+        }
+
+        fn evaluate(&self, context: &Context) -> EvaluationResult<FlagValue> {
+            let flag = Flag::new_boolean_flag_with_segment_match(vec!["segkey"], Kind::user());
+            self.evaluate_flag(&flag, context)
+        }
+
+        fn evaluate_flag(&self, flag: &Flag, context: &Context) -> EvaluationResult<FlagValue> {
+            let result =
+                evaluate_with_options(self, flag, context, None, &EvaluationOptions::default());
+            EvaluationResult {
+                detail: result.detail.map(FlagValue::clone),
+                big_segments_status: result.big_segments_status,
+                evaluation_error: result.evaluation_error,
+            }
+        }
+    }
+
+    impl Store for BigSegmentTestStore {
+        fn flag(&self, _flag_key: &str) -> Option<Flag> {
+            None
+        }
+        fn segment(&self, segment_key: &str) -> Option<Segment> {
+            self.segment.segment(segment_key)
+        }
+        fn big_segment_store(&self) -> Option<&dyn BigSegmentStore> {
+            if self.configured {
+                Some(self)
+            } else {
+                None
+            }
+        }
+    }
+
+    impl BigSegmentStore for BigSegmentTestStore {
+        fn membership(&self, context_hash: &str) -> Result<HashMap<String, bool>, String> {
+            assert_eq!(big_segment_context_hash("foo"), context_hash);
+            self.queries.set(self.queries.get() + 1);
+            let membership = self.membership.clone()?;
+            Ok(membership
+                .map(|included| ("segkey.g1".to_string(), included))
+                .into_iter()
+                .collect())
+        }
+        fn status(&self) -> BigSegmentsStatus {
+            self.status
+        }
+    }
+
+    #[test]
+    fn user_is_included_in_big_segment() {
+        let store = BigSegmentTestStore::new(Ok(Some(true)));
+        let context = ContextBuilder::new("foo").build().unwrap();
+        let result = store.evaluate(&context);
+        assert_eq!(Some(FlagValue::Bool(true)), result.detail.value);
+        assert_eq!(Some(BigSegmentsStatus::Healthy), result.big_segments_status);
+    }
+
+    #[test]
+    fn user_is_excluded_from_big_segment_even_if_rule_matches() {
+        let mut store = BigSegmentTestStore::new(Ok(Some(false)));
+        store.segment.rules.push(jane_rule(None, None, None));
+        let context = ContextBuilder::new("foo").name("Jane").build().unwrap();
+        let result = store.evaluate(&context);
+        assert_eq!(Some(FlagValue::Bool(false)), result.detail.value);
+        assert_eq!(Some(BigSegmentsStatus::Healthy), result.big_segments_status);
+    }
+
+    #[test]
+    fn big_segment_falls_through_to_rules_if_membership_unknown() {
+        let mut store = BigSegmentTestStore::new(Ok(None));
+        store.segment.rules.push(jane_rule(None, None, None));
+        store.status = BigSegmentsStatus::Stale;
+        let context = ContextBuilder::new("foo").name("Jane").build().unwrap();
+        let result = store.evaluate(&context);
+        assert_eq!(Some(FlagValue::Bool(true)), result.detail.value);
+        assert_eq!(Some(BigSegmentsStatus::Stale), result.big_segments_status);
+    }
+
+    #[test]
+    fn big_segment_store_is_queried_once_per_context_key() {
+        let store = BigSegmentTestStore::new(Ok(None));
+        let mut flag = Flag::new_boolean_flag_with_segment_match(vec!["segkey"], Kind::user());
+        flag.rules.push(flag.rules[0].clone());
+        let context = ContextBuilder::new("foo").build().unwrap();
+        let result = store.evaluate_flag(&flag, &context);
+        assert_eq!(Some(FlagValue::Bool(false)), result.detail.value);
+        assert_eq!(1, store.queries.get());
+    }
+
+    #[test]
+    fn big_segment_ignores_included_list_in_segment() {
+        let mut store = BigSegmentTestStore::new(Ok(None));
+        store.segment.included = vec!["foo".to_string()].into();
+        let context = ContextBuilder::new("foo").build().unwrap();
+        let result = store.evaluate(&context);
+        assert_eq!(Some(FlagValue::Bool(false)), result.detail.value);
+    }
+
+    #[test]
+    fn big_segment_reports_store_error() {
+        let store = BigSegmentTestStore::new(Err("unavailable".to_string()));
+        let context = ContextBuilder::new("foo").build().unwrap();
+        let result = store.evaluate(&context);
+        assert_eq!(Some(FlagValue::Bool(false)), result.detail.value);
+        assert_eq!(
+            Some(BigSegmentsStatus::StoreError),
+            result.big_segments_status
+        );
+    }
+
+    #[test]
+    fn big_segment_falls_through_to_rules_on_store_error() {
+        let mut store = BigSegmentTestStore::new(Err("unavailable".to_string()));
+        store.segment.rules.push(jane_rule(None, None, None));
+        let context = ContextBuilder::new("foo").name("Jane").build().unwrap();
+        let result = store.evaluate(&context);
+        assert_eq!(Some(FlagValue::Bool(true)), result.detail.value);
+        assert_eq!(
+            Some(BigSegmentsStatus::StoreError),
+            result.big_segments_status
+        );
+    }
+
+    #[test]
+    fn big_segment_is_not_configured_without_store() {
+        let mut store = BigSegmentTestStore::new(Ok(Some(true)));
+        store.configured = false;
+        let context = ContextBuilder::new("foo").build().unwrap();
+        let result = store.evaluate(&context);
+        assert_eq!(Some(FlagValue::Bool(false)), result.detail.value);
+        assert_eq!(
+            Some(BigSegmentsStatus::NotConfigured),
+            result.big_segments_status
+        );
+    }
+
+    #[test]
+    fn big_segment_is_not_configured_without_generation() {
+        let mut store = BigSegmentTestStore::new(Ok(Some(true)));
+        store.segment.generation = None;
+        let context = ContextBuilder::new("foo").build().unwrap();
+        let result = store.evaluate(&context);
+        assert_eq!(Some(FlagValue::Bool(false)), result.detail.value);
+        assert_eq!(
+            Some(BigSegmentsStatus::NotConfigured),
+            result.big_segments_status
+        );
+    }
+
+    #[test]
+    fn big_segment_uses_unbounded_context_kind() {
+        let mut store = BigSegmentTestStore::new(Ok(Some(true)));
+        store.segment.unbounded_context_kind = Some(Kind::from("org"));
+        let user = ContextBuilder::new("foo").build().unwrap();
+        assert_eq!(
+            Some(FlagValue::Bool(false)),
+            store.evaluate(&user).detail.value
+        );
+
+        let org = ContextBuilder::new("foo").kind("org").build().unwrap();
+        assert_eq!(
+            Some(FlagValue::Bool(true)),
+            store.evaluate(&org).detail.value
+        );
+    }
+
+    #[test]
+    fn evaluation_without_big_segments_has_no_status() {
+        let mut segment = new_segment();
+        segment.included = vec!["foo".to_string()].into();
+        let flag = Flag::new_boolean_flag_with_segment_match(vec!["segkey"], Kind::user());
+        let context = ContextBuilder::new("foo").build().unwrap();
+        let result = evaluate_with_options(
+            &segment,
+            &flag,
+            &context,
+            None,
+            &EvaluationOptions::default(),
+        );
+        assert_eq!(None, result.big_segments_status);
+    }
+
+    #[test]
+    fn validate_reports_malformed_rules() {
+        let segment: Segment = serde_json::from_value(json!({
+            "key": "segment",
+            "included": [],
+            "excluded": [],
+            "rules": [
+                {"clauses": [{"attribute": "name", "op": "in", "values": ["Jane"]}]},
+                {
+                    "clauses": [{"attribute": "joined", "op": "before", "values": [true]}],
+                    "weight": 200000,
+                    "bucketBy": "/a/",
+                    "rolloutContextKind": "user"
+                }
+            ],
+            "salt": "salty",
+            "version": 1
+        }))
+        .unwrap();
+
+        let diagnostics = segment.validate();
+        assert_eq!(3, diagnostics.len());
+        assert_eq!(
+            Problem::WeightOutOfRange { weight: 200000.0 },
+            diagnostics[0].problem
+        );
+        assert_eq!("rules[1].bucketBy", diagnostics[1].location);
+        assert_eq!(
+            "rules[1].clauses[0]: value Bool(true) cannot be used with operator Before",
+            diagnostics[2].to_string()
+        );
+    }
 }
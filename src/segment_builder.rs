@@ -0,0 +1,273 @@
+use crate::contexts::context::Kind;
+use crate::rule::Clause;
+use crate::segment::{Segment, SegmentRule, SegmentTarget};
+use crate::validation::check_built;
+use crate::variation::VariationWeight;
+use crate::Reference;
+
+/// Contains methods for building a [Segment] with a specified key.
+///
+/// This is intended for constructing segments in tests and tools, without having to write out
+/// their JSON representation. If you do not change any values, the defaults for the [Segment] are:
+/// - its key is set to whatever value you passed to [SegmentBuilder::new]
+/// - its version is 1
+/// - it has no included or excluded keys, and no rules
+/// - it is not a big segment
+pub struct SegmentBuilder {
+    key: String,
+    version: u64,
+    included: Vec<String>,
+    excluded: Vec<String>,
+    included_contexts: Vec<SegmentTarget>,
+    excluded_contexts: Vec<SegmentTarget>,
+    rules: Vec<SegmentRule>,
+    salt: String,
+    unbounded: bool,
+    generation: Option<i64>,
+    unbounded_context_kind: Option<Kind>,
+}
+
+impl SegmentBuilder {
+    /// Create a new segment builder for a segment with the provided key.
+    pub fn new(key: impl Into<String>) -> Self {
+        Self {
+            key: key.into(),
+            version: 1,
+            included: Vec::new(),
+            excluded: Vec::new(),
+            included_contexts: Vec::new(),
+            excluded_contexts: Vec::new(),
+            rules: Vec::new(),
+            salt: String::new(),
+            unbounded: false,
+            generation: None,
+            unbounded_context_kind: None,
+        }
+    }
+
+    /// Sets the segment's version.
+    pub fn version(&mut self, version: u64) -> &mut Self {
+        self.version = version;
+        self
+    }
+
+    /// Adds user keys which are always matched by the segment.
+    pub fn add_included(&mut self, keys: impl IntoIterator<Item = impl Into<String>>) -> &mut Self {
+        self.included.extend(keys.into_iter().map(Into::into));
+        self
+    }
+
+    /// Adds user keys which are never matched by the segment, unless they are also included.
+    pub fn add_excluded(&mut self, keys: impl IntoIterator<Item = impl Into<String>>) -> &mut Self {
+        self.excluded.extend(keys.into_iter().map(Into::into));
+        self
+    }
+
+    /// Adds keys of contexts of the given kind which are always matched by the segment.
+    pub fn add_included_contexts(
+        &mut self,
+        kind: Kind,
+        keys: impl IntoIterator<Item = impl Into<String>>,
+    ) -> &mut Self {
+        self.included_contexts.push(SegmentTarget {
+            values: keys.into_iter().map(Into::into).collect::<Vec<_>>().into(),
+            context_kind: kind,
+        });
+        self
+    }
+
+    /// Adds keys of contexts of the given kind which are never matched by the segment, unless
+    /// they are also included.
+    pub fn add_excluded_contexts(
+        &mut self,
+        kind: Kind,
+        keys: impl IntoIterator<Item = impl Into<String>>,
+    ) -> &mut Self {
+        self.excluded_contexts.push(SegmentTarget {
+            values: keys.into_iter().map(Into::into).collect::<Vec<_>>().into(),
+            context_kind: kind,
+        });
+        self
+    }
+
+    /// Adds a rule which matches any context which matches all of the provided clauses.
+    ///
+    /// See [crate::ClauseBuilder] for constructing a clause.
+    pub fn add_rule(&mut self, clauses: Vec<Clause>) -> &mut Self {
+        self.rules.push(SegmentRule {
+            id: None,
+            clauses,
+            weight: None,
+            bucket_by: None,
+            rollout_context_kind: None,
+        });
+        self
+    }
+
+    /// Adds a rule which matches only a proportion of the contexts which match all of the provided
+    /// clauses.
+    ///
+    /// The proportion is given by `weight`, from 0 to 100000. Contexts of kind `kind` are bucketed
+    /// by the attribute `bucket_by`, or by their key if this is None.
+    pub fn add_weighted_rule(
+        &mut self,
+        clauses: Vec<Clause>,
+        weight: VariationWeight,
+        kind: Kind,
+        bucket_by: Option<Reference>,
+    ) -> &mut Self {
+        self.rules.push(SegmentRule {
+            id: None,
+            clauses,
+            weight: Some(weight),
+            bucket_by,
+            rollout_context_kind: Some(kind),
+        });
+        self
+    }
+
+    /// Sets the salt used when computing rollout buckets for the segment's rules.
+    pub fn salt(&mut self, salt: impl Into<String>) -> &mut Self {
+        self.salt = salt.into();
+        self
+    }
+
+    /// Makes this a big segment with the given generation. See [Segment::unbounded].
+    pub fn unbounded(&mut self, generation: i64) -> &mut Self {
+        self.unbounded = true;
+        self.generation = Some(generation);
+        self
+    }
+
+    /// Sets the kind of context whose key is used to look up membership of a big segment.
+    pub fn unbounded_context_kind(&mut self, kind: Kind) -> &mut Self {
+        self.unbounded_context_kind = Some(kind);
+        self
+    }
+
+    /// Creates a segment from the current builder properties.
+    ///
+    /// An error is returned if the key is empty or if [Segment::validate] finds any problems with
+    /// the segment, such as a rule whose weight is out of range.
+    pub fn build(&self) -> Result<Segment, String> {
+        if self.key.is_empty() {
+            return Err("segment key cannot be empty".to_string());
+        }
+
+        let segment = Segment {
+            key: self.key.clone(),
+            included: self.included.clone().into(),
+            excluded: self.excluded.clone().into(),
+            included_contexts: self.included_contexts.clone(),
+            excluded_contexts: self.excluded_contexts.clone(),
+            rules: self.rules.clone(),
+            salt: self.salt.clone(),
+            unbounded: self.unbounded,
+            generation: self.generation,
+            unbounded_context_kind: self.unbounded_context_kind.clone(),
+            version: self.version,
+        };
+
+        check_built("segment", &self.key, segment.validate())?;
+
+        Ok(segment)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eval::evaluate;
+    use crate::flag::Flag;
+    use crate::flag_value::FlagValue;
+    use crate::rule::Op;
+    use crate::store::Store;
+    use crate::{ClauseBuilder, ContextBuilder};
+    use assert_json_diff::assert_json_eq;
+    use serde_json::json;
+
+    // Treat a Segment as a Store containing only itself
+    struct SegmentStore(Segment);
+
+    impl Store for SegmentStore {
+        fn flag(&self, _flag_key: &str) -> Option<Flag> {
+            None
+        }
+        fn segment(&self, segment_key: &str) -> Option<Segment> {
+            (self.0.key == segment_key).then(|| self.0.clone())
+        }
+    }
+
+    fn matches(segment: Segment, context: &crate::Context) -> bool {
+        let flag = Flag::new_boolean_flag_with_segment_match(vec![&segment.key], Kind::user());
+        let store = SegmentStore(segment);
+        evaluate(&store, &flag, context, None).value == Some(&FlagValue::Bool(true))
+    }
+
+    #[test]
+    fn builds_segment_equivalent_to_json() {
+        let segment = SegmentBuilder::new("segment")
+            .version(3)
+            .salt("salty")
+            .add_included(vec!["alice"])
+            .add_excluded(vec!["bob"])
+            .add_included_contexts(Kind::from("org"), vec!["acme"])
+            .add_rule(vec![ClauseBuilder::new("name", Op::In)
+                .add_value("Carol")
+                .build()
+                .unwrap()])
+            .build()
+            .unwrap();
+
+        assert_json_eq!(
+            json!(segment),
+            json!({
+                "key": "segment",
+                "version": 3,
+                "included": ["alice"],
+                "excluded": ["bob"],
+                "includedContexts": [{"contextKind": "org", "values": ["acme"]}],
+                "excludedContexts": [],
+                "rules": [{
+                    "clauses": [{
+                        "contextKind": "user",
+                        "attribute": "name",
+                        "op": "in",
+                        "values": ["Carol"]
+                    }]
+                }],
+                "salt": "salty",
+                "unbounded": false,
+                "generation": null
+            })
+        );
+    }
+
+    #[test]
+    fn built_segment_can_be_evaluated() {
+        let mut builder = SegmentBuilder::new("segment");
+        builder
+            .add_included(vec!["alice"])
+            .add_excluded(vec!["bob"])
+            .add_rule(vec![ClauseBuilder::new("key", Op::StartsWith)
+                .add_value("b")
+                .build()
+                .unwrap()]);
+
+        let alice = ContextBuilder::new("alice").build().unwrap();
+        let bob = ContextBuilder::new("bob").build().unwrap();
+        let bill = ContextBuilder::new("bill").build().unwrap();
+        assert!(matches(builder.build().unwrap(), &alice));
+        assert!(!matches(builder.build().unwrap(), &bob));
+        assert!(matches(builder.build().unwrap(), &bill));
+    }
+
+    #[test]
+    fn build_rejects_invalid_weight_and_empty_key() {
+        assert!(SegmentBuilder::new("segment")
+            .add_weighted_rule(vec![], 100_001.0, Kind::user(), None)
+            .build()
+            .is_err());
+        assert!(SegmentBuilder::new("").build().is_err());
+    }
+}
@@ -1,3 +1,6 @@
+use std::sync::Arc;
+
+use crate::big_segment::BigSegmentStore;
 use crate::flag::Flag;
 use crate::segment::Segment;
 
@@ -6,10 +9,69 @@ use crate::segment::Segment;
 ///
 /// Ordinarily, the only implementations of this interface are the default in-memory
 /// implementation, which holds references to actual SDK data model objects.
+///
+/// The evaluator retrieves flags and segments through [Store::shared_flag] and
+/// [Store::shared_segment]. By default these wrap the owned values returned by [Store::flag] and
+/// [Store::segment], so existing implementations continue to work unchanged. Implementations which
+/// already hold their data in an [Arc] should override them, so that looking up a prerequisite
+/// flag or a segment during evaluation does not require cloning it.
 pub trait Store {
     /// Retrieve the flag with key `flag_key`.
     fn flag(&self, flag_key: &str) -> Option<Flag>;
 
     /// Retrieve the segment with key `segment_key`.
     fn segment(&self, segment_key: &str) -> Option<Segment>;
+
+    /// Retrieve the keys of all flags currently held by the store, or None if the store cannot
+    /// list its flags.
+    ///
+    /// This is used by [crate::evaluate_all] to enumerate every flag in the store. The default
+    /// implementation returns None, in which case [crate::evaluate_all] returns a
+    /// [crate::FlagsState] which is not valid, rather than an empty one.
+    fn flag_keys(&self) -> Option<Vec<String>> {
+        None
+    }
+
+    /// Retrieve the keys of all segments currently held by the store, or None if the store cannot
+    /// list its segments.
+    ///
+    /// This is used by [crate::DependencyGraph] to include segments which no flag refers to. The
+    /// default implementation returns None, in which case only segments reachable from a flag are
+    /// included.
+    fn segment_keys(&self) -> Option<Vec<String>> {
+        None
+    }
+
+    /// Returns true if the store has received its data.
+    ///
+    /// [crate::evaluate_all] does not evaluate the flags of a store which is not initialized, and
+    /// instead returns a [crate::FlagsState] which is not valid. The default implementation
+    /// returns true.
+    fn is_initialized(&self) -> bool {
+        true
+    }
+
+    /// Retrieve a shared reference to the flag with key `flag_key`.
+    ///
+    /// The default implementation wraps the result of [Store::flag], so it still copies the flag
+    /// on every call if the store does.
+    fn shared_flag(&self, flag_key: &str) -> Option<Arc<Flag>> {
+        self.flag(flag_key).map(Arc::new)
+    }
+
+    /// Retrieve a shared reference to the segment with key `segment_key`.
+    ///
+    /// The default implementation wraps the result of [Store::segment], so it still copies the
+    /// segment on every call if the store does.
+    fn shared_segment(&self, segment_key: &str) -> Option<Arc<Segment>> {
+        self.segment(segment_key).map(Arc::new)
+    }
+
+    /// Retrieve the store holding big segment membership lists, if one is configured.
+    ///
+    /// If this returns None, any evaluation which references a big segment will report
+    /// [crate::BigSegmentsStatus::NotConfigured].
+    fn big_segment_store(&self) -> Option<&dyn BigSegmentStore> {
+        None
+    }
 }
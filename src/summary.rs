@@ -0,0 +1,358 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use serde::Serialize;
+
+use crate::clock::Clock;
+use crate::eval::{Detail, Error, SharedPrerequisiteEvent};
+use crate::events::{unix_millis, OutputEvent};
+use crate::flag::Flag;
+use crate::flag_value::FlagValue;
+use crate::util::is_false;
+use crate::variation::VariationIndex;
+use crate::Context;
+
+/// SummaryEvent counts the evaluations of every flag over a period of time, in an
+/// [OutputEvent::Summary] event. It is produced by [EventSummarizer::flush].
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SummaryEvent {
+    /// When the first evaluation was counted, as a Unix millisecond timestamp.
+    pub start_date: u64,
+    /// When the last evaluation was counted, as a Unix millisecond timestamp.
+    pub end_date: u64,
+    /// The evaluations of each flag, by flag key.
+    pub features: BTreeMap<String, FlagSummary>,
+}
+
+/// FlagSummary counts the evaluations of a single flag, in a [SummaryEvent].
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FlagSummary {
+    /// The default value supplied by the application. This is None if the flag was only
+    /// evaluated as a prerequisite.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default: Option<FlagValue>,
+    /// Every kind of context the flag was evaluated for.
+    pub context_kinds: BTreeSet<String>,
+    /// The number of evaluations for each combination of flag version and result.
+    pub counters: Vec<FlagCounter>,
+}
+
+/// FlagCounter is the number of evaluations of a flag which had the same result, in a
+/// [FlagSummary].
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FlagCounter {
+    /// The value which was returned.
+    pub value: Option<FlagValue>,
+    /// The index of the variation which was returned.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub variation: Option<VariationIndex>,
+    /// The version of the flag. This is None if the flag was not found.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<u64>,
+    /// The number of evaluations.
+    pub count: u64,
+    /// True if the flag was not found, so the application's default value was returned.
+    #[serde(skip_serializing_if = "is_false")]
+    pub unknown: bool,
+}
+
+// The counters for one flag, keyed by version and variation, as they are being collected.
+#[derive(Clone, Debug, Default)]
+struct FlagCounters {
+    default: Option<FlagValue>,
+    context_kinds: BTreeSet<String>,
+    counters: BTreeMap<(Option<u64>, Option<VariationIndex>), FlagCounter>,
+}
+
+/// EventSummarizer counts flag evaluations between flushes, for the summary events which SDKs
+/// send periodically.
+///
+/// Every evaluation should be counted, whether or not it also produced feature or debug events;
+/// see [crate::EventOptions::evaluation_events].
+#[derive(Clone, Debug, Default)]
+pub struct EventSummarizer {
+    start_date: u64,
+    end_date: u64,
+    features: HashMap<String, FlagCounters>,
+}
+
+impl EventSummarizer {
+    /// Create a summarizer which has not counted any evaluations.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count a single call to [crate::evaluate] at the time given by `clock`, given the flag,
+    /// context and result of the evaluation, the default value supplied by the application, and the
+    /// prerequisite evaluations reported to its [crate::PrerequisiteEventRecorder::record_shared].
+    ///
+    /// The summary reports the default value most recently supplied for each flag.
+    pub fn summarize(
+        &mut self,
+        flag: &Flag,
+        context: &Context,
+        detail: &Detail<FlagValue>,
+        default: &FlagValue,
+        prerequisites: &[SharedPrerequisiteEvent],
+        clock: &dyn Clock,
+    ) {
+        let now = unix_millis(clock.now());
+        for prerequisite in prerequisites {
+            self.count(
+                &prerequisite.prerequisite_flag.key,
+                Some(prerequisite.prerequisite_flag.version),
+                &prerequisite.context,
+                &prerequisite.prerequisite_result,
+                None,
+                now,
+            );
+        }
+        self.count(
+            &flag.key,
+            Some(flag.version),
+            context,
+            detail,
+            Some(default),
+            now,
+        );
+    }
+
+    /// Count an evaluation of a flag which was not found, at the time given by `clock`, so the
+    /// application's default value was returned.
+    pub fn summarize_unknown_flag(
+        &mut self,
+        flag_key: &str,
+        context: &Context,
+        default: &FlagValue,
+        clock: &dyn Clock,
+    ) {
+        let detail = Detail::err_default(Error::FlagNotFound, default.clone());
+        let now = unix_millis(clock.now());
+        self.count(flag_key, None, context, &detail, Some(default), now);
+    }
+
+    /// Returns true if no evaluations have been counted since the summarizer was created or last
+    /// flushed.
+    pub fn is_empty(&self) -> bool {
+        self.features.is_empty()
+    }
+
+    /// Return a summary event for the evaluations counted so far, and reset the summarizer.
+    ///
+    /// Returns None if no evaluations have been counted, since there is nothing to send.
+    pub fn flush(&mut self) -> Option<OutputEvent> {
+        if self.is_empty() {
+            return None;
+        }
+
+        let summarizer = std::mem::take(self);
+        let features = summarizer
+            .features
+            .into_iter()
+            .map(|(key, flag)| {
+                let summary = FlagSummary {
+                    default: flag.default,
+                    context_kinds: flag.context_kinds,
+                    counters: flag.counters.into_values().collect(),
+                };
+                (key, summary)
+            })
+            .collect();
+
+        Some(OutputEvent::Summary(SummaryEvent {
+            start_date: summarizer.start_date,
+            end_date: summarizer.end_date,
+            features,
+        }))
+    }
+
+    /// Discard every evaluation counted so far.
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    fn count(
+        &mut self,
+        flag_key: &str,
+        version: Option<u64>,
+        context: &Context,
+        detail: &Detail<FlagValue>,
+        default: Option<&FlagValue>,
+        now: u64,
+    ) {
+        if self.is_empty() || now < self.start_date {
+            self.start_date = now;
+        }
+        self.end_date = self.end_date.max(now);
+
+        let flag = self.features.entry(flag_key.to_string()).or_default();
+        if let Some(default) = default {
+            flag.default = Some(default.clone());
+        }
+        flag.context_kinds
+            .extend(context.kinds().into_iter().map(|kind| kind.to_string()));
+        flag.counters
+            .entry((version, detail.variation_index))
+            .or_insert_with(|| FlagCounter {
+                value: detail.value.clone(),
+                variation: detail.variation_index,
+                version,
+                count: 0,
+                unknown: version.is_none(),
+            })
+            .count += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eval::evaluate;
+    use crate::store::Store;
+    use crate::test_common::{InMemoryPrerequisiteEventRecorder, TestStore};
+    use crate::{ContextBuilder, FixedClock, MultiContextBuilder};
+    use assert_json_diff::assert_json_eq;
+    use chrono::{TimeZone, Utc};
+    use serde_json::json;
+    use std::cell::RefCell;
+
+    fn at(millis: i64) -> FixedClock {
+        FixedClock(Utc.timestamp_millis_opt(millis).unwrap())
+    }
+
+    #[test]
+    fn empty_summarizer_flushes_nothing() {
+        let mut summarizer = EventSummarizer::new();
+        assert!(summarizer.is_empty());
+        assert!(summarizer.flush().is_none());
+    }
+
+    #[test]
+    fn counts_evaluations_by_version_and_variation() {
+        let mut store = TestStore::new();
+        let alice = ContextBuilder::new("alice").build().unwrap();
+        let org = ContextBuilder::new("acme").kind("org").build().unwrap();
+        let multi = MultiContextBuilder::new()
+            .add_context(alice.clone())
+            .add_context(org)
+            .build()
+            .unwrap();
+
+        let mut summarizer = EventSummarizer::new();
+        let mut summarize = |store: &TestStore, context: &Context, now: i64| {
+            let flag = store.flag("flag").unwrap();
+            let detail = evaluate(store, &flag, context, None).map(|value| value.clone());
+            summarizer.summarize(
+                &flag,
+                context,
+                &detail,
+                &FlagValue::Bool(true),
+                &[],
+                &at(now),
+            );
+        };
+        summarize(&store, &alice, 2000);
+        summarize(&store, &multi, 1000);
+        store.update_flag("flag", |flag| {
+            flag.on = true;
+            flag.version = 43;
+        });
+        summarize(&store, &alice, 3000);
+        summarizer.summarize_unknown_flag(
+            "missing",
+            &alice,
+            &FlagValue::Str("x".into()),
+            &at(1500),
+        );
+
+        let event = serde_json::to_value(summarizer.flush().unwrap()).unwrap();
+        assert_json_eq!(
+            event,
+            json!({
+                "kind": "summary",
+                "startDate": 1000,
+                "endDate": 3000,
+                "features": {
+                    "flag": {
+                        "default": true,
+                        "contextKinds": ["org", "user"],
+                        "counters": [
+                            {"value": false, "variation": 0, "version": 42, "count": 2},
+                            {"value": true, "variation": 1, "version": 43, "count": 1}
+                        ]
+                    },
+                    "missing": {
+                        "default": "x",
+                        "contextKinds": ["user"],
+                        "counters": [{"value": "x", "count": 1, "unknown": true}]
+                    }
+                }
+            })
+        );
+        assert!(summarizer.is_empty());
+    }
+
+    #[test]
+    fn counts_prerequisites_without_a_default() {
+        let store = TestStore::new();
+        let flag = store.flag("flagWithSatisfiedPrereq").unwrap();
+        let alice = ContextBuilder::new("alice").build().unwrap();
+        let recorder = InMemoryPrerequisiteEventRecorder {
+            events: RefCell::new(Vec::new()),
+        };
+        let detail = evaluate(&store, &flag, &alice, Some(&recorder)).map(|value| value.clone());
+
+        let mut summarizer = EventSummarizer::new();
+        summarizer.summarize(
+            &flag,
+            &alice,
+            &detail,
+            &FlagValue::Bool(false),
+            &recorder.events.into_inner(),
+            &at(1000),
+        );
+
+        match summarizer.flush() {
+            Some(OutputEvent::Summary(summary)) => {
+                assert_eq!(None, summary.features["prereq"].default);
+                assert_eq!(1, summary.features["prereq"].counters[0].count);
+                assert_eq!(
+                    Some(FlagValue::Bool(false)),
+                    summary.features["flagWithSatisfiedPrereq"].default
+                );
+            }
+            other => panic!("unexpected event {:?}", other),
+        }
+    }
+
+    #[test]
+    fn reports_the_latest_default() {
+        let mut summarizer = EventSummarizer::new();
+        let alice = ContextBuilder::new("alice").build().unwrap();
+        summarizer.summarize_unknown_flag("missing", &alice, &FlagValue::Bool(false), &at(1000));
+        summarizer.summarize_unknown_flag("missing", &alice, &FlagValue::Bool(true), &at(2000));
+
+        match summarizer.flush() {
+            Some(OutputEvent::Summary(summary)) => {
+                let flag = &summary.features["missing"];
+                assert_eq!(Some(FlagValue::Bool(true)), flag.default);
+                assert_eq!(2, flag.counters[0].count);
+            }
+            other => panic!("unexpected event {:?}", other),
+        }
+    }
+
+    #[test]
+    fn reset_discards_counts() {
+        let mut summarizer = EventSummarizer::new();
+        let alice = ContextBuilder::new("alice").build().unwrap();
+        summarizer.summarize_unknown_flag("missing", &alice, &FlagValue::Bool(false), &at(1000));
+        assert!(!summarizer.is_empty());
+
+        summarizer.reset();
+        assert!(summarizer.is_empty());
+        assert!(summarizer.flush().is_none());
+    }
+}
@@ -5,19 +5,21 @@ use crate::segment::Segment;
 use crate::store::Store;
 use crate::PrerequisiteEvent;
 use crate::PrerequisiteEventRecorder;
+use crate::SharedPrerequisiteEvent;
 use maplit::hashmap;
 use std::cell::RefCell;
 use std::collections::HashMap;
+use std::sync::Arc;
 
 pub struct TestStore {
-    flags: HashMap<String, Flag>,
-    segments: HashMap<String, Segment>,
+    flags: HashMap<String, Arc<Flag>>,
+    segments: HashMap<String, Arc<Segment>>,
 }
 
 impl TestStore {
     pub fn new() -> Self {
-        Self {
-            flags: hashmap! {
+        Self::from_maps(
+            hashmap! {
                 "flag".to_string() => serde_json::from_str(r#"{
                         "key": "flag",
                         "version": 42,
@@ -663,7 +665,7 @@ impl TestStore {
                         "salt": "salty"
                     }"#).unwrap(),
             },
-            segments: hashmap! {
+            hashmap! {
                 "segment".to_string() => serde_json::from_str(r#"{
                         "key": "segment",
                         "included": ["alice"],
@@ -677,38 +679,130 @@ impl TestStore {
                         "version": 1
                     }"#).unwrap()
             },
-        }
+        )
+    }
+
+    // Flags with differing client-side availability and event tracking, for evaluate_all and
+    // client-side bootstrap tests.
+    pub fn new_flags_state() -> Self {
+        Self::new_from_json_str(
+            r#"{
+                "tracked": {
+                    "key": "tracked",
+                    "version": 3,
+                    "on": true,
+                    "targets": [],
+                    "rules": [],
+                    "prerequisites": [],
+                    "fallthrough": {"variation": 1},
+                    "offVariation": 0,
+                    "variations": ["a", "b"],
+                    "clientSideAvailability": {
+                        "usingEnvironmentId": true,
+                        "usingMobileKey": true
+                    },
+                    "salt": "salty",
+                    "trackEvents": true,
+                    "trackEventsFallthrough": true
+                },
+                "untracked": {
+                    "key": "untracked",
+                    "version": 7,
+                    "on": false,
+                    "targets": [],
+                    "rules": [],
+                    "prerequisites": [],
+                    "fallthrough": {"variation": 1},
+                    "offVariation": 0,
+                    "variations": [false, true],
+                    "clientSideAvailability": {
+                        "usingEnvironmentId": true,
+                        "usingMobileKey": true
+                    },
+                    "salt": "salty"
+                },
+                "server-only": {
+                    "key": "server-only",
+                    "version": 1,
+                    "on": false,
+                    "targets": [],
+                    "rules": [],
+                    "prerequisites": [],
+                    "fallthrough": {"variation": 0},
+                    "offVariation": 0,
+                    "variations": [1],
+                    "clientSideAvailability": {
+                        "usingEnvironmentId": false,
+                        "usingMobileKey": true
+                    },
+                    "salt": "salty"
+                }
+            }"#,
+            "{}",
+        )
     }
 
     pub fn new_from_json_str(flag_json: &str, segment_json: &str) -> Self {
-        let flags = serde_json::from_str(flag_json).unwrap();
-        let segments = serde_json::from_str(segment_json).unwrap();
+        Self::from_maps(
+            serde_json::from_str(flag_json).unwrap(),
+            serde_json::from_str(segment_json).unwrap(),
+        )
+    }
 
-        Self { flags, segments }
+    fn from_maps(flags: HashMap<String, Flag>, segments: HashMap<String, Segment>) -> Self {
+        Self {
+            flags: flags.into_iter().map(|(k, v)| (k, Arc::new(v))).collect(),
+            segments: segments
+                .into_iter()
+                .map(|(k, v)| (k, Arc::new(v)))
+                .collect(),
+        }
     }
 
     pub fn update_flag(&mut self, flag_key: &str, fun: fn(&mut Flag) -> ()) {
         let flag = self.flags.get_mut(flag_key).unwrap();
-        fun(flag);
+        fun(Arc::make_mut(flag));
     }
 }
 
 impl Store for TestStore {
     fn flag(&self, flag_key: &str) -> Option<Flag> {
-        self.flags.get(flag_key).cloned()
+        self.flags.get(flag_key).map(|flag| flag.as_ref().clone())
     }
 
     fn segment(&self, segment_key: &str) -> Option<Segment> {
+        self.segments
+            .get(segment_key)
+            .map(|segment| segment.as_ref().clone())
+    }
+
+    fn flag_keys(&self) -> Option<Vec<String>> {
+        Some(self.flags.keys().cloned().collect())
+    }
+
+    fn segment_keys(&self) -> Option<Vec<String>> {
+        Some(self.segments.keys().cloned().collect())
+    }
+
+    fn shared_flag(&self, flag_key: &str) -> Option<Arc<Flag>> {
+        self.flags.get(flag_key).cloned()
+    }
+
+    fn shared_segment(&self, segment_key: &str) -> Option<Arc<Segment>> {
         self.segments.get(segment_key).cloned()
     }
 }
 
 pub struct InMemoryPrerequisiteEventRecorder {
-    pub events: RefCell<Vec<PrerequisiteEvent>>,
+    pub events: RefCell<Vec<SharedPrerequisiteEvent>>,
 }
 
 impl PrerequisiteEventRecorder for InMemoryPrerequisiteEventRecorder {
     fn record(&self, event: PrerequisiteEvent) {
+        self.events.borrow_mut().push(event.into());
+    }
+
+    fn record_shared(&self, event: SharedPrerequisiteEvent) {
         self.events.borrow_mut().push(event);
     }
 }
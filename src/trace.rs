@@ -0,0 +1,346 @@
+use serde::Serialize;
+
+use crate::contexts::context::Kind;
+use crate::eval::Reason;
+use crate::flag_value::FlagValue;
+use crate::variation::VariationIndex;
+use crate::{AttributeValue, Reference};
+
+/// EvaluationTrace is a structured record of every step taken while evaluating a flag.
+///
+/// A trace is collected by passing it to [crate::evaluate_with_trace], or to
+/// [crate::evaluate_with_trace_and_options]. It is intended to explain why an evaluation produced a
+/// particular result, and can be serialized to JSON for inspection. The steps of each evaluation
+/// are appended to the trace, so a single trace may be reused to record several evaluations.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct EvaluationTrace {
+    steps: Vec<TraceStep>,
+
+    // Steps which have been started but not yet finished, innermost last.
+    #[serde(skip)]
+    open: Vec<TraceStep>,
+}
+
+impl EvaluationTrace {
+    /// Create an empty trace.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Retrieve the top-level steps recorded in this trace.
+    pub fn steps(&self) -> &[TraceStep] {
+        &self.steps
+    }
+
+    /// Remove all recorded steps from this trace.
+    pub fn clear(&mut self) {
+        self.steps.clear();
+        self.open.clear();
+    }
+
+    // Start a step which may contain nested steps. It will be attached to its parent when it is
+    // finished with a call to end.
+    pub(crate) fn begin(&mut self, event: TraceEvent) {
+        self.open.push(TraceStep {
+            event,
+            steps: Vec::new(),
+        });
+    }
+
+    // Finish the innermost open step, giving the caller a chance to record the outcome of the step.
+    pub(crate) fn end<F: FnOnce(&mut TraceEvent)>(&mut self, f: F) {
+        if let Some(mut step) = self.open.pop() {
+            f(&mut step.event);
+            self.attach(step);
+        }
+    }
+
+    // Record a step which cannot contain any nested steps.
+    pub(crate) fn record(&mut self, event: TraceEvent) {
+        self.attach(TraceStep {
+            event,
+            steps: Vec::new(),
+        });
+    }
+
+    fn attach(&mut self, step: TraceStep) {
+        match self.open.last_mut() {
+            Some(parent) => parent.steps.push(step),
+            None => self.steps.push(step),
+        }
+    }
+}
+
+/// TraceStep is a single node in an [EvaluationTrace].
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct TraceStep {
+    /// The event described by this step.
+    #[serde(flatten)]
+    pub event: TraceEvent,
+
+    /// The steps that were taken while processing this one, in order. For instance, the steps of a
+    /// [TraceEvent::Rule] are the clauses that were checked.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub steps: Vec<TraceStep>,
+}
+
+/// TraceEvent describes what happened during a single [TraceStep].
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE", tag = "kind")]
+pub enum TraceEvent {
+    /// A flag was evaluated. This is either the flag passed to [crate::evaluate_with_trace], or one
+    /// of its prerequisites.
+    #[serde(rename_all = "camelCase")]
+    Flag {
+        /// The key of the flag.
+        key: String,
+        /// The version of the flag.
+        version: u64,
+        /// The value the flag evaluated to, if any.
+        value: Option<FlagValue>,
+        /// The index of the variation the flag evaluated to, if any.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        variation: Option<VariationIndex>,
+        /// The reason for the result of the evaluation.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        reason: Option<Reason>,
+    },
+    /// A prerequisite of a flag was checked. If the prerequisite flag exists, its evaluation is
+    /// recorded as a nested [TraceEvent::Flag].
+    #[serde(rename_all = "camelCase")]
+    Prerequisite {
+        /// The key of the prerequisite flag.
+        key: String,
+        /// The variation the prerequisite flag is required to return.
+        variation: VariationIndex,
+        /// True if the prerequisite was met.
+        satisfied: bool,
+    },
+    /// A list of individually targeted context keys was checked.
+    #[serde(rename_all = "camelCase")]
+    Target {
+        /// The kind of context the target list applies to.
+        context_kind: Kind,
+        /// The variation returned if the context is in the list.
+        variation: VariationIndex,
+        /// True if the context key was found in the list.
+        matched: bool,
+    },
+    /// A flag rule was checked. Its clauses are recorded as nested [TraceEvent::Clause] steps.
+    #[serde(rename_all = "camelCase")]
+    Rule {
+        /// Zero-based index of the rule within the flag.
+        rule_index: usize,
+        /// The id of the rule.
+        #[serde(skip_serializing_if = "String::is_empty")]
+        rule_id: String,
+        /// True if every clause of the rule matched.
+        matched: bool,
+    },
+    /// A clause was checked. For a segment match clause, each segment is recorded as a nested
+    /// [TraceEvent::Segment] step.
+    #[serde(rename_all = "camelCase")]
+    Clause {
+        /// Zero-based index of the clause within its rule.
+        clause_index: usize,
+        /// The attribute the clause tests.
+        attribute: Reference,
+        /// The name of the clause's operator.
+        op: String,
+        /// The value of the clause's attribute in the context, if any.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        context_value: Option<AttributeValue>,
+        /// The clause values which matched the context value, before applying negation. This is
+        /// empty for a segment match clause, and for operators which apply to an array as a whole.
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        matched_values: Vec<AttributeValue>,
+        /// True if the clause matched, after applying negation.
+        matched: bool,
+    },
+    /// Membership of a segment was checked. The segment's rules are recorded as nested
+    /// [TraceEvent::SegmentRule] steps.
+    #[serde(rename_all = "camelCase")]
+    Segment {
+        /// The key of the segment.
+        key: String,
+        /// How membership was decided. This is None if the segment could not be evaluated.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        decision: Option<SegmentDecision>,
+    },
+    /// A segment rule was checked.
+    #[serde(rename_all = "camelCase")]
+    SegmentRule {
+        /// Zero-based index of the rule within the segment.
+        rule_index: usize,
+        /// The id of the rule.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        rule_id: Option<String>,
+        /// True if the context matched the rule.
+        matched: bool,
+    },
+    /// A context was assigned to a bucket for a percentage rollout.
+    #[serde(rename_all = "camelCase")]
+    Bucket {
+        /// The kind of context that was bucketed.
+        context_kind: Kind,
+        /// The attribute the context was bucketed by, if it was not the key.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        bucket_by: Option<Reference>,
+        /// The computed bucket value, from 0 (inclusive) to 1 (exclusive).
+        bucket: f32,
+    },
+}
+
+/// SegmentDecision describes how membership of a segment was decided.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum SegmentDecision {
+    /// The context key was explicitly included in the segment.
+    Included,
+    /// The context key was explicitly excluded from the segment.
+    Excluded,
+    /// The context matched one of the segment's rules.
+    RuleMatch,
+    /// The context did not match the segment.
+    NoMatch,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eval::evaluate_with_trace;
+    use crate::store::Store;
+    use crate::test_common::TestStore;
+    use crate::ContextBuilder;
+    use assert_json_diff::assert_json_eq;
+    use serde_json::json;
+
+    #[test]
+    fn nested_steps_are_attached_to_their_parent() {
+        let mut trace = EvaluationTrace::new();
+        trace.begin(TraceEvent::Prerequisite {
+            key: "prereq".into(),
+            variation: 1,
+            satisfied: false,
+        });
+        trace.record(TraceEvent::Target {
+            context_kind: Kind::user(),
+            variation: 0,
+            matched: false,
+        });
+        trace.end(|event| {
+            if let TraceEvent::Prerequisite { satisfied, .. } = event {
+                *satisfied = true;
+            }
+        });
+
+        assert_json_eq!(
+            json!(trace),
+            json!({
+                "steps": [{
+                    "kind": "PREREQUISITE",
+                    "key": "prereq",
+                    "variation": 1,
+                    "satisfied": true,
+                    "steps": [{
+                        "kind": "TARGET",
+                        "contextKind": "user",
+                        "variation": 0,
+                        "matched": false
+                    }]
+                }]
+            })
+        );
+    }
+
+    #[test]
+    fn trace_records_prerequisites_targets_and_rules() {
+        let store = TestStore::new();
+        let flag = store.flag("flagWithSatisfiedPrereq").unwrap();
+        let alice = ContextBuilder::new("alice").build().unwrap();
+
+        let mut trace = EvaluationTrace::new();
+        let detail = evaluate_with_trace(&store, &flag, &alice, None, &mut trace);
+
+        assert_eq!(1, trace.steps().len());
+        let root = &trace.steps()[0];
+        match &root.event {
+            TraceEvent::Flag { key, reason, .. } => {
+                assert_eq!(&flag.key, key);
+                assert_eq!(reason.as_ref(), Some(&detail.reason));
+            }
+            other => panic!("unexpected root step {:?}", other),
+        }
+
+        match &root.steps[0].event {
+            TraceEvent::Prerequisite { key, satisfied, .. } => {
+                assert_eq!("prereq", key);
+                assert!(*satisfied);
+            }
+            other => panic!("unexpected first step {:?}", other),
+        }
+        assert!(matches!(
+            root.steps[0].steps[0].event,
+            TraceEvent::Flag { .. }
+        ));
+    }
+
+    #[test]
+    fn trace_records_clause_values_and_segments() {
+        let store = TestStore::new();
+        let flag = store.flag("flagWithSegmentMatchRule").unwrap();
+        let alice = ContextBuilder::new("alice").build().unwrap();
+
+        let mut trace = EvaluationTrace::new();
+        evaluate_with_trace(&store, &flag, &alice, None, &mut trace);
+
+        let root = &trace.steps()[0];
+        let rule = root
+            .steps
+            .iter()
+            .find(|step| matches!(step.event, TraceEvent::Rule { .. }))
+            .expect("rule should be traced");
+        assert!(matches!(rule.event, TraceEvent::Rule { matched: true, .. }));
+
+        let clause = &rule.steps[0];
+        assert!(matches!(
+            clause.event,
+            TraceEvent::Clause { matched: true, .. }
+        ));
+        assert_eq!(
+            clause.steps[0].event,
+            TraceEvent::Segment {
+                key: "segment".into(),
+                decision: Some(SegmentDecision::Included),
+            }
+        );
+    }
+
+    #[test]
+    fn trace_records_clause_values_which_matched() {
+        let store = TestStore::new();
+        let mut flag = store.flag("flagWithInRule").unwrap();
+        flag.on = true;
+        let context = ContextBuilder::new("alice")
+            .set_value("team", "Avengers".into())
+            .build()
+            .unwrap();
+
+        let mut trace = EvaluationTrace::new();
+        evaluate_with_trace(&store, &flag, &context, None, &mut trace);
+
+        let rule = &trace.steps()[0].steps[0];
+        assert_json_eq!(
+            json!(rule.steps[0]),
+            json!({
+                "kind": "CLAUSE",
+                "clauseIndex": 0,
+                "attribute": "team",
+                "op": "in",
+                "contextValue": "Avengers",
+                "matchedValues": ["Avengers"],
+                "matched": true
+            })
+        );
+    }
+}
@@ -1,3 +1,10 @@
+use std::collections::HashSet;
+use std::net::IpAddr;
+
+use chrono::{Duration, NaiveTime, Timelike};
+use semver::{Version, VersionReq};
+use serde::{Deserialize, Serialize, Serializer};
+
 const FLOAT_TO_INT_MAX: f64 = 9007199254740991_f64;
 
 /// Converting float to int has undefined behaviour for huge floats: https://stackoverflow.com/a/41139453.
@@ -15,3 +22,353 @@ pub(crate) fn f64_to_i64_safe(f: f64) -> Option<i64> {
 pub(crate) fn is_false(b: &bool) -> bool {
     !(*b)
 }
+
+/// KeySet is a list of context keys which can also be searched without scanning the list.
+///
+/// It serializes as a plain list of strings, preserving the original order of the keys.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(from = "Vec<String>")]
+pub(crate) struct KeySet {
+    keys: Vec<String>,
+    lookup: HashSet<String>,
+}
+
+impl KeySet {
+    pub(crate) fn contains(&self, key: &str) -> bool {
+        self.lookup.contains(key)
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    pub(crate) fn as_slice(&self) -> &[String] {
+        &self.keys
+    }
+}
+
+impl From<Vec<String>> for KeySet {
+    fn from(keys: Vec<String>) -> Self {
+        let lookup = keys.iter().cloned().collect();
+        Self { keys, lookup }
+    }
+}
+
+impl Serialize for KeySet {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.keys.serialize(serializer)
+    }
+}
+
+impl PartialEq for KeySet {
+    fn eq(&self, other: &Self) -> bool {
+        self.keys == other.keys
+    }
+}
+
+/// IpNetwork is a range of IPv4 or IPv6 addresses, written in CIDR notation such as `10.0.0.0/8`.
+/// An address without a prefix length is a network containing only that address.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct IpNetwork {
+    address: IpAddr,
+    prefix_len: u32,
+}
+
+impl IpNetwork {
+    pub(crate) fn parse(s: &str) -> Option<Self> {
+        let (address, prefix_len) = match s.trim().split_once('/') {
+            Some((address, prefix_len)) => (address, Some(prefix_len)),
+            None => (s.trim(), None),
+        };
+        let address: IpAddr = address.parse().ok()?;
+        let max_prefix_len = match address {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        let prefix_len = match prefix_len {
+            Some(prefix_len) if prefix_len.bytes().all(|b| b.is_ascii_digit()) => {
+                prefix_len.parse().ok()?
+            }
+            Some(_) => return None,
+            None => max_prefix_len,
+        };
+
+        (prefix_len <= max_prefix_len).then(|| Self {
+            address,
+            prefix_len,
+        })
+    }
+
+    pub(crate) fn contains(&self, address: IpAddr) -> bool {
+        match (self.address, address) {
+            (IpAddr::V4(network), IpAddr::V4(address)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix_len).unwrap_or(0);
+                u32::from(network) & mask == u32::from(address) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(address)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix_len).unwrap_or(0);
+                u128::from(network) & mask == u128::from(address) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// SemVerRange is a set of semantic versions described by an npm or Cargo style requirement, such
+/// as `^2.3`, `>=1.2.0 <2.0.0`, `>=1.2.0, <2.0.0`, `1.2.3 - 1.4` or `~1.2 || ^3`.
+///
+/// A version without an operator is treated as npm treats it, in either style: `1.2.3` means
+/// exactly that version, and a partial version such as `1.2` means any `1.2.x` version, rather
+/// than Cargo's `^1.2`. As with both npm and Cargo, a pre-release version is only in the range if
+/// one of the comparators names a pre-release of the same major, minor and patch version.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct SemVerRange(Vec<VersionReq>);
+
+impl SemVerRange {
+    pub(crate) fn parse(s: &str) -> Option<Self> {
+        s.split("||")
+            .map(parse_version_req)
+            .collect::<Option<_>>()
+            .map(Self)
+    }
+
+    pub(crate) fn contains(&self, version: &Version) -> bool {
+        self.0.iter().any(|req| req.matches(version))
+    }
+}
+
+// Parses one of the alternatives of a SemVerRange. Cargo separates comparators with commas, and
+// npm with spaces, in which case an operator may also be separated from its version by a space.
+fn parse_version_req(s: &str) -> Option<VersionReq> {
+    let s = s.trim();
+    if let Some((lower, upper)) = s.split_once(" - ") {
+        return VersionReq::parse(&format!(">={}, <={}", lower.trim(), upper.trim())).ok();
+    }
+
+    let is_operator = |c: char| "<>=~^".contains(c);
+    let mut comparators: Vec<String> = Vec::new();
+    let mut pending_op = String::new();
+    for token in s.split(|c: char| c == ',' || c.is_whitespace()) {
+        if token.is_empty() {
+            continue;
+        }
+        if token.chars().all(is_operator) {
+            pending_op.push_str(token);
+            continue;
+        }
+        if pending_op.is_empty() && !token.starts_with(is_operator) && !is_wildcard(token) {
+            // The semver crate's `=` has npm's meaning for a bare version, where `=1.2` is any
+            // 1.2.x version.
+            pending_op.push('=');
+        }
+        comparators.push(format!("{}{}", pending_op, token));
+        pending_op.clear();
+    }
+    if !pending_op.is_empty() {
+        return None;
+    }
+    VersionReq::parse(&comparators.join(", ")).ok()
+}
+
+// Returns true for a version such as `*` or `1.x`, which has the same meaning in npm and Cargo.
+fn is_wildcard(version: &str) -> bool {
+    version
+        .split('.')
+        .any(|part| part == "*" || part.eq_ignore_ascii_case("x"))
+}
+
+/// Parses a non-negative duration written as a number followed by a unit, such as `30d`, or
+/// several of them, such as `1h30m`. The units are `w`, `d`, `h`, `m`, `s` and `ms`.
+pub(crate) fn parse_duration(s: &str) -> Option<Duration> {
+    let mut rest = s.trim();
+    if rest.is_empty() {
+        return None;
+    }
+
+    let mut millis: i64 = 0;
+    while !rest.is_empty() {
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let amount: i64 = rest[..digits].parse().ok()?;
+        rest = &rest[digits..];
+        let units = rest
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let unit_millis = match &rest[..units] {
+            "w" => 7 * 24 * 60 * 60 * 1000,
+            "d" => 24 * 60 * 60 * 1000,
+            "h" => 60 * 60 * 1000,
+            "m" => 60 * 1000,
+            "s" => 1000,
+            "ms" => 1,
+            _ => return None,
+        };
+        rest = &rest[units..];
+        millis = amount
+            .checked_mul(unit_millis)
+            .and_then(|amount| millis.checked_add(amount))?;
+    }
+
+    Some(Duration::milliseconds(millis))
+}
+
+/// TimeOfDayRange is a range of times within a day, written as `HH:MM-HH:MM`, with optional
+/// seconds. The start is included and the end is not. A range whose end is earlier than its start
+/// wraps around midnight, so `22:00-06:00` contains both 23:00 and 05:00. A range whose start and
+/// end are equal would contain no time at all, and is rejected as malformed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct TimeOfDayRange {
+    start: NaiveTime,
+    end: NaiveTime,
+}
+
+impl TimeOfDayRange {
+    pub(crate) fn parse(s: &str) -> Option<Self> {
+        let (start, end) = s.split_once('-')?;
+        let range = Self {
+            start: parse_time_of_day(start)?,
+            end: parse_time_of_day(end)?,
+        };
+        (range.start != range.end).then(|| range)
+    }
+
+    pub(crate) fn contains(&self, time: NaiveTime) -> bool {
+        // Leap seconds are counted as part of the second before them.
+        let time = time.with_nanosecond(0).unwrap_or(time);
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            self.start <= time || time < self.end
+        }
+    }
+}
+
+fn parse_time_of_day(s: &str) -> Option<NaiveTime> {
+    let s = s.trim();
+    NaiveTime::parse_from_str(s, "%H:%M:%S")
+        .or_else(|_| NaiveTime::parse_from_str(s, "%H:%M"))
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case("10.0.0.0/8", "10.255.1.2", true)]
+    #[test_case("10.0.0.0/8", "11.0.0.1", false)]
+    #[test_case("192.168.1.7", "192.168.1.7", true)]
+    #[test_case("192.168.1.7", "192.168.1.8", false)]
+    #[test_case("0.0.0.0/0", "8.8.8.8", true)]
+    #[test_case("2001:db8::/32", "2001:db8:1::1", true)]
+    #[test_case("2001:db8::/32", "2001:db9::1", false)]
+    #[test_case("::/0", "::1", true)]
+    #[test_case("10.0.0.0/8", "::ffff:10.0.0.1", false)]
+    fn ip_network_contains(network: &str, address: &str, expected: bool) {
+        let network = IpNetwork::parse(network).unwrap();
+        assert_eq!(expected, network.contains(address.parse().unwrap()));
+    }
+
+    #[test_case("10.0.0.0/33")]
+    #[test_case("10.0.0.0/")]
+    #[test_case("10.0.0.0/+8")]
+    #[test_case("2001:db8::/129")]
+    #[test_case("not an address")]
+    fn ip_network_rejects_invalid_ranges(network: &str) {
+        assert_eq!(None, IpNetwork::parse(network));
+    }
+
+    #[test_case("^2.3", "2.3.0", true)]
+    #[test_case("^2.3", "2.9.1", true)]
+    #[test_case("^2.3", "3.0.0", false)]
+    #[test_case("^2.3", "2.4.0-beta", false)]
+    #[test_case("2.3", "2.3.7", true)]
+    #[test_case("2.3", "2.5.0", false)]
+    #[test_case("2", "2.9.0", true)]
+    #[test_case("2", "3.0.0", false)]
+    #[test_case("1.2.3", "1.2.3", true)]
+    #[test_case("1.2.3", "1.2.4", false)]
+    #[test_case(">=1.0.0, 1.2", "1.3.0", false)]
+    #[test_case("1.x", "1.9.0", true)]
+    #[test_case("1.2.*", "1.3.0", false ; "wildcard patch excludes next minor")]
+    #[test_case("~1.2", "1.2.9", true)]
+    #[test_case("~1.2", "1.3.0", false)]
+    #[test_case(">=1.2.0 <2.0.0", "1.9.9", true)]
+    #[test_case(">=1.2.0 <2.0.0", "2.0.0", false)]
+    #[test_case(">= 1.2.0 < 2.0.0", "1.1.0", false)]
+    #[test_case(">=1.2.0, <2.0.0", "1.5.0", true)]
+    #[test_case("1.2.3 - 1.4", "1.4.7", true)]
+    #[test_case("1.2.3 - 1.4", "1.5.0", false)]
+    #[test_case("1.2.3 - 1.4", "1.2.2", false)]
+    #[test_case("~1.2 || ^3", "3.1.0", true)]
+    #[test_case("~1.2 || ^3", "2.0.0", false)]
+    #[test_case(">=2.4.0-beta.1", "2.4.0-beta.2", true)]
+    #[test_case("*", "0.0.1", true)]
+    fn semver_range_contains(range: &str, version: &str, expected: bool) {
+        let range = SemVerRange::parse(range).unwrap();
+        assert_eq!(expected, range.contains(&Version::parse(version).unwrap()));
+    }
+
+    #[test_case("" ; "empty")]
+    #[test_case("^2.3 ||")]
+    #[test_case(">=" ; "operator without version")]
+    #[test_case("not a range")]
+    #[test_case("1.2.3 - ")]
+    fn semver_range_rejects_invalid_ranges(range: &str) {
+        assert_eq!(None, SemVerRange::parse(range));
+    }
+
+    #[test_case("30d", 30 * 24 * 60 * 60 * 1000)]
+    #[test_case("2w", 14 * 24 * 60 * 60 * 1000)]
+    #[test_case("1h30m", 90 * 60 * 1000)]
+    #[test_case(" 45s ", 45 * 1000)]
+    #[test_case("1500ms", 1500)]
+    #[test_case("1m1ms", 60 * 1000 + 1)]
+    #[test_case("0s", 0)]
+    fn parses_durations(s: &str, millis: i64) {
+        assert_eq!(Some(Duration::milliseconds(millis)), parse_duration(s));
+    }
+
+    #[test_case("" ; "empty")]
+    #[test_case("30")]
+    #[test_case("d")]
+    #[test_case("-1d")]
+    #[test_case("1.5h")]
+    #[test_case("3 days")]
+    #[test_case("99999999999999999w")]
+    fn rejects_invalid_durations(s: &str) {
+        assert_eq!(None, parse_duration(s));
+    }
+
+    #[test_case("09:00-17:00", "09:00:00", true)]
+    #[test_case("09:00-17:00", "16:59:59", true)]
+    #[test_case("09:00-17:00", "17:00:00", false)]
+    #[test_case("09:00-17:00", "08:59:59", false)]
+    #[test_case("09:30:15 - 09:30:20", "09:30:15", true)]
+    #[test_case("22:00-06:00", "23:00:00", true)]
+    #[test_case("22:00-06:00", "05:59:59", true)]
+    #[test_case("22:00-06:00", "12:00:00", false)]
+    fn time_of_day_range_contains(range: &str, time: &str, expected: bool) {
+        let range = TimeOfDayRange::parse(range).unwrap();
+        let time = NaiveTime::parse_from_str(time, "%H:%M:%S").unwrap();
+        assert_eq!(expected, range.contains(time));
+    }
+
+    #[test_case("09:00")]
+    #[test_case("9am-5pm")]
+    #[test_case("09:00-25:00")]
+    #[test_case("09:00-09:00")]
+    #[test_case("09:00-09:00:00")]
+    fn time_of_day_range_rejects_invalid_ranges(range: &str) {
+        assert_eq!(None, TimeOfDayRange::parse(range));
+    }
+
+    #[test]
+    fn key_set_serializes_keys_in_original_order() {
+        let keys: KeySet = serde_json::from_str(r#"["b", "a", "b"]"#).unwrap();
+        assert!(keys.contains("a"));
+        assert!(!keys.contains("c"));
+        assert_eq!(r#"["b","a","b"]"#, serde_json::to_string(&keys).unwrap());
+    }
+}
@@ -0,0 +1,236 @@
+use std::fmt;
+
+use crate::attribute_value::AttributeValue;
+use crate::rule::{Clause, Op};
+use crate::variation::{VariationIndex, VariationOrRollout};
+use crate::Reference;
+
+const TOTAL_ROLLOUT_WEIGHT: i64 = 100_000;
+
+/// Diagnostic describes a problem found in a flag or segment by [crate::Flag::validate] or
+/// [crate::Segment::validate].
+///
+/// Evaluating a flag with one of these problems may result in [crate::Error::MalformedFlag], or
+/// in a clause which can never match.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    /// The location of the problem within the flag or segment, as a path of JSON property names
+    /// and array indices, such as `rules[1].clauses[0]`.
+    pub location: String,
+    /// The problem found at that location.
+    pub problem: Problem,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.location, self.problem)
+    }
+}
+
+/// Problem is a kind of problem described by a [Diagnostic].
+#[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
+pub enum Problem {
+    /// A variation index which does not refer to one of the flag's variations.
+    VariationOutOfRange {
+        /// The variation index.
+        variation: VariationIndex,
+        /// The number of variations it should refer to.
+        count: usize,
+    },
+    /// A rollout whose weights do not add up to 100000.
+    ///
+    /// Such a rollout can still be evaluated: any remainder is given to the last variation, so
+    /// [crate::FlagBuilder] does not reject it.
+    RolloutWeights {
+        /// The sum of the rollout's weights, each rounded to a whole number.
+        total: i64,
+    },
+    /// A segment rule whose weight is not between 0 and 100000.
+    WeightOutOfRange {
+        /// The rule's weight.
+        weight: f32,
+    },
+    /// A rule or fallthrough with neither a variation nor a rollout.
+    MalformedVariationOrRollout,
+    /// An attribute reference which is not valid.
+    InvalidReference {
+        /// The reference as it was given.
+        reference: String,
+        /// Why the reference is not valid.
+        error: String,
+    },
+    /// A clause value which cannot be interpreted as the clause's operator requires, such as an
+    /// invalid regular expression, semantic version, version range, date or network. The value
+    /// can never match.
+    InvalidClauseValue {
+        /// The clause's operator.
+        op: Op,
+        /// The value which cannot be interpreted.
+        value: AttributeValue,
+    },
+    /// A clause whose operator is not recognized, so the clause can never match unless the
+    /// operator is supplied by an [crate::OperatorRegistry].
+    UnknownOperator {
+        /// The name of the operator.
+        name: String,
+    },
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::VariationOutOfRange { variation, count } => write!(
+                f,
+                "variation index {} is out of range for {} variations",
+                variation, count
+            ),
+            Problem::RolloutWeights { total } => {
+                write!(f, "rollout weights add up to {} rather than 100000", total)
+            }
+            Problem::WeightOutOfRange { weight } => {
+                write!(f, "weight {} is outside of 0 to 100000", weight)
+            }
+            Problem::MalformedVariationOrRollout => {
+                write!(f, "neither a variation nor a rollout was specified")
+            }
+            Problem::InvalidReference { reference, error } => {
+                write!(f, "invalid attribute reference {:?}: {}", reference, error)
+            }
+            Problem::InvalidClauseValue { op, value } => {
+                write!(f, "value {:?} cannot be used with operator {:?}", value, op)
+            }
+            Problem::UnknownOperator { name } => write!(f, "unknown operator {}", name),
+        }
+    }
+}
+
+// Turns the diagnostics for a flag or segment being built by FlagBuilder or SegmentBuilder into
+// the builder's error. Unknown operators are allowed, since ClauseBuilder::custom creates them for
+// use with an OperatorRegistry, and so are rollout weights which do not add up, since evaluation
+// gives the remainder to the last variation.
+pub(crate) fn check_built(
+    kind: &str,
+    key: &str,
+    diagnostics: impl IntoIterator<Item = Diagnostic>,
+) -> Result<(), String> {
+    let problems: Vec<String> = diagnostics
+        .into_iter()
+        .filter(|d| {
+            !matches!(
+                d.problem,
+                Problem::UnknownOperator { .. } | Problem::RolloutWeights { .. }
+            )
+        })
+        .map(|d| d.to_string())
+        .collect();
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("{} {}: {}", kind, key, problems.join("; ")))
+    }
+}
+
+// Collects the diagnostics for a single flag or segment.
+#[derive(Default)]
+pub(crate) struct Diagnostics(Vec<Diagnostic>);
+
+impl Diagnostics {
+    pub(crate) fn push(&mut self, location: impl Into<String>, problem: Problem) {
+        self.0.push(Diagnostic {
+            location: location.into(),
+            problem,
+        });
+    }
+
+    pub(crate) fn check_variation(
+        &mut self,
+        location: impl Into<String>,
+        variation: VariationIndex,
+        count: usize,
+    ) {
+        if !matches!(usize::try_from(variation), Ok(i) if i < count) {
+            self.push(location, Problem::VariationOutOfRange { variation, count });
+        }
+    }
+
+    pub(crate) fn check_variation_or_rollout(
+        &mut self,
+        location: &str,
+        variation_or_rollout: &VariationOrRollout,
+        count: usize,
+    ) {
+        match variation_or_rollout {
+            VariationOrRollout::Variation { variation } => {
+                self.check_variation(format!("{}.variation", location), *variation, count)
+            }
+            VariationOrRollout::Rollout { rollout } => {
+                let mut total = 0;
+                for (index, weighted) in rollout.variations().iter().enumerate() {
+                    self.check_variation(
+                        format!("{}.rollout.variations[{}]", location, index),
+                        weighted.variation,
+                        count,
+                    );
+                    total += weighted.weight.round() as i64;
+                }
+                if total != TOTAL_ROLLOUT_WEIGHT {
+                    self.push(
+                        format!("{}.rollout", location),
+                        Problem::RolloutWeights { total },
+                    );
+                }
+                if let Some(bucket_by) = rollout.bucket_by() {
+                    self.check_reference(format!("{}.rollout.bucketBy", location), bucket_by);
+                }
+            }
+            VariationOrRollout::Malformed(_) => {
+                self.push(location, Problem::MalformedVariationOrRollout)
+            }
+        }
+    }
+
+    pub(crate) fn check_reference(&mut self, location: impl Into<String>, reference: &Reference) {
+        if !reference.is_valid() {
+            self.push(
+                location,
+                Problem::InvalidReference {
+                    reference: reference.to_string(),
+                    error: reference.error(),
+                },
+            );
+        }
+    }
+
+    pub(crate) fn check_clauses(&mut self, location: &str, clauses: &[Clause]) {
+        for (index, clause) in clauses.iter().enumerate() {
+            let location = format!("{}.clauses[{}]", location, index);
+            match clause.op() {
+                Op::Unknown => {
+                    self.push(
+                        location,
+                        Problem::UnknownOperator {
+                            name: clause.op_name().to_string(),
+                        },
+                    );
+                    continue;
+                }
+                Op::SegmentMatch => (),
+                _ => self.check_reference(format!("{}.attribute", location), clause.attribute()),
+            }
+            for value in clause.invalid_values() {
+                self.push(
+                    location.clone(),
+                    Problem::InvalidClauseValue {
+                        op: clause.op(),
+                        value: value.clone(),
+                    },
+                );
+            }
+        }
+    }
+
+    pub(crate) fn into_vec(self) -> Vec<Diagnostic> {
+        self.0
+    }
+}
@@ -1,10 +1,12 @@
 use serde::{Deserialize, Serialize};
 
 use crate::contexts::attribute_reference::AttributeName;
+use crate::eval::EvaluationError;
+use crate::trace::TraceEvent;
 use crate::util::is_false;
 use crate::{
     contexts::context::{BucketPrefix, Kind},
-    Context, Reference,
+    Context, EvaluationStack, Reference,
 };
 use serde_with::skip_serializing_none;
 
@@ -165,6 +167,34 @@ pub(crate) mod proptest_generators {
 }
 
 impl Rollout {
+    /// Whether this is a simple percentage rollout or an experiment.
+    pub fn kind(&self) -> RolloutKind {
+        self.kind.clone().unwrap_or_default()
+    }
+
+    /// The kind of context which is bucketed by this rollout. If this is None, the context kind is
+    /// assumed to be user.
+    pub fn context_kind(&self) -> Option<&Kind> {
+        self.context_kind.as_ref()
+    }
+
+    /// The attribute which contexts are bucketed by. If this is None, contexts are bucketed by
+    /// their key.
+    pub fn bucket_by(&self) -> Option<&Reference> {
+        self.bucket_by.as_ref()
+    }
+
+    /// The variations included in the rollout, and their weights.
+    pub fn variations(&self) -> &[WeightedVariation] {
+        &self.variations
+    }
+
+    /// The seed used when computing buckets, if one was specified. If not, buckets are computed
+    /// from the flag key and salt.
+    pub fn seed(&self) -> Option<i64> {
+        self.seed
+    }
+
     #[cfg(test)]
     fn with_variations<V: Into<Vec<WeightedVariation>>>(variations: V) -> Self {
         Rollout {
@@ -177,7 +207,7 @@ impl Rollout {
     }
 
     #[cfg(test)]
-    fn bucket_by(self, bucket_by: &str) -> Self {
+    fn with_bucket_by(self, bucket_by: &str) -> Self {
         Rollout {
             bucket_by: Some(Reference::new(bucket_by)),
             ..self
@@ -253,7 +283,8 @@ impl VariationOrRollout {
         flag_key: &str,
         context: &Context,
         salt: &str,
-    ) -> Result<Option<BucketResult>, String> {
+        evaluation_stack: &mut EvaluationStack,
+    ) -> Result<Option<BucketResult>, EvaluationError> {
         match self {
             VariationOrRollout::Variation { variation: var } => Ok(Some(var.into())),
             VariationOrRollout::Rollout {
@@ -274,12 +305,16 @@ impl VariationOrRollout {
                     None => BucketPrefix::KeyAndSalt(flag_key, salt),
                 };
 
-                let (bucket, was_missing_context) = context.bucket(
-                    bucket_by,
-                    prefix,
-                    is_experiment,
-                    context_kind.as_ref().unwrap_or(&Kind::default()),
-                )?;
+                let default_kind = Kind::default();
+                let context_kind = context_kind.as_ref().unwrap_or(&default_kind);
+                let (bucket, was_missing_context) =
+                    context.bucket(bucket_by, prefix, is_experiment, context_kind)?;
+
+                evaluation_stack.trace_record(|| TraceEvent::Bucket {
+                    context_kind: context_kind.clone(),
+                    bucket_by: bucket_by.clone(),
+                    bucket,
+                });
 
                 let mut sum = 0.0;
                 for variation in variations {
@@ -290,9 +325,9 @@ impl VariationOrRollout {
                         ));
                     }
                 }
-                return Ok(variations
+                Ok(variations
                     .last()
-                    .map(|var| var.as_bucket_result(is_experiment && !was_missing_context)));
+                    .map(|var| var.as_bucket_result(is_experiment && !was_missing_context)))
             }
             VariationOrRollout::Malformed(_) => Ok(None),
         }
@@ -333,6 +368,7 @@ mod consistency_tests {
                         HASH_KEY,
                         &ContextBuilder::new("userKeyA").build().unwrap(),
                         SALT,
+                        &mut EvaluationStack::default(),
                     )
                     .unwrap(),
             )
@@ -347,6 +383,7 @@ mod consistency_tests {
                         HASH_KEY,
                         &ContextBuilder::new("userKeyB").build().unwrap(),
                         SALT,
+                        &mut EvaluationStack::default(),
                     )
                     .unwrap(),
             )
@@ -361,6 +398,7 @@ mod consistency_tests {
                         HASH_KEY,
                         &ContextBuilder::new("userKeyC").build().unwrap(),
                         SALT,
+                        &mut EvaluationStack::default(),
                     )
                     .unwrap(),
             )
@@ -388,7 +426,7 @@ mod consistency_tests {
         let wv1 = WeightedVariation::new(1, 20_000.0);
         let wv2 = WeightedVariation::new(2, 70_000.0);
 
-        let mut rollout = Rollout::with_variations(vec![wv0, wv1, wv2]).bucket_by("intAttr");
+        let mut rollout = Rollout::with_variations(vec![wv0, wv1, wv2]).with_bucket_by("intAttr");
         rollout.kind = Some(RolloutKind::Experiment);
         rollout.seed = seed;
         let rollout = VariationOrRollout::Rollout { rollout };
@@ -401,6 +439,7 @@ mod consistency_tests {
                     .build()
                     .unwrap(),
                 SALT,
+                &mut EvaluationStack::default(),
             )
             .unwrap()
             .unwrap();
@@ -416,7 +455,7 @@ mod consistency_tests {
         let wv0 = WeightedVariation::new(0, 60_000.0);
         let wv1 = WeightedVariation::new(1, 40_000.0);
         let rollout = VariationOrRollout::Rollout {
-            rollout: Rollout::with_variations(vec![wv0, wv1]).bucket_by("intAttr"),
+            rollout: Rollout::with_variations(vec![wv0, wv1]).with_bucket_by("intAttr"),
         };
 
         asserting!("userKeyD (bucket 0.54771423) should get variation 0")
@@ -429,6 +468,7 @@ mod consistency_tests {
                             .build()
                             .unwrap(),
                         SALT,
+                        &mut EvaluationStack::default(),
                     )
                     .unwrap(),
             )
@@ -447,6 +487,7 @@ mod consistency_tests {
                             .build()
                             .unwrap(),
                         SALT,
+                        &mut EvaluationStack::default(),
                     )
                     .unwrap(),
             )
@@ -491,6 +532,7 @@ mod consistency_tests {
                         HASH_KEY,
                         &ContextBuilder::new("userKeyA").build().unwrap(),
                         SALT,
+                        &mut EvaluationStack::default(),
                     )
                     .unwrap(),
             )
@@ -505,6 +547,7 @@ mod consistency_tests {
                         HASH_KEY,
                         &ContextBuilder::new("userKeyB").build().unwrap(),
                         SALT,
+                        &mut EvaluationStack::default(),
                     )
                     .unwrap(),
             )
@@ -521,6 +564,7 @@ mod consistency_tests {
                     HASH_KEY,
                     &ContextBuilder::new("userKeyC").build().unwrap(),
                     SALT,
+                    &mut EvaluationStack::default(),
                 )
                 .unwrap(),
         )
@@ -709,7 +753,11 @@ mod consistency_tests {
             .build()
             .unwrap();
         asserting!("userKeyD should get variation 1 and not be in the experiment")
-            .that(&rollout.variation(HASH_KEY, &context, SALT).unwrap())
+            .that(
+                &rollout
+                    .variation(HASH_KEY, &context, SALT, &mut EvaluationStack::default())
+                    .unwrap(),
+            )
             .contains_value(BucketResult {
                 variation_index: 1,
                 in_experiment: false,
@@ -735,7 +783,11 @@ mod consistency_tests {
             .build()
             .unwrap();
         asserting!("userKeyD should get variation 1 and be in the experiment")
-            .that(&rollout.variation(HASH_KEY, &context, SALT).unwrap())
+            .that(
+                &rollout
+                    .variation(HASH_KEY, &context, SALT, &mut EvaluationStack::default())
+                    .unwrap(),
+            )
             .contains_value(BucketResult {
                 variation_index: 1,
                 in_experiment: true,
@@ -868,6 +920,7 @@ mod tests {
                         HASH_KEY,
                         &ContextBuilder::new("userKeyD").build().unwrap(),
                         SALT,
+                        &mut EvaluationStack::default(),
                     )
                     .unwrap(),
             )